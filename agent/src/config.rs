@@ -0,0 +1,62 @@
+use crate::Error;
+use serde::Deserialize;
+use std::{fs, path::PathBuf};
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    pub server: String,
+    pub username: String,
+    pub password: String,
+    pub hostnames: Vec<String>,
+    #[serde(default = "default_state_file")]
+    pub state_file: PathBuf,
+    // Seconds between address checks.
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    // Seconds after which the address is reported again even if it has not
+    // changed.
+    #[serde(default = "default_max_age")]
+    pub max_age: u64,
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    pub ipv4: Option<Source>,
+    pub ipv6: Option<Source>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "source", rename_all = "lowercase")]
+pub enum Source {
+    Interface { name: Option<String> },
+    Url { url: String },
+    Command { command: String },
+}
+
+fn default_state_file() -> PathBuf {
+    PathBuf::from("ddns-agent.state")
+}
+
+fn default_interval() -> u64 {
+    300
+}
+
+fn default_max_age() -> u64 {
+    86400
+}
+
+fn default_user_agent() -> String {
+    format!("aws-ddns-agent/{}", env!("CARGO_PKG_VERSION"))
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let raw = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let config: Config = toml::from_str(&raw).map_err(|e| format!("{}: {}", path, e))?;
+        if config.hostnames.is_empty() {
+            return Err(format!("{}: hostnames must not be empty", path).into());
+        }
+        if config.ipv4.is_none() && config.ipv6.is_none() {
+            return Err(format!("{}: at least one of ipv4 or ipv6 must be set", path).into());
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{config::Source, Error};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+use tokio::process::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    fn matches(&self, ip: &IpAddr) -> bool {
+        match self {
+            Family::V4 => ip.is_ipv4(),
+            Family::V6 => ip.is_ipv6(),
+        }
+    }
+}
+
+impl std::fmt::Display for Family {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Family::V4 => write!(f, "IPv4"),
+            Family::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+pub async fn detect(
+    source: &Source,
+    family: Family,
+    http: &reqwest::Client,
+) -> Result<IpAddr, Error> {
+    let ip = match source {
+        Source::Interface { name } => from_interface(name.as_deref(), family)?,
+        Source::Url { url } => {
+            let body = http
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            parse(&body, url)?
+        }
+        Source::Command { command } => {
+            let output = Command::new("sh").arg("-c").arg(command).output().await?;
+            if !output.status.success() {
+                return Err(format!("{} exited with {}", command, output.status).into());
+            }
+            parse(&String::from_utf8_lossy(&output.stdout), command)?
+        }
+    };
+    if !family.matches(&ip) {
+        return Err(format!("{} is not an {} address", ip, family).into());
+    }
+    Ok(ip)
+}
+
+fn parse(raw: &str, from: &str) -> Result<IpAddr, Error> {
+    let raw = raw.trim();
+    IpAddr::from_str(raw)
+        .map_err(|_| format!("{} returned {}, not an IP address", from, raw).into())
+}
+
+fn from_interface(name: Option<&str>, family: Family) -> Result<IpAddr, Error> {
+    get_if_addrs::get_if_addrs()?
+        .into_iter()
+        .filter(|i| match name {
+            Some(n) => i.name == n,
+            None => true,
+        })
+        .map(|i| i.ip())
+        .find(|ip| family.matches(ip) && is_public(ip))
+        .ok_or_else(|| {
+            let on = name.map(|n| format!(" on {}", n)).unwrap_or_default();
+            format!("no public {} address found{}", family, on).into()
+        })
+}
+
+// Addresses the rest of the internet cannot reach are skipped, so a router
+// reports its WAN address rather than a LAN or link-local one.
+fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_public_v4(v4),
+        IpAddr::V6(v6) => is_public_v6(v6),
+    }
+}
+
+fn is_public_v4(ip: &Ipv4Addr) -> bool {
+    let o = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || (o[0] == 100 && (o[1] & 0xc0) == 64))
+}
+
+fn is_public_v6(ip: &Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || (first & 0xffc0) == 0xfe80
+        || (first & 0xfe00) == 0xfc00)
+}
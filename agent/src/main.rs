@@ -0,0 +1,263 @@
+mod config;
+mod detect;
+mod state;
+
+use config::Config;
+use detect::{detect, Family};
+use reqwest::{header::USER_AGENT, StatusCode};
+use state::State;
+use std::{
+    env,
+    net::IpAddr,
+    process,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+// How long to wait after `911` or `dnserr` before calling the server again.
+const RETRY_BACKOFF: u64 = 30 * 60;
+
+const USAGE: &str = "usage: agent <config.toml> [--once] [--reset]";
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+async fn run() -> Result<(), Error> {
+    let mut path = None;
+    let mut once = false;
+    let mut reset = false;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--once" => once = true,
+            "--reset" => reset = true,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    let config = Config::load(&path.ok_or(USAGE)?)?;
+    let http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()?;
+
+    if reset {
+        let mut state = State::load(&config.state_file)?;
+        state.blocked = None;
+        state.retry_after = 0;
+        state.save(&config.state_file)?;
+    }
+
+    loop {
+        match check(&config, &http).await {
+            Ok(()) => {}
+            Err(e) if once => return Err(e),
+            Err(e) => eprintln!("{}", e),
+        }
+        if once {
+            return Ok(());
+        }
+        if let Some(reply) = State::load(&config.state_file)?.blocked {
+            return Err(blocked_message(&reply).into());
+        }
+        tokio::time::sleep(Duration::from_secs(config.interval)).await;
+    }
+}
+
+async fn check(config: &Config, http: &reqwest::Client) -> Result<(), Error> {
+    let mut state = State::load(&config.state_file)?;
+    if let Some(reply) = &state.blocked {
+        return Err(blocked_message(reply).into());
+    }
+    let now = now();
+    if now < state.retry_after {
+        return Ok(());
+    }
+
+    let mut ips = Vec::new();
+    if let Some(source) = &config.ipv4 {
+        ips.push(detect(source, Family::V4, http).await?);
+    }
+    if let Some(source) = &config.ipv6 {
+        ips.push(detect(source, Family::V6, http).await?);
+    }
+    if ips == state.ips && now.saturating_sub(state.updated_at) < config.max_age {
+        return Ok(());
+    }
+
+    match send(config, http, &ips).await? {
+        Outcome::Success => {
+            println!(
+                "updated {} to {}",
+                config.hostnames.join(","),
+                join_ips(&ips)
+            );
+            state.ips = ips;
+            state.updated_at = now;
+            state.retry_after = 0;
+        }
+        Outcome::Retry(reply) => {
+            eprintln!(
+                "server replied {}, waiting {} seconds",
+                reply, RETRY_BACKOFF
+            );
+            state.retry_after = now + RETRY_BACKOFF;
+        }
+        Outcome::Fatal(reply) => {
+            eprintln!("{}", blocked_message(&reply));
+            state.blocked = Some(reply);
+        }
+    }
+    state.save(&config.state_file)
+}
+
+fn blocked_message(reply: &str) -> String {
+    format!(
+        "updates are stopped after the server replied {}; fix the configuration and run with --reset",
+        reply
+    )
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Success,
+    Retry(String),
+    Fatal(String),
+}
+
+async fn send(config: &Config, http: &reqwest::Client, ips: &[IpAddr]) -> Result<Outcome, Error> {
+    let v4: Vec<IpAddr> = ips.iter().filter(|ip| ip.is_ipv4()).cloned().collect();
+    let v6: Vec<IpAddr> = ips.iter().filter(|ip| ip.is_ipv6()).cloned().collect();
+    let mut query = vec![("hostname", config.hostnames.join(","))];
+    if !v4.is_empty() {
+        query.push(("myip", join_ips(&v4)));
+    }
+    if !v6.is_empty() {
+        query.push(("myipv6", join_ips(&v6)));
+    }
+    let resp = http
+        .get(format!(
+            "{}/nic/update",
+            config.server.trim_end_matches('/')
+        ))
+        .query(&query)
+        .basic_auth(&config.username, Some(&config.password))
+        .header(USER_AGENT, &config.user_agent)
+        .send()
+        .await?;
+    let status = resp.status();
+    let body = resp.text().await?;
+    Ok(classify(status, &body))
+}
+
+// Follows the dyndns client rules: `911` and `dnserr` mean try again later,
+// anything other than `good` or `nochg` needs someone to fix the setup before
+// the client may call again. Servers in JSON mode answer `OK` or an HTTP error.
+fn classify(status: StatusCode, body: &str) -> Outcome {
+    if !status.is_success() {
+        return match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::BAD_REQUEST => {
+                Outcome::Fatal(format!("{} {}", status, body.trim()))
+            }
+            _ => Outcome::Retry(format!("{}", status)),
+        };
+    }
+    if body.trim() == "OK" {
+        return Outcome::Success;
+    }
+    let mut outcome = Outcome::Success;
+    for line in body.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        let code = line.split_whitespace().next().unwrap_or_default();
+        match code {
+            "good" | "nochg" => {}
+            "911" | "dnserr" => {
+                if outcome == Outcome::Success {
+                    outcome = Outcome::Retry(code.into());
+                }
+            }
+            _ => return Outcome::Fatal(code.into()),
+        }
+    }
+    outcome
+}
+
+fn join_ips(ips: &[IpAddr]) -> String {
+    ips.iter()
+        .map(|ip| format!("{}", ip))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dyn_body(body: &str) -> Outcome {
+        classify(StatusCode::OK, body)
+    }
+
+    #[test]
+    fn good_and_nochg_are_success() {
+        assert_eq!(dyn_body("good 93.184.216.34"), Outcome::Success);
+        assert_eq!(dyn_body("nochg 93.184.216.34"), Outcome::Success);
+        assert_eq!(
+            dyn_body("good 93.184.216.34\nnochg 93.184.216.34\n"),
+            Outcome::Success
+        );
+    }
+
+    #[test]
+    fn server_and_dns_errors_are_retried() {
+        assert_eq!(dyn_body("911"), Outcome::Retry("911".into()));
+        assert_eq!(dyn_body("dnserr"), Outcome::Retry("dnserr".into()));
+        assert_eq!(
+            dyn_body("good 93.184.216.34\ndnserr"),
+            Outcome::Retry("dnserr".into())
+        );
+    }
+
+    #[test]
+    fn other_codes_stop_the_agent() {
+        assert_eq!(dyn_body("badauth"), Outcome::Fatal("badauth".into()));
+        assert_eq!(dyn_body("abuse"), Outcome::Fatal("abuse".into()));
+        assert_eq!(dyn_body("nohost"), Outcome::Fatal("nohost".into()));
+        // A fatal line wins over one that could be retried.
+        assert_eq!(dyn_body("911\nnohost"), Outcome::Fatal("nohost".into()));
+    }
+
+    #[test]
+    fn json_mode_uses_the_status() {
+        assert_eq!(classify(StatusCode::OK, "OK"), Outcome::Success);
+        for status in &[
+            StatusCode::UNAUTHORIZED,
+            StatusCode::FORBIDDEN,
+            StatusCode::BAD_REQUEST,
+        ] {
+            match classify(*status, "{}") {
+                Outcome::Fatal(_) => {}
+                other => panic!("{} gave {:?}", status, other),
+            }
+        }
+        for status in &[
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+        ] {
+            assert_eq!(
+                classify(*status, "{}"),
+                Outcome::Retry(format!("{}", status))
+            );
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::{fs, io::ErrorKind, net::IpAddr, path::Path};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    // Addresses the server last accepted.
+    pub ips: Vec<IpAddr>,
+    // Epoch seconds of the last accepted update.
+    pub updated_at: u64,
+    // Epoch seconds before which the server must not be called again.
+    pub retry_after: u64,
+    // The reply that stopped updates until someone fixes the configuration.
+    pub blocked: Option<String>,
+}
+
+impl State {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(raw) => {
+                serde_json::from_str(&raw).map_err(|e| format!("{}: {}", path.display(), e).into())
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e).into()),
+        }
+    }
+
+    // Writes to a temporary file first so a crash never leaves a truncated
+    // state file behind.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)
+            .map_err(|e| format!("{}: {}", tmp.display(), e))?;
+        fs::rename(&tmp, path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(())
+    }
+}
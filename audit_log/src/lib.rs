@@ -0,0 +1,100 @@
+use ddns_core::{
+    audit::{AuditEvent, AuditQuery},
+    client::Client,
+    error::{ResponseError, ResponseErrors},
+};
+use http::StatusCode;
+use lambda_http::{Body, IntoResponse, Request, RequestExt, Response};
+use serde::Serialize;
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 100;
+
+// Serves `GET /audit`, filtered by `username`, `hostname`, `from` and `to`.
+pub async fn audit_log(client: &Client, request: Request) -> Response<Body> {
+    let query = match parse_query(&request).map_err(ResponseError::from) {
+        Ok(q) => q,
+        Err(e) => return e.into_response(),
+    };
+    // Only events a filter left out may be missing from between two others.
+    let filtered = query.hostname.is_some() || query.from.is_some() || query.to.is_some();
+    match client.query_audit(&query).await {
+        Ok(page) => {
+            let intact = client.audit_page_intact(&page.events, filtered);
+            let body = AuditList {
+                events: page
+                    .events
+                    .iter()
+                    .zip(intact)
+                    .map(|(event, intact)| EventInfo { event, intact })
+                    .collect(),
+                cursor: page.cursor,
+            };
+            Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(Body::from(
+                    serde_json::to_string(&body).expect("unable to turn body into json"),
+                ))
+                .expect("unable to create response")
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct AuditList<'a> {
+    events: Vec<EventInfo<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
+}
+
+// `intact` is false when the event no longer matches its own hash, or does
+// not follow on from the event before it in the page.
+#[derive(Serialize)]
+struct EventInfo<'a> {
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+    intact: bool,
+}
+
+fn parse_query(request: &Request) -> Result<AuditQuery, ResponseErrors> {
+    let mut errs = ResponseErrors::default();
+    let queries = request.query_string_parameters();
+    let mut query = AuditQuery {
+        username: queries.get("username").map(|u| u.to_owned()),
+        hostname: queries.get("hostname").map(|h| h.to_owned()),
+        cursor: queries.get("cursor").map(|c| c.to_owned()),
+        limit: DEFAULT_PAGE_SIZE,
+        ..AuditQuery::default()
+    };
+    if let Some(raw) = queries.get("limit") {
+        match raw.parse::<usize>() {
+            Ok(limit) if (1..=MAX_PAGE_SIZE).contains(&limit) => query.limit = limit,
+            _ => errs.add(ResponseError::InvalidQuery(
+                "limit".into(),
+                format!("must be between 1 and {}", MAX_PAGE_SIZE),
+            )),
+        }
+    }
+    for (key, value) in [("from", &mut query.from), ("to", &mut query.to)] {
+        if let Some(raw) = queries.get(key) {
+            match raw.parse::<i64>() {
+                Ok(at) => *value = Some(at),
+                Err(_) => errs.add(ResponseError::InvalidQuery(
+                    key.into(),
+                    format!("{} is not a time in epoch seconds", raw),
+                )),
+            }
+        }
+    }
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            errs.add(ResponseError::InvalidQuery(
+                "to".into(),
+                "is before from".into(),
+            ));
+        }
+    }
+    errs.into_result(query)
+}
@@ -0,0 +1,28 @@
+use audit_log::audit_log;
+use ddns_core::{client::Client, error::LambdaError};
+use lambda_http::{
+    handler,
+    lambda::{self, Context},
+    IntoResponse, Request,
+};
+use std::sync::Arc;
+
+#[tokio::main]
+async fn main() -> Result<(), LambdaError> {
+    ddns_core::logging::init();
+    // Made once, so warm invocations share its clients and connections.
+    let client = Arc::new(Client::from_env()?);
+    lambda::run(handler(move |request, context| {
+        handle(client.clone(), request, context)
+    }))
+    .await?;
+    Ok(())
+}
+
+async fn handle(
+    client: Arc<Client>,
+    request: Request,
+    _: Context,
+) -> Result<impl IntoResponse, LambdaError> {
+    Ok(audit_log(&client, request).await)
+}
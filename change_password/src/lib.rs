@@ -0,0 +1,147 @@
+use ddns_core::{
+    auth::parse_authorization,
+    client::Client,
+    error::{ResponseError, ResponseErrors},
+    user::check_password,
+};
+use http::StatusCode;
+use lambda_http::{Body, IntoResponse, Request, RequestExt, Response};
+use serde::Deserialize;
+use std::net::IpAddr;
+
+pub async fn change_password(
+    client: &Client,
+    request: Request,
+    source_ip: Option<IpAddr>,
+) -> Response<Body> {
+    match parse_request(&request).map_err(ResponseError::from) {
+        Ok(req) => match client
+            .change_password(&req.username, &req.password, &req.new_password, source_ip)
+            .await
+        {
+            Ok(_) => Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::from(()))
+                .expect("unable to create response"),
+            Err(e) => e.into_response(),
+        },
+        Err(e) => e.into_response(),
+    }
+}
+
+#[derive(Default)]
+struct ChangePasswordRequest {
+    username: String,
+    password: String,
+    new_password: String,
+}
+
+#[derive(Deserialize)]
+struct NewPassword {
+    password: String,
+}
+
+fn parse_request(request: &Request) -> Result<ChangePasswordRequest, ResponseErrors> {
+    let mut errs = ResponseErrors::default();
+    let mut req = ChangePasswordRequest::default();
+
+    match request.headers().get("Authorization") {
+        Some(a) => match parse_authorization(a) {
+            Ok(creds) => {
+                req.username = creds.username;
+                req.password = creds.password;
+            }
+            Err(e) => errs.add(e),
+        },
+        None => errs.add(ResponseError::MissingHeader("Authorization".into())),
+    };
+
+    match request.payload::<NewPassword>() {
+        Ok(Some(r)) => {
+            if r.password.is_empty() {
+                errs.add(ResponseError::MissingField("password".into()));
+            } else if let Err(reason) = check_password(&r.password) {
+                errs.add(ResponseError::InvalidField("password".into(), reason));
+            } else if r.password == req.password {
+                errs.add(ResponseError::InvalidField(
+                    "password".into(),
+                    "is the same as the current password".into(),
+                ));
+            }
+            req.new_password = r.password;
+        }
+        Ok(None) => errs.add(ResponseError::MissingField("password".into())),
+        Err(e) => errs.add(ResponseError::ParseError(format!("{}", e))),
+    }
+    errs.into_result(req)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ddns_core::{
+        dns::MemoryDnsProvider,
+        lockout::MemoryAttemptStore,
+        password::{test_hasher, TEST_PARAMS},
+        store::{MemoryUserStore, PutMode, UserStore},
+        user::test_user,
+        zone::Zone,
+    };
+
+    // A client with one user, alice, whose password is `secret12`.
+    async fn client() -> Client {
+        let users = MemoryUserStore::new();
+        let mut user = test_user();
+        user.set_password("secret12", &test_hasher()).unwrap();
+        users.put_user(user, PutMode::Create).await.unwrap();
+        Client::builder()
+            .user_store(users)
+            .dns_provider(MemoryDnsProvider::new(vec![Zone::new("example.com", "Z1")]))
+            .attempt_store(MemoryAttemptStore::new())
+            .hash_params(TEST_PARAMS)
+            .build()
+            .unwrap()
+    }
+
+    fn request(password: &str, new_password: &str) -> Request {
+        let creds = base64::encode(format!("alice:{}", password));
+        http::Request::builder()
+            .method("POST")
+            .uri("/nic/password")
+            .header("Authorization", format!("Basic {}", creds))
+            .header("Content-Type", "application/json")
+            .body(Body::Text(format!(r#"{{"password": "{}"}}"#, new_password)))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn wrong_password_is_401() {
+        let client = client().await;
+        let response = change_password(&client, request("guess123", "newpass12"), None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(client.authenticate("alice", "secret12", None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn short_new_password_is_400() {
+        let client = client().await;
+        let response = change_password(&client, request("secret12", "short"), None).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(client.authenticate("alice", "secret12", None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn new_password_replaces_the_old_one() {
+        let client = client().await;
+        let response = change_password(&client, request("secret12", "newpass12"), None).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(client
+            .authenticate("alice", "newpass12", None)
+            .await
+            .is_ok());
+        assert!(matches!(
+            client.authenticate("alice", "secret12", None).await,
+            Err(ResponseError::InvalidCredentials)
+        ));
+    }
+}
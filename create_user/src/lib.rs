@@ -0,0 +1,234 @@
+use ddns_core::{
+    client::{Client, TtlLimits},
+    error::{ResponseError, ResponseErrors},
+    store::PutMode,
+    user::check_password,
+    zone::check_grant,
+};
+use http::StatusCode;
+use lambda_http::{Body, IntoResponse, Request, RequestExt, Response};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+pub async fn create_user(client: &Client, request: Request) -> Response<Body> {
+    match parse_request(request, client.ttl_limits()).map_err(ResponseError::from) {
+        Ok(req) => {
+            let domains: Vec<String> = req.domains.iter().cloned().collect();
+            if let Err(e) = client.check_hostnames(&domains).await {
+                return e.into_response();
+            }
+            match client
+                .new_user(&req.username, &req.password, req.domains.clone())
+                .await
+            {
+                Ok(mut user) => {
+                    user.set_ttl(req.ttl);
+                    for (domain, ttl) in &req.ttls {
+                        user.set_domain_ttl(domain, Some(*ttl));
+                    }
+                    user.set_user_agents(req.user_agents.clone());
+                    user.set_allow_private_addresses(req.allow_private_addresses);
+                    match client.put_user(user, PutMode::Create).await {
+                        Ok(_) => Response::builder()
+                            .status(StatusCode::CREATED)
+                            .body(Body::from(()))
+                            .expect("unable to create response"),
+                        Err(e) => e.into_response(),
+                    }
+                }
+                Err(e) => e.into_response(),
+            }
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+fn parse_request(
+    request: Request,
+    limits: &TtlLimits,
+) -> Result<CreateUserRequest, ResponseErrors> {
+    let mut req = CreateUserRequest::default();
+    let mut errs = ResponseErrors::default();
+
+    match request.payload::<CreateUserRequest>() {
+        Ok(r) => match r {
+            Some(r) => {
+                if r.username.is_empty() {
+                    errs.add(ResponseError::MissingField("username".into()));
+                } else {
+                    if r.username.len() < 7 {
+                        errs.add(ResponseError::InvalidField(
+                            "username".into(),
+                            "is less than 7 characters long".into(),
+                        ))
+                    }
+                    if r.username.contains(':') {
+                        errs.add(ResponseError::InvalidField(
+                            "username".into(),
+                            "contains a colon (:)".into(),
+                        ))
+                    }
+                }
+
+                if r.password.is_empty() {
+                    errs.add(ResponseError::MissingField("password".into()));
+                } else if let Err(reason) = check_password(&r.password) {
+                    errs.add(ResponseError::InvalidField("password".into(), reason))
+                }
+
+                if r.domains.is_empty() {
+                    errs.add(ResponseError::MissingField("domains".into()));
+                }
+                for domain in &r.domains {
+                    if let Err(reason) = check_grant(domain) {
+                        errs.add(ResponseError::InvalidField(
+                            "domains".into(),
+                            format!("{} {}", domain, reason),
+                        ));
+                    }
+                }
+
+                if let Some(ttl) = r.ttl {
+                    if let Err(reason) = limits.check(ttl) {
+                        errs.add(ResponseError::InvalidField("ttl".into(), reason));
+                    }
+                }
+
+                for (domain, ttl) in &r.ttls {
+                    if !r.domains.contains(domain) {
+                        errs.add(ResponseError::InvalidField(
+                            "ttls".into(),
+                            format!("{} is not one of the domains", domain),
+                        ));
+                    } else if let Err(reason) = limits.check(*ttl) {
+                        errs.add(ResponseError::InvalidField(
+                            "ttls".into(),
+                            format!("{} {}", domain, reason),
+                        ));
+                    }
+                }
+                if r.user_agents.iter().any(|a| a.trim().is_empty()) {
+                    errs.add(ResponseError::InvalidField(
+                        "user_agents".into(),
+                        "contains an empty entry".into(),
+                    ));
+                }
+                req = r;
+            }
+            None => {
+                errs.add(ResponseError::MissingField("username".into()));
+                errs.add(ResponseError::MissingField("password".into()));
+                errs.add(ResponseError::MissingField("domains".into()));
+            }
+        },
+        Err(e) => {
+            errs.add(ResponseError::ParseError(format!("{}", e)));
+        }
+    }
+    errs.into_result(req)
+}
+
+#[derive(Deserialize, Default)]
+struct CreateUserRequest {
+    username: String,
+    password: String,
+    domains: HashSet<String>,
+    #[serde(default)]
+    ttl: Option<i64>,
+    #[serde(default)]
+    ttls: HashMap<String, i64>,
+    #[serde(default)]
+    user_agents: HashSet<String>,
+    #[serde(default)]
+    allow_private_addresses: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ddns_core::{
+        dns::MemoryDnsProvider, lockout::MemoryAttemptStore, password::TEST_PARAMS,
+        store::MemoryUserStore, user::UserInfo, zone::Zone,
+    };
+
+    fn client() -> Client {
+        Client::builder()
+            .user_store(MemoryUserStore::new())
+            .dns_provider(MemoryDnsProvider::new(vec![Zone::new("example.com", "Z1")]))
+            .attempt_store(MemoryAttemptStore::new())
+            .hash_params(TEST_PARAMS)
+            .build()
+            .unwrap()
+    }
+
+    fn request(body: &str) -> Request {
+        http::Request::builder()
+            .method("POST")
+            .uri("/user")
+            .header("Content-Type", "application/json")
+            .body(Body::Text(body.to_owned()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn domains_outside_the_hosted_zones_are_rejected() {
+        let client = client();
+        let body = r#"{"username": "alice01", "password": "secret123", "domains": ["host.example.com", "host.example.org"]}"#;
+        let response = create_user(&client, request(body)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = String::from_utf8(response.body().as_ref().to_vec()).unwrap();
+        assert!(body.contains("hosted zone"), "{}", body);
+        assert!(body.contains("host.example.org"), "{}", body);
+        assert!(matches!(
+            client.get_user("alice01").await,
+            Err(ResponseError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn taken_usernames_are_a_conflict() {
+        let client = client();
+        let first =
+            r#"{"username": "alice01", "password": "secret123", "domains": ["host.example.com"]}"#;
+        let response = create_user(&client, request(first)).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let second =
+            r#"{"username": "alice01", "password": "other1234", "domains": ["other.example.com"]}"#;
+        let response = create_user(&client, request(second)).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let user = client
+            .authenticate("alice01", "secret123", None)
+            .await
+            .unwrap();
+        assert!(user.domains().contains("host.example.com"));
+        assert!(!user.domains().contains("other.example.com"));
+    }
+
+    #[tokio::test]
+    async fn ttls_out_of_range_are_rejected() {
+        let client = client();
+        for body in &[
+            r#"{"username": "alice01", "password": "secret123", "domains": ["host.example.com"], "ttl": 30}"#,
+            r#"{"username": "alice01", "password": "secret123", "domains": ["host.example.com"], "ttls": {"host.example.com": 86401}}"#,
+        ] {
+            let response = create_user(&client, request(body)).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", body);
+        }
+        assert!(matches!(
+            client.get_user("alice01").await,
+            Err(ResponseError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn ttls_are_stored() {
+        let client = client();
+        let body = r#"{"username": "alice01", "password": "secret123", "domains": ["host.example.com", "**.lab.example.com"], "ttl": 120, "ttls": {"**.lab.example.com": 3600}}"#;
+        let response = create_user(&client, request(body)).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let user = UserInfo::from(&client.get_user("alice01").await.unwrap());
+        assert_eq!(user.ttl, Some(120));
+        assert_eq!(user.ttls["**.lab.example.com"], 3600);
+    }
+}
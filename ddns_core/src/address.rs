@@ -0,0 +1,163 @@
+use crate::{error::ResponseError, user::User};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AddressPolicy {
+    // Only addresses that are routable on the internet may be published.
+    #[default]
+    Public,
+    Any,
+}
+
+impl FromStr for AddressPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("public") {
+            Ok(AddressPolicy::Public)
+        } else if s.eq_ignore_ascii_case("any") {
+            Ok(AddressPolicy::Any)
+        } else {
+            Err(format!("{} is not one of public or any", s))
+        }
+    }
+}
+
+impl AddressPolicy {
+    // Whether `user` may only publish public addresses. A user allowed
+    // private addresses, e.g. for split-horizon DNS, is exempt.
+    pub fn restricts(&self, user: &User) -> bool {
+        *self == AddressPolicy::Public && !user.allow_private_addresses
+    }
+}
+
+// Refuses an address in a special-purpose range as an invalid query. `source`
+// names where it came from: `myip`, `myipv6` or the request's source address.
+pub fn check_address(source: impl AsRef<str>, ip: IpAddr) -> Result<(), ResponseError> {
+    match reserved_range(ip) {
+        Some(range) => Err(ResponseError::InvalidQuery(
+            source.as_ref().to_owned(),
+            format!("{} is a {} address", ip, range),
+        )),
+        None => Ok(()),
+    }
+}
+
+// Names the special-purpose range an address is in, if any.
+pub fn reserved_range(ip: IpAddr) -> Option<&'static str> {
+    match ip {
+        IpAddr::V4(v4) => reserved_v4(v4),
+        IpAddr::V6(v6) => reserved_v6(v6),
+    }
+}
+
+fn reserved_v4(ip: Ipv4Addr) -> Option<&'static str> {
+    let o = ip.octets();
+    if o[0] == 0 {
+        Some("unspecified")
+    } else if ip.is_private() {
+        Some("private")
+    } else if o[0] == 100 && (64..128).contains(&o[1]) {
+        Some("carrier-grade NAT")
+    } else if ip.is_loopback() {
+        Some("loopback")
+    } else if ip.is_link_local() {
+        Some("link-local")
+    } else if ip.is_multicast() {
+        Some("multicast")
+    } else if ip.is_documentation() {
+        Some("documentation")
+    } else if o[0] == 198 && (o[1] == 18 || o[1] == 19) {
+        Some("benchmarking")
+    } else if o[0] == 192 && o[1] == 0 && o[2] == 0 {
+        Some("protocol assignment")
+    } else if o[0] >= 240 {
+        Some("reserved")
+    } else {
+        None
+    }
+}
+
+fn reserved_v6(ip: Ipv6Addr) -> Option<&'static str> {
+    let s = ip.segments();
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return reserved_v4(v4);
+    }
+    if ip.is_unspecified() {
+        Some("unspecified")
+    } else if ip.is_loopback() {
+        Some("loopback")
+    } else if s[0] & 0xfe00 == 0xfc00 {
+        Some("unique local")
+    } else if s[0] & 0xffc0 == 0xfe80 {
+        Some("link-local")
+    } else if ip.is_multicast() {
+        Some("multicast")
+    } else if s[0] == 0x2001 && s[1] == 0x0db8 {
+        Some("documentation")
+    } else if s[0] == 0x0100 && s[1] == 0 && s[2] == 0 && s[3] == 0 {
+        Some("discard")
+    } else if s[0] == 0x0064 && s[1] == 0xff9b && s[2..6].iter().all(|&x| x == 0) {
+        Some("NAT64")
+    } else if s[0] == 0x2002 {
+        Some("6to4")
+    } else if s[0] == 0x2001 && s[1] < 0x0200 {
+        Some("protocol assignment")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(ip: &str) -> Option<&'static str> {
+        reserved_range(IpAddr::from_str(ip).unwrap())
+    }
+
+    #[test]
+    fn public_addresses_are_allowed() {
+        assert_eq!(range("203.0.114.1"), None);
+        assert_eq!(range("8.8.8.8"), None);
+        assert_eq!(range("2600:1f18::1"), None);
+        assert_eq!(range("2001:4860::8888"), None);
+    }
+
+    #[test]
+    fn reserved_v4_ranges() {
+        assert_eq!(range("10.1.2.3"), Some("private"));
+        assert_eq!(range("100.64.0.1"), Some("carrier-grade NAT"));
+        assert_eq!(range("127.0.0.1"), Some("loopback"));
+        assert_eq!(range("203.0.113.1"), Some("documentation"));
+        assert_eq!(range("::ffff:192.168.1.1"), Some("private"));
+    }
+
+    #[test]
+    fn reserved_v6_ranges() {
+        assert_eq!(range("::1"), Some("loopback"));
+        assert_eq!(range("fd00::1"), Some("unique local"));
+        assert_eq!(range("fe80::1"), Some("link-local"));
+        assert_eq!(range("2001:db8::1"), Some("documentation"));
+        assert_eq!(range("64:ff9b::a00:1"), Some("NAT64"));
+        assert_eq!(range("2002:c000:201::1"), Some("6to4"));
+        assert_eq!(range("2001::1"), Some("protocol assignment"));
+        assert_eq!(range("2001:1ff::1"), Some("protocol assignment"));
+        assert_eq!(range("2001:200::1"), None);
+    }
+
+    #[test]
+    fn check_names_the_source() {
+        let ip = IpAddr::from_str("192.168.1.1").unwrap();
+        match check_address("myipv6", ip) {
+            Err(ResponseError::InvalidQuery(source, reason)) => {
+                assert_eq!(source, "myipv6");
+                assert_eq!(reason, "192.168.1.1 is a private address");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
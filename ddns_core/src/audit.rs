@@ -0,0 +1,314 @@
+use crate::error::ResponseError;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac, NewMac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+mod dynamodb;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use dynamodb::DynamoDbAuditSink;
+pub use memory::MemoryAuditSink;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteAuditSink;
+
+// Recorded for attempts that did not log in as a user, so callers cannot add
+// events to someone else's chain by naming them. Usernames are at least 7
+// characters long, so it cannot be mistaken for one. Its events are not
+// chained: every failed login in the deployment lands here, and making each
+// of them wait for the one before would turn an attack into dropped events.
+pub const ANONYMOUS: &str = "-";
+
+pub const DEFAULT_RETENTION_DAYS: i64 = 90;
+
+// One `/nic/update` attempt, before it is added to the log.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: i64,
+    pub username: String,
+    // The username an attempt that did not log in gave, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claimed_username: Option<String>,
+    pub hostnames: Vec<String>,
+    // `myip` as it was sent, when it was sent.
+    pub requested_ip: Option<String>,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+    // The dyndns return code each hostname got, in request order.
+    pub outcome: Vec<String>,
+    // The `ResponseError` variant the request failed with.
+    pub error: Option<String>,
+}
+
+// Every user's events form a chain: `seq` counts up from 1 and `hash` is an
+// HMAC over the entry, its `seq` and the previous event's hash. Changing or
+// removing an event breaks the chain from that point on, and without the key
+// the chain cannot be recomputed to hide it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    #[serde(flatten)]
+    pub entry: AuditEntry,
+    pub seq: i64,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEvent {
+    // Adds `entry` after `prev`, the last event in the user's chain.
+    pub fn chain(entry: AuditEntry, prev: Option<&AuditEvent>, key: &[u8]) -> Self {
+        let seq = prev.map_or(1, |prev| prev.seq + 1);
+        if entry.username == ANONYMOUS {
+            return AuditEvent::unchained(entry, seq, key);
+        }
+        let prev_hash = prev.map(|prev| prev.hash.clone()).unwrap_or_default();
+        let hash = event_hash(&entry, seq, &prev_hash, key);
+        AuditEvent {
+            entry,
+            seq,
+            prev_hash,
+            hash,
+        }
+    }
+
+    // An event that only vouches for itself. `seq` only has to be unique.
+    pub fn unchained(entry: AuditEntry, seq: i64, key: &[u8]) -> Self {
+        let hash = event_hash(&entry, seq, "", key);
+        AuditEvent {
+            entry,
+            seq,
+            prev_hash: String::new(),
+            hash,
+        }
+    }
+
+    pub fn is_intact(&self, key: &[u8]) -> bool {
+        self.hash == event_hash(&self.entry, self.seq, &self.prev_hash, key)
+    }
+
+    fn follows(&self, prev: &AuditEvent) -> bool {
+        self.entry.username == ANONYMOUS
+            || (self.seq == prev.seq + 1 && self.prev_hash == prev.hash)
+    }
+}
+
+fn event_hash(entry: &AuditEntry, seq: i64, prev_hash: &str, key: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("hmac accepts any key length");
+    mac.update(prev_hash.as_bytes());
+    mac.update(seq.to_string().as_bytes());
+    mac.update(
+        serde_json::to_string(entry)
+            .expect("unable to turn audit entry into json")
+            .as_bytes(),
+    );
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// Checks one user's events, oldest first. Events that expired before the
+// first one given are not needed. Returns the `seq` of the first event that
+// was altered or does not follow the one before it.
+pub fn verify_chain<'a>(
+    events: impl IntoIterator<Item = &'a AuditEvent>,
+    key: &[u8],
+) -> Result<(), i64> {
+    let mut prev: Option<&AuditEvent> = None;
+    for event in events {
+        if !event.is_intact(key) {
+            return Err(event.seq);
+        }
+        if let Some(prev) = prev {
+            if !event.follows(prev) {
+                return Err(event.seq);
+            }
+        }
+        prev = Some(event);
+    }
+    Ok(())
+}
+
+// Checks a page of events from any number of users, each user's in order,
+// and gives whether each event is intact. Every run of a user's events the
+// page holds is verified as a chain. A filtered page skips events, so only
+// events whose `seq` follows on are checked against the one before them.
+pub fn verify_page(events: &[AuditEvent], key: &[u8], filtered: bool) -> Vec<bool> {
+    let mut runs: Vec<Vec<&AuditEvent>> = Vec::new();
+    for event in events {
+        let follows_on = match runs.last().and_then(|run| run.last()) {
+            Some(prev) if prev.entry.username == event.entry.username => {
+                !filtered || event.seq == prev.seq + 1
+            }
+            _ => false,
+        };
+        match runs.last_mut() {
+            Some(run) if follows_on => run.push(event),
+            _ => runs.push(vec![event]),
+        }
+    }
+    let mut intact = Vec::with_capacity(events.len());
+    for run in runs {
+        let mut start = 0;
+        while start < run.len() {
+            let broken = match verify_chain(run[start..].iter().copied(), key) {
+                Ok(()) => run.len(),
+                Err(seq) => start + run[start..].iter().position(|e| e.seq == seq).unwrap_or(0),
+            };
+            intact.extend(run[start..broken].iter().map(|_| true));
+            if broken < run.len() {
+                intact.push(false);
+            }
+            start = broken + 1;
+        }
+    }
+    intact
+}
+
+// Every filter is optional. `from` and `to` are inclusive epoch seconds.
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery {
+    pub username: Option<String>,
+    pub hostname: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub limit: usize,
+    pub cursor: Option<String>,
+}
+
+impl AuditQuery {
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        let entry = &event.entry;
+        match &self.username {
+            Some(u) if *u != entry.username => return false,
+            _ => {}
+        }
+        match &self.hostname {
+            Some(h) if !entry.hostnames.iter().any(|e| e.eq_ignore_ascii_case(h)) => return false,
+            _ => {}
+        }
+        match self.from {
+            Some(from) if entry.at < from => return false,
+            _ => {}
+        }
+        match self.to {
+            Some(to) if entry.at > to => return false,
+            _ => {}
+        }
+        true
+    }
+}
+
+// `cursor` is set when more events may follow. A sink that filters after
+// reading a page may return fewer than `limit` events with a cursor.
+pub struct AuditPage {
+    pub events: Vec<AuditEvent>,
+    pub cursor: Option<String>,
+}
+
+// Cursors name the last event returned as `<username>#<seq>`.
+pub(crate) fn event_cursor(event: &AuditEvent) -> String {
+    format!("{}#{}", event.entry.username, event.seq)
+}
+
+pub(crate) fn parse_cursor(cursor: &str) -> Result<(String, i64), ResponseError> {
+    let mut parts = cursor.rsplitn(2, '#');
+    match (parts.next().map(str::parse::<i64>), parts.next()) {
+        (Some(Ok(seq)), Some(username)) => Ok((username.to_owned(), seq)),
+        _ => Err(ResponseError::InvalidQuery(
+            "cursor".into(),
+            "is not a cursor this API returned".into(),
+        )),
+    }
+}
+
+#[async_trait]
+pub trait AuditSink: Send + Sync {
+    // Adds the entry to the end of its user's chain, hashed with `key`.
+    async fn append(&self, entry: AuditEntry, key: &[u8]) -> Result<AuditEvent, ResponseError>;
+    async fn query(&self, query: &AuditQuery) -> Result<AuditPage, ResponseError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(key: &[u8]) -> Vec<AuditEvent> {
+        user_chain("someuser", key)
+    }
+
+    fn user_chain(username: &str, key: &[u8]) -> Vec<AuditEvent> {
+        let mut events: Vec<AuditEvent> = Vec::new();
+        for at in 1..=3 {
+            let entry = AuditEntry {
+                at,
+                username: username.into(),
+                ..AuditEntry::default()
+            };
+            let event = AuditEvent::chain(entry, events.last(), key);
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn chain_verifies_with_its_key() {
+        let events = chain(b"key");
+        assert_eq!(verify_chain(&events, b"key"), Ok(()));
+        assert_eq!(verify_chain(&events, b"other"), Err(1));
+    }
+
+    #[test]
+    fn rewritten_chain_needs_the_key() {
+        let mut events = chain(b"key");
+        events[1].entry.outcome = vec!["good 1.2.3.4".into()];
+        assert_eq!(verify_chain(&events, b"key"), Err(2));
+
+        // Recomputing the hashes without the key does not help.
+        let mut forged = vec![events[0].clone()];
+        for event in &events[1..] {
+            let next = AuditEvent::chain(event.entry.clone(), forged.last(), b"guess");
+            forged.push(next);
+        }
+        assert_eq!(verify_chain(&forged, b"key"), Err(2));
+    }
+
+    #[test]
+    fn anonymous_events_are_not_chained() {
+        let entry = AuditEntry {
+            username: ANONYMOUS.into(),
+            ..AuditEntry::default()
+        };
+        let first = AuditEvent::chain(entry.clone(), None, b"key");
+        let second = AuditEvent::chain(entry.clone(), Some(&first), b"key");
+        assert_eq!(second.prev_hash, "");
+        let apart = AuditEvent::unchained(entry, 1_000_000, b"key");
+        assert_eq!(verify_chain(&[first, second, apart], b"key"), Ok(()));
+    }
+
+    #[test]
+    fn page_marks_where_the_chain_breaks() {
+        let mut events = chain(b"key");
+        events.remove(1);
+        assert_eq!(verify_page(&events, b"key", false), vec![true, false]);
+        // A filter may have left the missing event out.
+        assert_eq!(verify_page(&events, b"key", true), vec![true, true]);
+
+        let mut events = chain(b"key");
+        events[1].entry.outcome = vec!["good 1.2.3.4".into()];
+        assert_eq!(verify_page(&events, b"key", true), vec![true, false, true]);
+    }
+
+    #[test]
+    fn page_checks_each_users_chain() {
+        let mut events = user_chain("otheruser", b"key");
+        events.extend(chain(b"key"));
+        assert_eq!(verify_page(&events, b"key", false), vec![true; 6]);
+
+        // One user's chain does not continue another's.
+        events.remove(3);
+        assert_eq!(verify_page(&events, b"key", false), vec![true; 5]);
+    }
+}
@@ -0,0 +1,299 @@
+use super::{parse_cursor, AuditEntry, AuditEvent, AuditPage, AuditQuery, AuditSink, ANONYMOUS};
+use crate::{
+    error::ResponseError,
+    store::{AttributeValueExt, MapAttributeValueExt},
+};
+use async_trait::async_trait;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaChaRng;
+use rusoto_core::{Region, RusotoError};
+use rusoto_dynamodb::{
+    AttributeValue, DynamoDb, DynamoDbClient, PutItemError, PutItemInput, QueryInput, ScanInput,
+};
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+};
+
+// How many times an append is retried when another request added to the same
+// user's chain first.
+const APPEND_ATTEMPTS: usize = 3;
+
+// Unchained events are numbered `at` in microseconds plus a random part, so
+// they can be written without reading the partition first.
+const UNCHAINED_SEQ_SCALE: i64 = 1_000_000;
+
+// The table is keyed by `username` and the number `seq`, with TTL enabled on
+// `expires_at`.
+pub struct DynamoDbAuditSink {
+    db: DynamoDbClient,
+    audit_table_name: String,
+    retention: i64,
+}
+
+impl DynamoDbAuditSink {
+    pub fn new(region: Region, audit_table_name: impl AsRef<str>, retention_days: i64) -> Self {
+        DynamoDbAuditSink::with_client(
+            DynamoDbClient::new(region),
+            audit_table_name,
+            retention_days,
+        )
+    }
+
+    pub fn with_client(
+        db: DynamoDbClient,
+        audit_table_name: impl AsRef<str>,
+        retention_days: i64,
+    ) -> Self {
+        DynamoDbAuditSink {
+            db,
+            audit_table_name: audit_table_name.as_ref().to_owned(),
+            retention: retention_days * 86400,
+        }
+    }
+
+    async fn last_event(&self, username: &str) -> Result<Option<AuditEvent>, ResponseError> {
+        let mut values = HashMap::new();
+        values.insert(
+            ":username".to_owned(),
+            AttributeValue::from_string(username.to_owned()),
+        );
+        let mut input = QueryInput::default();
+        input.table_name = self.audit_table_name.clone();
+        input.key_condition_expression = Some("username = :username".into());
+        input.expression_attribute_values = Some(values);
+        input.scan_index_forward = Some(false);
+        input.consistent_read = Some(true);
+        input.limit = Some(1);
+        match self.db.query(input).await {
+            Ok(resp) => match resp.items.unwrap_or_default().into_iter().next() {
+                Some(item) => Ok(Some(item.try_into()?)),
+                None => Ok(None),
+            },
+            Err(e) => Err(ResponseError::DbError(format!("{}", e))),
+        }
+    }
+
+    // Writes the event unless its `seq` is taken, and gives whether it was written.
+    async fn put_event(&self, event: &AuditEvent) -> Result<bool, ResponseError> {
+        let mut item: HashMap<String, AttributeValue> = event.clone().into();
+        item.insert(
+            "expires_at".to_owned(),
+            AttributeValue::from_number(event.entry.at + self.retention),
+        );
+        let mut input = PutItemInput::default();
+        input.table_name = self.audit_table_name.clone();
+        input.item = item;
+        input.condition_expression = Some("attribute_not_exists(seq)".into());
+        match self.db.put_item(input).await {
+            Ok(_) => Ok(true),
+            Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => Ok(false),
+            Err(e) => Err(ResponseError::DbError(format!("{}", e))),
+        }
+    }
+}
+
+#[async_trait]
+impl AuditSink for DynamoDbAuditSink {
+    async fn append(&self, entry: AuditEntry, key: &[u8]) -> Result<AuditEvent, ResponseError> {
+        for _ in 0..APPEND_ATTEMPTS {
+            let event = if entry.username == ANONYMOUS {
+                let seq = unchained_seq(entry.at);
+                AuditEvent::unchained(entry.clone(), seq, key)
+            } else {
+                let prev = self.last_event(&entry.username).await?;
+                AuditEvent::chain(entry.clone(), prev.as_ref(), key)
+            };
+            if self.put_event(&event).await? {
+                return Ok(event);
+            }
+        }
+        Err(ResponseError::DbError(format!(
+            "{} audit chain kept changing",
+            entry.username
+        )))
+    }
+
+    // A user's events are read with a query, in order. Anything else is a
+    // scan. Both filter after reading `limit` events.
+    async fn query(&self, query: &AuditQuery) -> Result<AuditPage, ResponseError> {
+        let start = match &query.cursor {
+            Some(c) => {
+                let (username, seq) = parse_cursor(c)?;
+                Some(event_key(username, seq))
+            }
+            None => None,
+        };
+        let mut names = HashMap::new();
+        let mut values = HashMap::new();
+        let mut filters = Vec::new();
+        if let Some(hostname) = &query.hostname {
+            values.insert(
+                ":hostname".to_owned(),
+                AttributeValue::from_string(hostname.to_lowercase()),
+            );
+            filters.push("contains(hostnames, :hostname)");
+        }
+        if let Some(from) = query.from {
+            names.insert("#at".to_owned(), "at".to_owned());
+            values.insert(":from".to_owned(), AttributeValue::from_number(from));
+            filters.push("#at >= :from");
+        }
+        if let Some(to) = query.to {
+            names.insert("#at".to_owned(), "at".to_owned());
+            values.insert(":to".to_owned(), AttributeValue::from_number(to));
+            filters.push("#at <= :to");
+        }
+        let filter = if filters.is_empty() {
+            None
+        } else {
+            Some(filters.join(" AND "))
+        };
+
+        let resp = match &query.username {
+            Some(username) => {
+                values.insert(
+                    ":username".to_owned(),
+                    AttributeValue::from_string(username.to_owned()),
+                );
+                let mut input = QueryInput::default();
+                input.table_name = self.audit_table_name.clone();
+                input.key_condition_expression = Some("username = :username".into());
+                input.filter_expression = filter;
+                input.expression_attribute_names = non_empty(names);
+                input.expression_attribute_values = Some(values);
+                input.exclusive_start_key = start;
+                input.limit = Some(query.limit as i64);
+                self.db
+                    .query(input)
+                    .await
+                    .map(|resp| (resp.items, resp.last_evaluated_key))
+                    .map_err(|e| ResponseError::DbError(format!("{}", e)))?
+            }
+            None => {
+                let mut input = ScanInput::default();
+                input.table_name = self.audit_table_name.clone();
+                input.filter_expression = filter;
+                input.expression_attribute_names = non_empty(names);
+                input.expression_attribute_values = non_empty(values);
+                input.exclusive_start_key = start;
+                input.limit = Some(query.limit as i64);
+                self.db
+                    .scan(input)
+                    .await
+                    .map(|resp| (resp.items, resp.last_evaluated_key))
+                    .map_err(|e| ResponseError::DbError(format!("{}", e)))?
+            }
+        };
+        let (items, last_key) = resp;
+        let mut events = Vec::new();
+        for item in items.unwrap_or_default() {
+            events.push(AuditEvent::try_from(item)?);
+        }
+        let cursor = match last_key {
+            Some(key) => {
+                let username = key.get_string_att_value("username")?;
+                let seq = key
+                    .get_optional_number_att_value("seq")?
+                    .unwrap_or_default();
+                Some(format!("{}#{}", username, seq))
+            }
+            None => None,
+        };
+        Ok(AuditPage { events, cursor })
+    }
+}
+
+fn unchained_seq(at: i64) -> i64 {
+    let mut random = [0; 4];
+    ChaChaRng::from_entropy().fill(&mut random);
+    at * UNCHAINED_SEQ_SCALE + i64::from(u32::from_le_bytes(random)) % UNCHAINED_SEQ_SCALE
+}
+
+fn non_empty<V>(map: HashMap<String, V>) -> Option<HashMap<String, V>> {
+    if map.is_empty() {
+        None
+    } else {
+        Some(map)
+    }
+}
+
+fn event_key(username: impl AsRef<str>, seq: i64) -> HashMap<String, AttributeValue> {
+    let mut key = HashMap::new();
+    key.insert(
+        "username".into(),
+        AttributeValue::from_string(username.as_ref().to_owned()),
+    );
+    key.insert("seq".into(), AttributeValue::from_number(seq));
+    key
+}
+
+impl TryFrom<HashMap<String, AttributeValue>> for AuditEvent {
+    type Error = ResponseError;
+
+    fn try_from(value: HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
+        Ok(AuditEvent {
+            entry: AuditEntry {
+                at: value
+                    .get_optional_number_att_value("at")?
+                    .unwrap_or_default(),
+                username: value.get_string_att_value("username")?,
+                claimed_username: value.get_optional_string_att_value("claimed_username")?,
+                hostnames: value.get_string_list_att_value("hostnames")?,
+                requested_ip: value.get_optional_string_att_value("requested_ip")?,
+                source_ip: value.get_optional_string_att_value("source_ip")?,
+                user_agent: value.get_optional_string_att_value("user_agent")?,
+                outcome: value.get_string_list_att_value("outcome")?,
+                error: value.get_optional_string_att_value("error")?,
+            },
+            seq: value
+                .get_optional_number_att_value("seq")?
+                .unwrap_or_default(),
+            prev_hash: value
+                .get_optional_string_att_value("prev_hash")?
+                .unwrap_or_default(),
+            hash: value.get_string_att_value("hash")?,
+        })
+    }
+}
+
+impl From<AuditEvent> for HashMap<String, AttributeValue> {
+    fn from(event: AuditEvent) -> Self {
+        let entry = event.entry;
+        let mut map = HashMap::new();
+        map.insert(
+            "username".to_owned(),
+            AttributeValue::from_string(entry.username),
+        );
+        map.insert("seq".to_owned(), AttributeValue::from_number(event.seq));
+        map.insert("at".to_owned(), AttributeValue::from_number(entry.at));
+        map.insert(
+            "hostnames".to_owned(),
+            AttributeValue::from_string_list(entry.hostnames),
+        );
+        let optional = vec![
+            ("claimed_username", entry.claimed_username),
+            ("requested_ip", entry.requested_ip),
+            ("source_ip", entry.source_ip),
+            ("user_agent", entry.user_agent),
+            ("error", entry.error),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                map.insert(key.to_owned(), AttributeValue::from_string(value));
+            }
+        }
+        map.insert(
+            "outcome".to_owned(),
+            AttributeValue::from_string_list(entry.outcome),
+        );
+        if !event.prev_hash.is_empty() {
+            map.insert(
+                "prev_hash".to_owned(),
+                AttributeValue::from_string(event.prev_hash),
+            );
+        }
+        map.insert("hash".to_owned(), AttributeValue::from_string(event.hash));
+        map
+    }
+}
@@ -0,0 +1,64 @@
+use super::{event_cursor, parse_cursor, AuditEntry, AuditEvent, AuditPage, AuditQuery, AuditSink};
+use crate::error::ResponseError;
+use async_trait::async_trait;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+// Events are kept per user, oldest first, and never expire. Clones share the
+// same events.
+#[derive(Clone, Default)]
+pub struct MemoryAuditSink {
+    events: Arc<Mutex<BTreeMap<String, Vec<AuditEvent>>>>,
+}
+
+impl MemoryAuditSink {
+    pub fn new() -> Self {
+        MemoryAuditSink::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Vec<AuditEvent>>> {
+        self.events.lock().expect("memory audit sink lock poisoned")
+    }
+}
+
+#[async_trait]
+impl AuditSink for MemoryAuditSink {
+    async fn append(&self, entry: AuditEntry, key: &[u8]) -> Result<AuditEvent, ResponseError> {
+        let mut events = self.lock();
+        let chain = events.entry(entry.username.clone()).or_default();
+        let event = AuditEvent::chain(entry, chain.last(), key);
+        chain.push(event.clone());
+        Ok(event)
+    }
+
+    async fn query(&self, query: &AuditQuery) -> Result<AuditPage, ResponseError> {
+        let after = match &query.cursor {
+            Some(c) => Some(parse_cursor(c)?),
+            None => None,
+        };
+        let events = self.lock();
+        let mut page: Vec<AuditEvent> = events
+            .values()
+            .flat_map(|chain| chain.iter())
+            .filter(|e| match &after {
+                Some((username, seq)) => (&e.entry.username, e.seq) > (username, *seq),
+                None => true,
+            })
+            .filter(|e| query.matches(e))
+            .take(query.limit + 1)
+            .cloned()
+            .collect();
+        let more = page.len() > query.limit;
+        page.truncate(query.limit);
+        let cursor = match page.last() {
+            Some(last) if more => Some(event_cursor(last)),
+            _ => None,
+        };
+        Ok(AuditPage {
+            events: page,
+            cursor,
+        })
+    }
+}
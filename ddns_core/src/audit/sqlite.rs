@@ -0,0 +1,118 @@
+use super::{event_cursor, parse_cursor, AuditEntry, AuditEvent, AuditPage, AuditQuery, AuditSink};
+use crate::{
+    error::ResponseError,
+    sqlite::{blocking, db_error},
+    user::now,
+};
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+// Events are kept as JSON next to the columns they are looked up by. Events
+// older than the retention are deleted as new ones are added.
+pub struct SqliteAuditSink {
+    conn: Arc<Mutex<Connection>>,
+    retention: i64,
+}
+
+impl SqliteAuditSink {
+    pub fn open(path: impl AsRef<Path>, retention_days: i64) -> Result<Self, ResponseError> {
+        let conn = Connection::open(path).map_err(db_error)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS audit_events (
+                username TEXT NOT NULL,
+                seq INTEGER NOT NULL,
+                at INTEGER NOT NULL,
+                event TEXT NOT NULL,
+                PRIMARY KEY (username, seq)
+            )",
+            params![],
+        )
+        .map_err(db_error)?;
+        Ok(SqliteAuditSink {
+            conn: Arc::new(Mutex::new(conn)),
+            retention: retention_days * 86400,
+        })
+    }
+}
+
+#[async_trait]
+impl AuditSink for SqliteAuditSink {
+    async fn append(&self, entry: AuditEntry, key: &[u8]) -> Result<AuditEvent, ResponseError> {
+        let key = key.to_vec();
+        let oldest = now() - self.retention;
+        blocking(&self.conn, move |conn| {
+            let prev: Option<String> = conn
+                .query_row(
+                    "SELECT event FROM audit_events WHERE username = ?1 ORDER BY seq DESC LIMIT 1",
+                    params![entry.username],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(db_error)?;
+            let prev: Option<AuditEvent> = match prev {
+                Some(raw) => Some(serde_json::from_str(&raw).map_err(db_error)?),
+                None => None,
+            };
+            let event = AuditEvent::chain(entry, prev.as_ref(), &key);
+            let raw = serde_json::to_string(&event).map_err(db_error)?;
+            conn.execute(
+                "INSERT INTO audit_events (username, seq, at, event) VALUES (?1, ?2, ?3, ?4)",
+                params![event.entry.username, event.seq, event.entry.at, raw],
+            )
+            .map_err(db_error)?;
+            conn.execute("DELETE FROM audit_events WHERE at < ?1", params![oldest])
+                .map_err(db_error)?;
+            Ok(event)
+        })
+        .await
+    }
+
+    async fn query(&self, query: &AuditQuery) -> Result<AuditPage, ResponseError> {
+        let (after_user, after_seq) = match &query.cursor {
+            Some(c) => parse_cursor(c)?,
+            None => (String::new(), 0),
+        };
+        let query = query.clone();
+        blocking(&self.conn, move |conn| {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT event FROM audit_events
+                    WHERE (username, seq) > (?1, ?2)
+                    AND (?3 IS NULL OR username = ?3)
+                    AND (?4 IS NULL OR at >= ?4)
+                    AND (?5 IS NULL OR at <= ?5)
+                    ORDER BY username, seq",
+                )
+                .map_err(db_error)?;
+            let rows = stmt
+                .query_map(
+                    params![after_user, after_seq, query.username, query.from, query.to],
+                    |row| row.get::<_, String>(0),
+                )
+                .map_err(db_error)?;
+            let mut events = Vec::new();
+            for raw in rows {
+                let event: AuditEvent =
+                    serde_json::from_str(&raw.map_err(db_error)?).map_err(db_error)?;
+                if query.matches(&event) {
+                    events.push(event);
+                }
+                if events.len() > query.limit {
+                    break;
+                }
+            }
+            let more = events.len() > query.limit;
+            events.truncate(query.limit);
+            let cursor = match events.last() {
+                Some(last) if more => Some(event_cursor(last)),
+                _ => None,
+            };
+            Ok(AuditPage { events, cursor })
+        })
+        .await
+    }
+}
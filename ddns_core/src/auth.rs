@@ -0,0 +1,51 @@
+use crate::error::ResponseError;
+use http::header::HeaderValue;
+use sha2::{Digest, Sha256};
+
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+pub fn parse_authorization(header: &HeaderValue) -> Result<Credentials, ResponseError> {
+    let raw_auth = String::from_utf8(base64::decode(
+        header
+            .to_str()?
+            .strip_prefix("Basic ")
+            .ok_or(ResponseError::MalformedAuthorizationHeader)?,
+    )?)?;
+    let auth_parts: Vec<&str> = raw_auth.splitn(2, ':').collect();
+    if auth_parts.len() != 2 {
+        return Err(ResponseError::MalformedAuthorizationHeader);
+    }
+    Ok(Credentials {
+        username: auth_parts[0].into(),
+        password: auth_parts[1].into(),
+    })
+}
+
+// Compares the SHA-256 digests of the two, which always have the same length,
+// and every byte of them, so the time taken gives away neither the length of
+// `expected` nor how much of `given` was right.
+pub fn secrets_match(given: &str, expected: &str) -> bool {
+    let given = Sha256::digest(given.as_bytes());
+    let expected = Sha256::digest(expected.as_bytes());
+    given
+        .iter()
+        .zip(expected.iter())
+        .fold(0, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_match_only_the_same_secret() {
+        assert!(secrets_match("change-me", "change-me"));
+        assert!(!secrets_match("change-mf", "change-me"));
+        assert!(!secrets_match("change", "change-me"));
+        assert!(!secrets_match("", "change-me"));
+    }
+}
@@ -1,339 +1,744 @@
-use crate::error::ResponseError;
-use rand::{Rng, SeedableRng};
-use rand_chacha::ChaChaRng;
-use rusoto_core::Region;
-use rusoto_dynamodb::{AttributeValue, DynamoDb, DynamoDbClient, GetItemInput, PutItemInput};
-use rusoto_route53::{
-    Change, ChangeBatch, ChangeResourceRecordSetsRequest, ListHostedZonesRequest, ResourceRecord,
-    ResourceRecordSet, Route53, Route53Client,
+use crate::{
+    address::AddressPolicy,
+    audit::{verify_page, AuditEntry, AuditEvent, AuditPage, AuditQuery, AuditSink},
+    config::ClientBuilder,
+    dns::{DnsProvider, RecordChange, RecordSet},
+    dyndns::DynResponse,
+    error::{ConfigError, ResponseError, ResponseErrors},
+    lockout::{AttemptStore, LockoutPolicy},
+    password::Hasher,
+    store::{PutMode, UserPage, UserStore, UserUpdate},
+    token::{check_token_hostname, split_token, too_many_tokens, NewToken, Token, MAX_TOKENS},
+    user::{dummy_user, now, HostState, User},
+    user_agent::UserAgentPolicy,
+    zone::{find_zone, grant_base, normalize_name, Zone},
 };
 use std::{
     collections::{HashMap, HashSet},
-    convert::{TryFrom, TryInto},
-    env,
-    net::Ipv4Addr,
+    net::IpAddr,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
 };
 
+// Made with `ClientBuilder`.
 pub struct Client {
-    db: DynamoDbClient,
-    dns: Route53Client,
-    users_table_name: String,
+    pub(crate) users: Box<dyn UserStore>,
+    pub(crate) dns: Box<dyn DnsProvider>,
+    pub(crate) ttl_limits: TtlLimits,
+    pub(crate) audit: Option<Box<dyn AuditSink>>,
+    // Kept out of the audit table, so the chain cannot be rewritten by anyone
+    // who can only write to the table.
+    pub(crate) audit_key: Vec<u8>,
+    // Events the sink refused since this client was made.
+    pub(crate) dropped_audit_events: AtomicU64,
+    pub(crate) user_agents: UserAgentPolicy,
+    pub(crate) attempts: Box<dyn AttemptStore>,
+    pub(crate) lockout: LockoutPolicy,
+    pub(crate) hasher: Hasher,
+    pub(crate) addresses: AddressPolicy,
 }
 
-impl Default for Client {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TtlLimits {
+    pub default: i64,
+    pub min: i64,
+    pub max: i64,
+}
+
+impl Default for TtlLimits {
     fn default() -> Self {
-        Client {
-            db: DynamoDbClient::new(Region::default()),
-            dns: Route53Client::new(Region::default()),
-            users_table_name: env::var("USERS_TABLE_NAME")
-                .expect("unable to find USERS_TABLE_NAME"),
+        TtlLimits {
+            default: 300,
+            min: 60,
+            max: 86400,
         }
     }
 }
 
+impl TtlLimits {
+    pub fn check(&self, ttl: i64) -> Result<(), String> {
+        if ttl < self.min || ttl > self.max {
+            return Err(format!("must be between {} and {}", self.min, self.max));
+        }
+        Ok(())
+    }
+
+    // Limits can change after a user was created, so a stored TTL is
+    // brought back within the current limits when it is applied.
+    pub(crate) fn apply(&self, ttl: Option<i64>) -> i64 {
+        ttl.unwrap_or(self.default).max(self.min).min(self.max)
+    }
+}
+
 impl Client {
-    pub async fn get_user(&self, username: impl AsRef<str>) -> Result<User, ResponseError> {
-        let mut input = GetItemInput::default();
-        input.table_name = self.users_table_name.clone();
-        input.key.insert(
-            "username".into(),
-            AttributeValue::from_string(username.as_ref().to_owned()),
-        );
-        match self.db.get_item(input).await {
-            Ok(resp) => match resp.item {
-                Some(item) => item.try_into(),
-                None => Err(ResponseError::NotFound(format!(
-                    "{} user",
-                    username.as_ref()
-                ))),
-            },
-            Err(e) => Err(ResponseError::DbError(format!("{}", e))),
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    pub fn from_env() -> Result<Self, ConfigError> {
+        ClientBuilder::from_env()?.build()
+    }
+
+    pub fn address_policy(&self) -> AddressPolicy {
+        self.addresses
+    }
+
+    // What new passwords are hashed with.
+    pub fn hasher(&self) -> &Hasher {
+        &self.hasher
+    }
+
+    // Failing to audit does not fail the request being audited. The event
+    // is logged in full instead, so it can still be recovered from the logs.
+    pub async fn audit(&self, entry: AuditEntry) {
+        if let Some(audit) = &self.audit {
+            let raw = serde_json::to_string(&entry).unwrap_or_default();
+            if let Err(e) = audit.append(entry, &self.audit_key).await {
+                let dropped = self.dropped_audit_events.fetch_add(1, Ordering::Relaxed) + 1;
+                log::error!("dropped audit event ({} so far): {}: {}", dropped, e, raw);
+            }
         }
     }
 
-    pub async fn put_user(&self, user: User) -> Result<(), ResponseError> {
-        let mut input = PutItemInput::default();
-        input.item = user.into();
-        input.table_name = self.users_table_name.clone();
-        match self.db.put_item(input).await {
-            Ok(_) => Ok(()),
-            Err(e) => Err(ResponseError::DbError(format!("{}", e))),
+    pub fn dropped_audit_events(&self) -> u64 {
+        self.dropped_audit_events.load(Ordering::Relaxed)
+    }
+
+    pub async fn query_audit(&self, query: &AuditQuery) -> Result<AuditPage, ResponseError> {
+        match &self.audit {
+            Some(audit) => audit.query(query).await,
+            None => Err(ResponseError::NotFound("audit log".into())),
         }
     }
 
-    pub async fn validate_user(
+    // Whether the event still matches the hash it was stored with.
+    pub fn audit_intact(&self, event: &AuditEvent) -> bool {
+        event.is_intact(&self.audit_key)
+    }
+
+    // Whether each event of a page is intact and follows on from the one
+    // before it. See `verify_page`.
+    pub fn audit_page_intact(&self, events: &[AuditEvent], filtered: bool) -> Vec<bool> {
+        verify_page(events, &self.audit_key, filtered)
+    }
+
+    pub fn ttl_limits(&self) -> &TtlLimits {
+        &self.ttl_limits
+    }
+
+    pub async fn get_user(&self, username: impl AsRef<str>) -> Result<User, ResponseError> {
+        self.users.get_user(username.as_ref()).await
+    }
+
+    // `User::new`, with the password hashed on a blocking thread.
+    pub async fn new_user(
+        &self,
+        username: impl AsRef<str>,
+        password: impl AsRef<str>,
+        domains: HashSet<String>,
+    ) -> Result<User, ResponseError> {
+        let username = username.as_ref().to_owned();
+        let password = password.as_ref().to_owned();
+        self.hasher
+            .blocking(move |hasher| User::new(username, password, domains, hasher))
+            .await
+    }
+
+    // Returns the user as stored, with `updated_at` set.
+    pub async fn put_user(&self, mut user: User, mode: PutMode) -> Result<User, ResponseError> {
+        user.updated_at = now();
+        self.users.put_user(user.clone(), mode).await?;
+        Ok(user)
+    }
+
+    // Returns the user as stored, with `updated_at` set.
+    pub async fn update_user(
+        &self,
+        username: impl AsRef<str>,
+        mut update: UserUpdate,
+    ) -> Result<User, ResponseError> {
+        update.updated_at = now();
+        self.users.update_user(username.as_ref(), update).await
+    }
+
+    pub async fn delete_user(&self, username: impl AsRef<str>) -> Result<(), ResponseError> {
+        self.users.delete_user(username.as_ref()).await
+    }
+
+    pub async fn list_users(
+        &self,
+        limit: usize,
+        cursor: Option<String>,
+    ) -> Result<UserPage, ResponseError> {
+        self.users.list_users(limit, cursor).await
+    }
+
+    // An unknown username gets the same work and the same error as a wrong
+    // password, so neither timing nor the response tells them apart.
+    async fn find_login(
         &self,
         username: impl AsRef<str>,
         raw_pass: impl AsRef<str>,
-        _user_agent: impl AsRef<str>,
-        hostnames: &[String],
-    ) -> Result<(), ResponseError> {
-        let user = self.get_user(username).await?;
-        if !user.compare_password(raw_pass)? {
-            return Err(ResponseError::InvalidCredentials);
-        }
-        for host in hostnames {
-            if !user.has_domain(host) {
-                return Err(ResponseError::HostnameValidation(host.to_owned()));
+    ) -> Result<User, ResponseError> {
+        match self.get_user(username).await {
+            Ok(user) => Ok(user),
+            Err(ResponseError::NotFound(_)) => {
+                dummy_user(&self.hasher)
+                    .compare_password(raw_pass, &self.hasher)
+                    .await?;
+                Err(ResponseError::InvalidCredentials)
             }
+            Err(e) => Err(e),
         }
-        Ok(())
     }
 
-    pub async fn update_hostnames(
+    // Only accepts the account password, never an update token. Goes through
+    // the same lockout as `/nic/update`, so no endpoint can be used to test
+    // guesses without limit.
+    pub async fn authenticate(
         &self,
-        hostnames: &[String],
-        ip: &Ipv4Addr,
-    ) -> Result<(), ResponseError> {
-        let zones = self.list_all_hosted_zones().await?;
-        let mut map: HashMap<String, Vec<(String, Ipv4Addr)>> = HashMap::new();
+        username: impl AsRef<str>,
+        raw_pass: impl AsRef<str>,
+        source_ip: Option<IpAddr>,
+    ) -> Result<User, ResponseError> {
+        let (user, _) = self
+            .authenticate_limited(username, raw_pass, source_ip, false)
+            .await?;
+        Ok(user)
+    }
 
-        for host in hostnames {
-            for zone in &zones {
-                if host.ends_with::<&str>(zone.0.as_ref()) {
-                    match map.get(&zone.1) {
-                        Some(v) => {
-                            let mut v = v.clone();
-                            v.push((host.clone(), ip.to_owned()));
-                            map.insert(zone.1.clone(), v.clone());
-                        }
-                        None => {
-                            map.insert(zone.1.clone(), vec![(host.clone(), ip.to_owned())]);
-                        }
-                    }
-                    break;
+    // Accepts the account password, or with `tokens` one of the user's update
+    // tokens, and returns the token's id when one was used. A password that
+    // happens to look like a token is still checked as a password unless the
+    // user has a token with that id.
+    async fn check_credentials(
+        &self,
+        username: impl AsRef<str>,
+        raw_pass: impl AsRef<str>,
+        tokens: bool,
+    ) -> Result<(User, Option<String>), ResponseError> {
+        let user = self.find_login(username, &raw_pass).await?;
+        match split_token(raw_pass.as_ref()) {
+            Some((id, secret)) if tokens && user.tokens.contains_key(id) => {
+                // Costs what checking a password would, so how long a login
+                // takes does not tell whether the user has a token with `id`.
+                dummy_user(&self.hasher)
+                    .compare_password(&raw_pass, &self.hasher)
+                    .await?;
+                if user.tokens[id].matches(secret) {
+                    Ok((user, Some(id.to_owned())))
+                } else {
+                    Err(ResponseError::InvalidCredentials)
                 }
             }
+            _ => {
+                if !user.compare_password(raw_pass, &self.hasher).await? {
+                    return Err(ResponseError::InvalidCredentials);
+                }
+                Ok((user, None))
+            }
         }
-        for zone in map {
-            self.update_zone_records(zone.0, zone.1).await?;
-        }
-        Ok(())
     }
 
-    async fn update_zone_records(
+    // Counts the attempt against the username and the address before the
+    // password is checked, and refuses to check it at all once either is over
+    // its limit, so parallel guesses cannot all slip in under it. Attempts
+    // that turn out not to be failed logins are taken back. Without the
+    // counts the login is refused rather than let through unlimited.
+    async fn authenticate_limited(
         &self,
-        zone_id: String,
-        records: Vec<(String, Ipv4Addr)>,
-    ) -> Result<(), ResponseError> {
-        let mut req = ChangeResourceRecordSetsRequest {
-            change_batch: ChangeBatch {
-                comment: None,
-                changes: Vec::new(),
-            },
-            hosted_zone_id: zone_id,
-        };
-        for record in records {
-            req.change_batch.changes.push(Change {
-                action: "UPSERT".to_owned(),
-                resource_record_set: ResourceRecordSet {
-                    alias_target: None,
-                    failover: None,
-                    geo_location: None,
-                    health_check_id: None,
-                    multi_value_answer: None,
-                    name: record.0,
-                    region: None,
-                    resource_records: Some(vec![ResourceRecord {
-                        value: format!("{}", record.1),
-                    }]),
-                    set_identifier: None,
-                    ttl: Some(300),
-                    traffic_policy_instance_id: None,
-                    type_: "A".to_owned(),
-                    weight: None,
-                },
-            })
+        username: impl AsRef<str>,
+        raw_pass: impl AsRef<str>,
+        source_ip: Option<IpAddr>,
+        tokens: bool,
+    ) -> Result<(User, Option<String>), ResponseError> {
+        let now = now();
+        let keys = self.lockout.keys(username.as_ref(), source_ip);
+        let mut counted = Vec::new();
+        let mut locked_for = None;
+        for (key, max) in &keys {
+            let attempts = match self
+                .attempts
+                .add_attempt(key, now, self.lockout.lockout_seconds)
+                .await
+            {
+                Ok(attempts) => attempts,
+                Err(e) => {
+                    self.remove_attempts(&counted).await;
+                    return Err(e);
+                }
+            };
+            counted.push(key.as_str());
+            if attempts.count > *max {
+                locked_for = locked_for.max(Some(attempts.expires_at - now));
+            }
         }
-        if let Err(e) = self.dns.change_resource_record_sets(req).await {
-            Err(ResponseError::Route53Error(format!("{}", e)))
-        } else {
-            Ok(())
+        if let Some(seconds) = locked_for {
+            return Err(ResponseError::TooManyAttempts(seconds));
+        }
+        match self.check_credentials(username, raw_pass, tokens).await {
+            Ok(login) => {
+                if let Err(e) = self.attempts.clear(&keys[0].0).await {
+                    log::warn!("unable to clear login attempts: {}", e);
+                }
+                self.remove_attempts(&counted[1..]).await;
+                Ok(login)
+            }
+            Err(e @ ResponseError::InvalidCredentials) => Err(e),
+            Err(e) => {
+                self.remove_attempts(&counted).await;
+                Err(e)
+            }
         }
     }
 
-    async fn list_all_hosted_zones(&self) -> Result<Vec<(String, String)>, ResponseError> {
-        let (mut zones, mut next_marker) = self.list_hosted_zones(None).await?;
-        while next_marker.is_some() {
-            let (more_zones, new_marker) = self.list_hosted_zones(next_marker).await?;
-            next_marker = new_marker;
-            for zone in more_zones {
-                zones.push((zone.0, zone.1));
+    async fn remove_attempts(&self, keys: &[&str]) {
+        for key in keys {
+            if let Err(e) = self.attempts.remove_attempt(key).await {
+                log::warn!("unable to take back login attempt: {}", e);
             }
         }
-        Ok(zones)
     }
 
-    async fn list_hosted_zones(
+    pub async fn change_password(
         &self,
-        marker: Option<String>,
-    ) -> Result<(Vec<(String, String)>, Option<String>), ResponseError> {
-        let mut map: Vec<(String, String)> = Vec::new();
-        let mut req = ListHostedZonesRequest::default();
-        req.marker = marker;
-        let mut next_marker: Option<String> = None;
-        match self.dns.list_hosted_zones(req).await {
-            Ok(resp) => {
-                if resp.is_truncated {
-                    next_marker = resp.next_marker
-                }
-                for zone in resp.hosted_zones {
-                    match zone.config {
-                        Some(c) => match c.private_zone {
-                            Some(false) | None => {
-                                map.push((zone.name.trim_end_matches('.').to_owned(), zone.id));
-                            }
-                            _ => {}
-                        },
-                        None => {
-                            map.push((zone.name.trim_end_matches('.').to_owned(), zone.id));
-                        }
-                    }
+        username: impl AsRef<str>,
+        raw_pass: impl AsRef<str>,
+        new_pass: impl AsRef<str>,
+        source_ip: Option<IpAddr>,
+    ) -> Result<User, ResponseError> {
+        let user = self.authenticate(username, raw_pass, source_ip).await?;
+        let mut update = UserUpdate::default();
+        update.set_password(new_pass, &self.hasher).await?;
+        self.update_user(user.username(), update).await
+    }
+
+    pub async fn validate_user(
+        &self,
+        username: impl AsRef<str>,
+        raw_pass: impl AsRef<str>,
+        user_agent: impl AsRef<str>,
+        source_ip: Option<IpAddr>,
+        hostnames: &[String],
+    ) -> Result<User, ResponseError> {
+        let (mut user, token) = self
+            .authenticate_limited(&username, &raw_pass, source_ip, true)
+            .await?;
+        let token = match token {
+            Some(id) => Some((user.tokens[&id].clone(), id)),
+            None => {
+                if user.needs_rehash(&self.hasher) {
+                    self.rehash(&mut user, raw_pass.as_ref()).await;
                 }
+                None
+            }
+        };
+        self.user_agents.check(&user, user_agent.as_ref())?;
+        let mut errs = ResponseErrors::default();
+        for host in hostnames {
+            let allowed = match &token {
+                Some((token, _)) => token.allows(host),
+                None => true,
+            };
+            if !allowed || !user.has_domain(host) {
+                errs.add(ResponseError::HostnameValidation(host.to_owned()));
             }
-            Err(e) => return Err(ResponseError::Route53Error(format!("{}", e))),
         }
-        Ok((map, next_marker))
+        let user = errs.into_result(user).map_err(ResponseError::from)?;
+        // Failing to record the use does not fail the update.
+        if let Some((_, id)) = token {
+            if let Err(e) = self
+                .users
+                .record_token_use(&user.username, &id, now())
+                .await
+            {
+                log::warn!("unable to record token use: {}", e);
+            }
+        }
+        Ok(user)
     }
-}
 
-pub struct User {
-    username: String,
-    password: String,
-    domains: HashSet<String>,
-}
+    // Tokens can only be bound to hostnames the user may already update, and
+    // never to wildcards.
+    pub async fn create_token(
+        &self,
+        username: impl AsRef<str>,
+        hostnames: HashSet<String>,
+    ) -> Result<NewToken, ResponseError> {
+        let user = self.get_user(&username).await?;
+        let mut errs = ResponseErrors::default();
+        // Reported along with the other errors here, but only the store can
+        // hold the cap against concurrent requests.
+        if user.tokens.len() >= MAX_TOKENS {
+            errs.add(too_many_tokens());
+        }
+        for host in &hostnames {
+            if let Err(reason) = check_token_hostname(host) {
+                errs.add(ResponseError::InvalidField(
+                    "hostnames".into(),
+                    format!("{} {}", host, reason),
+                ));
+            } else if !user.has_domain(host) {
+                errs.add(ResponseError::HostnameValidation(host.to_owned()));
+            }
+        }
+        errs.into_result(()).map_err(ResponseError::from)?;
+        let (id, value, token) = Token::generate(hostnames, now());
+        let info = token.info(&id);
+        self.users.put_token(username.as_ref(), &id, token).await?;
+        Ok(NewToken { info, token: value })
+    }
 
-impl User {
-    pub fn new(
+    pub async fn revoke_token(
+        &self,
         username: impl AsRef<str>,
-        password: impl AsRef<str>,
-        domains: HashSet<String>,
-    ) -> Result<Self, ResponseError> {
-        let mut user = User {
-            username: username.as_ref().to_owned(),
-            password: String::new(),
-            domains,
+        id: impl AsRef<str>,
+    ) -> Result<(), ResponseError> {
+        self.users
+            .delete_token(username.as_ref(), id.as_ref())
+            .await
+    }
+
+    // The password was just checked, so it can be hashed again with the
+    // current parameters and pepper. Failing to store the new hash leaves the
+    // old one, which still works.
+    async fn rehash(&self, user: &mut User, raw_pass: &str) {
+        let (new, pepper_id) = match self.hasher.hash_async(raw_pass).await {
+            Ok(new) => new,
+            Err(e) => {
+                log::warn!("unable to rehash password: {}", e);
+                return;
+            }
         };
-        user.set_password(password.as_ref().to_owned())?;
-        Ok(user)
+        match self
+            .users
+            .replace_password(&user.username, &user.password, &new, pepper_id.as_deref())
+            .await
+        {
+            Ok(true) => {
+                user.password = new;
+                user.pepper_id = pepper_id;
+            }
+            Ok(false) => {}
+            Err(e) => log::warn!("unable to store rehashed password: {}", e),
+        }
     }
 
-    fn set_password(&mut self, pass: impl AsRef<str>) -> Result<(), ResponseError> {
-        let mut config = argon2::Config::default();
-        config.variant = argon2::Variant::Argon2id;
-        let mut rng = ChaChaRng::from_entropy();
-        let mut salt: [u8; 64] = [0; 64];
-        rng.fill(&mut salt);
-        let hash = argon2::hash_encoded(pass.as_ref().as_bytes(), &salt, &config)?;
-        self.password = hash;
-        Ok(())
+    // Each granted hostname's outcome is recorded on the user, up to
+    // `MAX_RECORDED_HOSTS`. Failing to record it does not fail the update,
+    // which has already happened. Hostnames are recorded and returned by
+    // their normalized name, as the audit log has them.
+    pub async fn update_hostnames(
+        &self,
+        user: &User,
+        hostnames: &[String],
+        ips: &[IpAddr],
+        user_agent: impl AsRef<str>,
+    ) -> Result<HashMap<String, UpdateStatus>, ResponseError> {
+        let hostnames: Vec<String> = hostnames.iter().map(normalize_name).collect();
+        let hostnames = hostnames.as_slice();
+        let now = now();
+        let last_ip = ips
+            .iter()
+            .map(|ip| format!("{}", ip))
+            .collect::<Vec<String>>()
+            .join(",");
+        let cached: HashSet<&str> = hostnames
+            .iter()
+            .filter(|h| self.is_cached(user, h, &last_ip, now))
+            .map(|h| h.as_str())
+            .collect();
+        let result = self.apply_hostnames(user, hostnames, ips, &cached).await;
+        let response = match &result {
+            Ok(statuses) => DynResponse::from_statuses(hostnames, statuses, ips),
+            Err(e) => DynResponse::from_error(e, hostnames),
+        };
+        // A refused request can answer with one line for every hostname.
+        let lines = response.lines();
+        let states: HashMap<String, HostState> = hostnames
+            .iter()
+            .zip(lines.iter().chain(lines.last().into_iter().cycle()))
+            .filter(|(host, _)| user.has_domain(host))
+            .map(|(host, code)| {
+                (
+                    host.to_owned(),
+                    HostState {
+                        last_ip: last_ip.clone(),
+                        last_update_at: now,
+                        last_user_agent: user_agent.as_ref().to_owned(),
+                        last_result: format!("{}", code),
+                        ttl: self.ttl_limits.apply(user.ttl_for(host)),
+                        checked_at: match user.hosts.get(host.as_str()) {
+                            Some(state) if cached.contains(host.as_str()) => state.checked_at,
+                            _ => now,
+                        },
+                    },
+                )
+            })
+            .collect();
+        let stale = user.stale_hosts(&states);
+        if let Err(e) = self.users.record_hosts(&user.username, states, stale).await {
+            log::warn!("unable to record host state: {}", e);
+        }
+        result
     }
 
-    fn compare_password(&self, raw_pass: impl AsRef<str>) -> Result<bool, ResponseError> {
-        let verify = argon2::verify_encoded(self.password.as_ref(), raw_pass.as_ref().as_bytes())?;
-        Ok(verify)
+    // Whether the hostname was last left holding these addresses with its
+    // current TTL, recently enough to answer `nochg` without asking DNS.
+    fn is_cached(&self, user: &User, host: &str, last_ip: &str, now: i64) -> bool {
+        match user.hosts.get(host) {
+            Some(state) => {
+                (state.last_result.starts_with("good ") || state.last_result.starts_with("nochg "))
+                    && state.last_ip == last_ip
+                    && state.ttl == self.ttl_limits.apply(user.ttl_for(host))
+                    && now - state.checked_at < RECORD_CACHE_SECONDS
+            }
+            None => false,
+        }
     }
 
-    fn has_domain(&self, domain: impl AsRef<str>) -> bool {
-        self.domains.contains(domain.as_ref())
+    async fn apply_hostnames(
+        &self,
+        user: &User,
+        hostnames: &[String],
+        ips: &[IpAddr],
+        cached: &HashSet<&str>,
+    ) -> Result<HashMap<String, UpdateStatus>, ResponseError> {
+        let mut statuses = HashMap::new();
+        let mut pending = Vec::new();
+        for host in hostnames {
+            if cached.contains(host.as_str()) {
+                statuses.insert(host.to_owned(), UpdateStatus::Unchanged);
+            } else {
+                pending.push(host.to_owned());
+            }
+        }
+        if pending.is_empty() {
+            return Ok(statuses);
+        }
+        let zones = self.dns.list_zones().await?;
+        let map = group_by_zone(&pending, &zones)?;
+        // Every zone is checked before any is changed, so a hostname that
+        // cannot be updated leaves the others untouched.
+        let mut errors = ResponseErrors::default();
+        let mut batches = Vec::new();
+        for (zone_id, hosts) in map {
+            let changes = self
+                .plan_zone_records(user, &zone_id, hosts, ips, &mut statuses, &mut errors)
+                .await?;
+            if !changes.is_empty() {
+                batches.push((zone_id, changes));
+            }
+        }
+        errors.into_result(())?;
+        for (zone_id, changes) in batches {
+            self.dns.apply_change_batch(&zone_id, changes).await?;
+        }
+        Ok(statuses)
     }
-}
 
-impl TryFrom<HashMap<String, AttributeValue>> for User {
-    type Error = ResponseError;
+    // Hostnames may be grants; a wildcard grant is checked by the name it is
+    // under.
+    pub async fn check_hostnames(&self, hostnames: &[String]) -> Result<(), ResponseError> {
+        let zones = self.dns.list_zones().await?;
+        let bases: Vec<String> = hostnames.iter().map(|h| grant_base(h).to_owned()).collect();
+        group_by_zone(&bases, &zones)?;
+        Ok(())
+    }
 
-    fn try_from(value: HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
-        Ok(User {
-            username: value.get_string_att_value("username")?,
-            password: value.get_string_att_value("password")?,
-            domains: value.get_string_set_att_value("domains")?,
-        })
+    async fn plan_zone_records(
+        &self,
+        user: &User,
+        zone_id: &str,
+        hosts: Vec<String>,
+        ips: &[IpAddr],
+        statuses: &mut HashMap<String, UpdateStatus>,
+        errors: &mut ResponseErrors,
+    ) -> Result<Vec<RecordChange>, ResponseError> {
+        let families: Vec<(&str, Vec<IpAddr>)> = vec![
+            ("A", ips.iter().filter(|ip| ip.is_ipv4()).cloned().collect()),
+            (
+                "AAAA",
+                ips.iter().filter(|ip| ip.is_ipv6()).cloned().collect(),
+            ),
+        ];
+        let mut changes = Vec::new();
+        for host in hosts {
+            let current = match self.dns.get_records(zone_id, &host).await {
+                Ok(current) => current,
+                Err(e @ ResponseError::UnmanagedRecord(_)) => {
+                    errors.add(e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let ttl = self.ttl_limits.apply(user.ttl_for(&host));
+            let mut status = UpdateStatus::Unchanged;
+            for (type_, family_ips) in &families {
+                if family_ips.is_empty() || holds_record(&current, type_, family_ips, ttl) {
+                    continue;
+                }
+                changes.push(RecordChange::Upsert(RecordSet {
+                    name: host.clone(),
+                    type_: (*type_).to_owned(),
+                    ttl,
+                    values: family_ips.iter().map(|ip| format!("{}", ip)).collect(),
+                }));
+                status = UpdateStatus::Updated;
+            }
+            statuses.insert(host, status);
+        }
+        Ok(changes)
     }
 }
 
-impl Into<HashMap<String, AttributeValue>> for User {
-    fn into(self) -> HashMap<String, AttributeValue> {
-        let mut map = HashMap::new();
-        map.insert(
-            "username".to_owned(),
-            AttributeValue::from_string(self.username),
-        );
-        map.insert(
-            "password".to_owned(),
-            AttributeValue::from_string(self.password),
-        );
-        map.insert(
-            "domains".to_owned(),
-            AttributeValue::from_string_set(self.domains),
-        );
-        map
+fn group_by_zone(
+    hostnames: &[String],
+    zones: &[Zone],
+) -> Result<HashMap<String, Vec<String>>, ResponseError> {
+    let mut errs = ResponseErrors::default();
+    let mut map: HashMap<String, Vec<String>> = HashMap::new();
+    for host in hostnames {
+        match find_zone(host, zones) {
+            Some(zone) => match map.get_mut(&zone.id) {
+                Some(v) => v.push(host.clone()),
+                None => {
+                    map.insert(zone.id.clone(), vec![host.clone()]);
+                }
+            },
+            None => errs.add(ResponseError::NoHostedZone(host.to_owned())),
+        }
     }
+    errs.into_result(map).map_err(ResponseError::from)
 }
 
-trait AttributeValueExt {
-    type Error;
-    fn get_string(&self) -> Result<String, Self::Error>;
-    fn from_string(value: String) -> Self;
-    fn get_string_set(&self) -> Result<HashSet<String>, Self::Error>;
-    fn from_string_set(value: HashSet<String>) -> Self;
+fn holds_record(sets: &[RecordSet], type_: &str, ips: &[IpAddr], ttl: i64) -> bool {
+    let wanted: HashSet<IpAddr> = ips.iter().cloned().collect();
+    sets.iter()
+        .filter(|s| s.type_ == type_ && s.ttl == ttl)
+        .any(|s| {
+            let current: Option<HashSet<IpAddr>> =
+                s.values.iter().map(|v| IpAddr::from_str(v).ok()).collect();
+            current == Some(wanted.clone())
+        })
 }
 
-impl AttributeValueExt for AttributeValue {
-    type Error = ResponseError;
+// How long a hostname's recorded state is trusted to answer `nochg` before
+// its records are looked up again, in case they were changed outside of this
+// service.
+const RECORD_CACHE_SECONDS: i64 = 3600;
 
-    fn get_string(&self) -> Result<String, Self::Error> {
-        match &self.s {
-            Some(v) => Ok(v.to_owned()),
-            None => Err(ResponseError::DbError("not of type string".into())),
-        }
-    }
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpdateStatus {
+    Updated,
+    Unchanged,
+}
 
-    fn from_string(value: String) -> Self {
-        let mut att = AttributeValue::default();
-        att.s = Some(value);
-        att
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dns::MemoryDnsProvider, lockout::MemoryAttemptStore, password::TEST_PARAMS,
+        store::MemoryUserStore, user::test_user,
+    };
 
-    fn get_string_set(&self) -> Result<HashSet<String>, Self::Error> {
-        match &self.ss {
-            Some(v) => Ok(v.iter().map(|s| s.to_owned()).collect::<HashSet<String>>()),
-            None => Err(ResponseError::DbError("not of type string set".into())),
-        }
+    #[test]
+    fn ttl_limits_check_rejects_ttls_out_of_range() {
+        let limits = TtlLimits::default();
+        assert!(limits.check(60).is_ok());
+        assert!(limits.check(86400).is_ok());
+        assert_eq!(
+            limits.check(59),
+            Err("must be between 60 and 86400".to_owned())
+        );
+        assert!(limits.check(86401).is_err());
     }
 
-    fn from_string_set(value: HashSet<String>) -> Self {
-        let mut att = AttributeValue::default();
-        att.ss = Some(value.iter().map(|s| s.to_owned()).collect());
-        att
+    #[test]
+    fn ttl_limits_apply_defaults_and_clamps() {
+        let limits = TtlLimits {
+            default: 300,
+            min: 120,
+            max: 3600,
+        };
+        assert_eq!(limits.apply(None), 300);
+        assert_eq!(limits.apply(Some(600)), 600);
+        // TTLs stored under older limits are brought within the current ones.
+        assert_eq!(limits.apply(Some(60)), 120);
+        assert_eq!(limits.apply(Some(86400)), 3600);
     }
-}
-
-trait MapAttributeValueExt<K: AsRef<str>> {
-    type Error;
-    fn get_string_att_value(&self, key: K) -> Result<String, Self::Error>;
-    fn get_string_set_att_value(&self, key: K) -> Result<HashSet<String>, Self::Error>;
-}
 
-impl<K: AsRef<str>> MapAttributeValueExt<K> for HashMap<String, AttributeValue> {
-    type Error = ResponseError;
+    // A client with one user, alice, who may update host.example.com.
+    async fn client() -> Client {
+        let users = MemoryUserStore::new();
+        users.put_user(test_user(), PutMode::Create).await.unwrap();
+        Client::builder()
+            .user_store(users)
+            .dns_provider(MemoryDnsProvider::new(vec![Zone::new("example.com", "Z1")]))
+            .attempt_store(MemoryAttemptStore::new())
+            .hash_params(TEST_PARAMS)
+            .build()
+            .unwrap()
+    }
 
-    fn get_string_att_value(&self, key: K) -> Result<String, Self::Error> {
-        match self.get(key.as_ref()) {
-            Some(att) => att.get_string(),
-            None => Err(ResponseError::DbError(format!(
-                "{} not in map",
-                key.as_ref()
-            ))),
+    #[tokio::test]
+    async fn hosts_are_recorded_by_normalized_name() {
+        let client = client().await;
+        let ips = vec![IpAddr::from_str("93.184.216.34").unwrap()];
+        for host in &["Host.Example.com.", "host.example.com"] {
+            let user = client.get_user("alice").await.unwrap();
+            let statuses = client
+                .update_hostnames(&user, &[(*host).to_owned()], &ips, "test/1.0")
+                .await
+                .unwrap();
+            assert_eq!(
+                statuses.keys().collect::<Vec<_>>(),
+                vec!["host.example.com"]
+            );
         }
+        let user = client.get_user("alice").await.unwrap();
+        assert_eq!(
+            user.hosts().keys().collect::<Vec<_>>(),
+            vec!["host.example.com"]
+        );
+        // The second update was answered from the first one's state.
+        assert_eq!(
+            user.hosts()["host.example.com"].last_result,
+            "nochg 93.184.216.34"
+        );
+    }
+
+    fn verified(client: &Client) -> usize {
+        client
+            .hasher
+            .verified
+            .load(std::sync::atomic::Ordering::SeqCst)
     }
 
-    fn get_string_set_att_value(&self, key: K) -> Result<HashSet<String>, Self::Error> {
-        match self.get(key.as_ref()) {
-            Some(att) => att.get_string_set(),
-            None => Err(ResponseError::DbError(format!(
-                "{} not in map",
-                key.as_ref()
-            ))),
+    // Every way of logging in checks one password, so how long it takes does
+    // not tell whether the user exists or has a token with that id.
+    #[tokio::test]
+    async fn every_login_checks_one_password() {
+        let client = client().await;
+        let mut hostnames = HashSet::new();
+        hostnames.insert("host.example.com".to_owned());
+        let token = client.create_token("alice", hostnames).await.unwrap().token;
+        let (id, _) = split_token(&token).unwrap();
+        let wrong_secret = format!("{}.{}", id, "x".repeat(43));
+        let logins = vec![
+            ("alice", "secret", true),
+            ("alice", token.as_str(), true),
+            ("alice", "guess", false),
+            ("alice", wrong_secret.as_str(), false),
+            ("mallory", "secret", false),
+        ];
+        for (username, raw_pass, ok) in logins {
+            let before = verified(&client);
+            let login = client
+                .validate_user(username, raw_pass, "test/1.0", None, &[])
+                .await;
+            assert_eq!(login.is_ok(), ok, "{} {}", username, raw_pass);
+            assert_eq!(verified(&client), before + 1, "{} {}", username, raw_pass);
         }
     }
 }
@@ -0,0 +1,747 @@
+use crate::{
+    address::AddressPolicy,
+    audit::{AuditSink, DynamoDbAuditSink, MemoryAuditSink, DEFAULT_RETENTION_DAYS},
+    client::{Client, TtlLimits},
+    dns::{DnsProvider, Route53Provider},
+    error::ConfigError,
+    lockout::{AttemptStore, DynamoDbAttemptStore, LockoutPolicy, MemoryAttemptStore},
+    password::{HashParams, Hasher},
+    store::{DynamoDbStore, MemoryUserStore, UserStore},
+    user_agent::UserAgentPolicy,
+};
+use async_trait::async_trait;
+use rusoto_core::{
+    credential::{AwsCredentials, CredentialsError, ProvideAwsCredentials, StaticProvider},
+    HttpClient, Region,
+};
+use rusoto_dynamodb::DynamoDbClient;
+use rusoto_route53::Route53Client;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::Path,
+    str::FromStr,
+    sync::{atomic::AtomicU64, Arc},
+};
+
+// Every setting is optional so a config file only needs the values that differ
+// from the defaults. Environment variables take precedence over the file.
+// Unknown keys are refused, so a misspelt setting is not silently ignored.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub region: Option<String>,
+    pub users_table_name: Option<String>,
+    pub dynamodb_endpoint: Option<String>,
+    pub route53_endpoint: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+    pub user_store: Option<String>,
+    pub sqlite_path: Option<String>,
+    pub default_ttl: Option<i64>,
+    pub min_ttl: Option<i64>,
+    pub max_ttl: Option<i64>,
+    pub audit_sink: Option<String>,
+    pub audit_table_name: Option<String>,
+    pub audit_retention_days: Option<i64>,
+    pub audit_key: Option<String>,
+    pub audit_key_file: Option<String>,
+    pub user_agent_format: Option<String>,
+    pub blocked_user_agents: Option<Vec<String>>,
+    pub address_policy: Option<String>,
+    pub attempt_store: Option<String>,
+    pub attempts_table_name: Option<String>,
+    pub max_user_failures: Option<i64>,
+    pub max_ip_failures: Option<i64>,
+    pub lockout_seconds: Option<i64>,
+    pub argon2_mem_cost: Option<u32>,
+    pub argon2_time_cost: Option<u32>,
+    pub argon2_lanes: Option<u32>,
+    pub pepper_id: Option<String>,
+    pub pepper: Option<String>,
+    pub pepper_file: Option<String>,
+    pub retired_peppers: Option<HashMap<String, String>>,
+}
+
+impl Settings {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let raw = fs::read_to_string(path.as_ref())
+            .map_err(|e| ConfigError::File(format!("{}: {}", path.as_ref().display(), e)))?;
+        toml::from_str(&raw)
+            .map_err(|e| ConfigError::File(format!("{}: {}", path.as_ref().display(), e)))
+    }
+
+    pub fn merge_env(&mut self) -> Result<(), ConfigError> {
+        env_string("DDNS_REGION", &mut self.region);
+        env_string("USERS_TABLE_NAME", &mut self.users_table_name);
+        env_string("DYNAMODB_ENDPOINT", &mut self.dynamodb_endpoint);
+        env_string("ROUTE53_ENDPOINT", &mut self.route53_endpoint);
+        env_string("USER_STORE", &mut self.user_store);
+        env_string("SQLITE_PATH", &mut self.sqlite_path);
+        env_i64("DEFAULT_TTL", &mut self.default_ttl)?;
+        env_i64("MIN_TTL", &mut self.min_ttl)?;
+        env_i64("MAX_TTL", &mut self.max_ttl)?;
+        env_string("AUDIT_SINK", &mut self.audit_sink);
+        env_string("AUDIT_TABLE_NAME", &mut self.audit_table_name);
+        env_i64("AUDIT_RETENTION_DAYS", &mut self.audit_retention_days)?;
+        env_string("AUDIT_KEY", &mut self.audit_key);
+        env_string("AUDIT_KEY_FILE", &mut self.audit_key_file);
+        env_string("USER_AGENT_FORMAT", &mut self.user_agent_format);
+        env_string("ADDRESS_POLICY", &mut self.address_policy);
+        env_string("ATTEMPT_STORE", &mut self.attempt_store);
+        env_string("ATTEMPTS_TABLE_NAME", &mut self.attempts_table_name);
+        env_i64("MAX_USER_FAILURES", &mut self.max_user_failures)?;
+        env_i64("MAX_IP_FAILURES", &mut self.max_ip_failures)?;
+        env_i64("LOCKOUT_SECONDS", &mut self.lockout_seconds)?;
+        env_u32("ARGON2_MEM_COST", &mut self.argon2_mem_cost)?;
+        env_u32("ARGON2_TIME_COST", &mut self.argon2_time_cost)?;
+        env_u32("ARGON2_LANES", &mut self.argon2_lanes)?;
+        env_string("PEPPER_ID", &mut self.pepper_id);
+        env_string("PEPPER", &mut self.pepper);
+        env_string("PEPPER_FILE", &mut self.pepper_file);
+        let mut retired = None;
+        env_string("RETIRED_PEPPERS", &mut retired);
+        if let Some(retired) = retired {
+            let mut peppers = HashMap::new();
+            for entry in retired.split(',') {
+                let mut kv = entry.splitn(2, '=');
+                match (kv.next(), kv.next()) {
+                    (Some(id), Some(secret)) => {
+                        peppers.insert(id.trim().to_owned(), secret.to_owned());
+                    }
+                    _ => {
+                        return Err(ConfigError::Invalid(
+                            "RETIRED_PEPPERS".into(),
+                            "entries must look like id=secret".into(),
+                        ))
+                    }
+                }
+            }
+            self.retired_peppers = Some(peppers);
+        }
+        let mut blocked = None;
+        env_string("BLOCKED_USER_AGENTS", &mut blocked);
+        if let Some(blocked) = blocked {
+            self.blocked_user_agents = Some(
+                blocked
+                    .split(',')
+                    .map(|a| a.trim().to_owned())
+                    .filter(|a| !a.is_empty())
+                    .collect(),
+            );
+        }
+        Ok(())
+    }
+}
+
+fn env_string(key: &str, value: &mut Option<String>) {
+    if let Ok(v) = env::var(key) {
+        if !v.is_empty() {
+            *value = Some(v);
+        }
+    }
+}
+
+fn env_i64(key: &str, value: &mut Option<i64>) -> Result<(), ConfigError> {
+    let mut raw = None;
+    env_string(key, &mut raw);
+    if let Some(raw) = raw {
+        *value =
+            Some(raw.parse().map_err(|_| {
+                ConfigError::Invalid(key.into(), format!("{} is not a number", raw))
+            })?);
+    }
+    Ok(())
+}
+
+fn env_u32(key: &str, value: &mut Option<u32>) -> Result<(), ConfigError> {
+    let mut raw = None;
+    env_string(key, &mut raw);
+    if let Some(raw) = raw {
+        *value = Some(raw.parse().map_err(|_| {
+            ConfigError::Invalid(key.into(), format!("{} is not a positive number", raw))
+        })?);
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct ClientBuilder {
+    region: Option<Region>,
+    users_table_name: Option<String>,
+    dynamodb_endpoint: Option<String>,
+    route53_endpoint: Option<String>,
+    credentials: Option<SharedCredentials>,
+    ttl_limits: TtlLimits,
+    users: Option<Box<dyn UserStore>>,
+    dns: Option<Box<dyn DnsProvider>>,
+    audit_table_name: Option<String>,
+    audit_retention_days: Option<i64>,
+    audit: Option<Box<dyn AuditSink>>,
+    audit_key: Option<Vec<u8>>,
+    user_agents: UserAgentPolicy,
+    addresses: AddressPolicy,
+    attempts_table_name: Option<String>,
+    attempts: Option<Box<dyn AttemptStore>>,
+    lockout: LockoutPolicy,
+    hasher: Hasher,
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        ClientBuilder::default()
+    }
+
+    // Reads the file named by DDNS_CONFIG, if any, then applies environment
+    // variables on top of it.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let mut settings = match env::var("DDNS_CONFIG") {
+            Ok(path) if !path.is_empty() => Settings::from_file(path)?,
+            _ => Settings::default(),
+        };
+        settings.merge_env()?;
+        ClientBuilder::from_settings(settings)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        ClientBuilder::from_settings(Settings::from_file(path)?)
+    }
+
+    pub fn from_settings(settings: Settings) -> Result<Self, ConfigError> {
+        let mut builder = ClientBuilder::new();
+        if let Some(region) = settings.region {
+            builder.region = Some(
+                Region::from_str(&region)
+                    .map_err(|e| ConfigError::Invalid("region".into(), format!("{}", e)))?,
+            );
+        }
+        builder.users_table_name = settings.users_table_name;
+        builder.dynamodb_endpoint = settings.dynamodb_endpoint;
+        builder.route53_endpoint = settings.route53_endpoint;
+        match (settings.access_key_id, settings.secret_access_key) {
+            (Some(key), Some(secret)) => {
+                builder = builder.credentials(StaticProvider::new(
+                    key,
+                    secret,
+                    settings.session_token,
+                    None,
+                ))
+            }
+            (None, None) => {}
+            (Some(_), None) => return Err(ConfigError::Missing("secret_access_key".into())),
+            (None, Some(_)) => return Err(ConfigError::Missing("access_key_id".into())),
+        };
+        let defaults = TtlLimits::default();
+        builder.ttl_limits = TtlLimits {
+            default: settings.default_ttl.unwrap_or(defaults.default),
+            min: settings.min_ttl.unwrap_or(defaults.min),
+            max: settings.max_ttl.unwrap_or(defaults.max),
+        };
+        match settings.user_store.as_deref() {
+            None | Some("dynamodb") => {}
+            Some("memory") => builder.users = Some(Box::new(MemoryUserStore::new())),
+            Some("sqlite") => builder.users = Some(sqlite_store(settings.sqlite_path.clone())?),
+            Some(other) => {
+                return Err(ConfigError::Invalid(
+                    "user_store".into(),
+                    format!("{} is not one of dynamodb, memory or sqlite", other),
+                ))
+            }
+        };
+        if let Some(format) = settings.user_agent_format {
+            builder.user_agents.format = format
+                .parse()
+                .map_err(|e| ConfigError::Invalid("user_agent_format".into(), e))?;
+        }
+        builder.user_agents.blocked = settings.blocked_user_agents.unwrap_or_default();
+        if let Some(policy) = settings.address_policy {
+            builder.addresses = policy
+                .parse()
+                .map_err(|e| ConfigError::Invalid("address_policy".into(), e))?;
+        }
+        let defaults = LockoutPolicy::default();
+        builder.lockout = LockoutPolicy {
+            max_user_failures: settings
+                .max_user_failures
+                .unwrap_or(defaults.max_user_failures),
+            max_ip_failures: settings.max_ip_failures.unwrap_or(defaults.max_ip_failures),
+            lockout_seconds: settings.lockout_seconds.unwrap_or(defaults.lockout_seconds),
+        };
+        let defaults = HashParams::default();
+        builder.hasher = Hasher::new(HashParams {
+            mem_cost: settings.argon2_mem_cost.unwrap_or(defaults.mem_cost),
+            time_cost: settings.argon2_time_cost.unwrap_or(defaults.time_cost),
+            lanes: settings.argon2_lanes.unwrap_or(defaults.lanes),
+        });
+        let pepper = match (settings.pepper, settings.pepper_file) {
+            (Some(_), Some(_)) => {
+                return Err(ConfigError::Invalid(
+                    "pepper_file".into(),
+                    "cannot be set along with pepper".into(),
+                ))
+            }
+            (Some(pepper), None) => Some(pepper.into_bytes()),
+            (None, Some(path)) => Some(read_secret(&path)?),
+            (None, None) => None,
+        };
+        match (settings.pepper_id, pepper) {
+            (Some(id), Some(pepper)) => builder.hasher = builder.hasher.with_pepper(id, pepper),
+            (Some(_), None) => return Err(ConfigError::Missing("pepper".into())),
+            (None, Some(_)) => return Err(ConfigError::Missing("pepper_id".into())),
+            (None, None) => {}
+        }
+        for (id, secret) in settings.retired_peppers.unwrap_or_default() {
+            if builder.hasher.pepper_id() == Some(id.as_str()) {
+                return Err(ConfigError::Invalid(
+                    "retired_peppers".into(),
+                    format!("{} is the current pepper_id", id),
+                ));
+            }
+            builder.hasher = builder.hasher.with_retired_pepper(id, secret.into_bytes());
+        }
+        // Without a store named, counts go to DynamoDB once a table is set,
+        // and `build` refuses to go on without one.
+        match settings.attempt_store.as_deref() {
+            None => builder.attempts_table_name = settings.attempts_table_name,
+            Some("dynamodb") => {
+                builder.attempts_table_name = Some(
+                    settings
+                        .attempts_table_name
+                        .ok_or_else(|| ConfigError::Missing("attempts_table_name".into()))?,
+                )
+            }
+            Some("memory") => builder.attempts = Some(Box::new(MemoryAttemptStore::new())),
+            Some("sqlite") => {
+                builder.attempts = Some(sqlite_attempts(settings.sqlite_path.clone())?)
+            }
+            Some(other) => {
+                return Err(ConfigError::Invalid(
+                    "attempt_store".into(),
+                    format!("{} is not one of dynamodb, memory or sqlite", other),
+                ))
+            }
+        };
+        let retention = settings
+            .audit_retention_days
+            .unwrap_or(DEFAULT_RETENTION_DAYS);
+        builder.audit_retention_days = Some(retention);
+        builder.audit_key = match (settings.audit_key, settings.audit_key_file) {
+            (Some(_), Some(_)) => {
+                return Err(ConfigError::Invalid(
+                    "audit_key_file".into(),
+                    "cannot be set along with audit_key".into(),
+                ))
+            }
+            (Some(key), None) => Some(key.into_bytes()),
+            (None, Some(path)) => Some(read_secret(&path)?),
+            (None, None) => None,
+        };
+        // Without a sink named, updates are audited once a table is set.
+        match settings.audit_sink.as_deref() {
+            None => builder.audit_table_name = settings.audit_table_name,
+            Some("none") => {}
+            Some("dynamodb") => {
+                builder.audit_table_name = Some(
+                    settings
+                        .audit_table_name
+                        .ok_or_else(|| ConfigError::Missing("audit_table_name".into()))?,
+                )
+            }
+            Some("memory") => builder.audit = Some(Box::new(MemoryAuditSink::new())),
+            Some("sqlite") => builder.audit = Some(sqlite_audit(settings.sqlite_path, retention)?),
+            Some(other) => {
+                return Err(ConfigError::Invalid(
+                    "audit_sink".into(),
+                    format!("{} is not one of none, dynamodb, memory or sqlite", other),
+                ))
+            }
+        };
+        Ok(builder)
+    }
+
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    pub fn users_table_name(mut self, name: impl AsRef<str>) -> Self {
+        self.users_table_name = Some(name.as_ref().to_owned());
+        self
+    }
+
+    pub fn dynamodb_endpoint(mut self, endpoint: impl AsRef<str>) -> Self {
+        self.dynamodb_endpoint = Some(endpoint.as_ref().to_owned());
+        self
+    }
+
+    pub fn route53_endpoint(mut self, endpoint: impl AsRef<str>) -> Self {
+        self.route53_endpoint = Some(endpoint.as_ref().to_owned());
+        self
+    }
+
+    // Without credentials, each AWS client finds its own the usual way.
+    pub fn credentials(
+        mut self,
+        credentials: impl ProvideAwsCredentials + Send + Sync + 'static,
+    ) -> Self {
+        self.credentials = Some(SharedCredentials(Arc::new(credentials)));
+        self
+    }
+
+    pub fn ttl_limits(mut self, ttl_limits: TtlLimits) -> Self {
+        self.ttl_limits = ttl_limits;
+        self
+    }
+
+    pub fn user_store(mut self, users: impl UserStore + 'static) -> Self {
+        self.users = Some(Box::new(users));
+        self
+    }
+
+    pub fn dns_provider(mut self, dns: impl DnsProvider + 'static) -> Self {
+        self.dns = Some(Box::new(dns));
+        self
+    }
+
+    pub fn user_agent_policy(mut self, user_agents: UserAgentPolicy) -> Self {
+        self.user_agents = user_agents;
+        self
+    }
+
+    pub fn address_policy(mut self, addresses: AddressPolicy) -> Self {
+        self.addresses = addresses;
+        self
+    }
+
+    pub fn attempts_table_name(mut self, name: impl AsRef<str>) -> Self {
+        self.attempts_table_name = Some(name.as_ref().to_owned());
+        self
+    }
+
+    pub fn attempt_store(mut self, attempts: impl AttemptStore + 'static) -> Self {
+        self.attempts = Some(Box::new(attempts));
+        self
+    }
+
+    pub fn lockout_policy(mut self, lockout: LockoutPolicy) -> Self {
+        self.lockout = lockout;
+        self
+    }
+
+    pub fn hash_params(mut self, hash_params: HashParams) -> Self {
+        self.hasher = self.hasher.with_params(hash_params);
+        self
+    }
+
+    pub fn pepper(mut self, id: impl AsRef<str>, secret: impl Into<Vec<u8>>) -> Self {
+        self.hasher = self.hasher.with_pepper(id, secret);
+        self
+    }
+
+    pub fn retired_pepper(mut self, id: impl AsRef<str>, secret: impl Into<Vec<u8>>) -> Self {
+        self.hasher = self.hasher.with_retired_pepper(id, secret);
+        self
+    }
+
+    pub fn audit_table_name(mut self, name: impl AsRef<str>) -> Self {
+        self.audit_table_name = Some(name.as_ref().to_owned());
+        self
+    }
+
+    pub fn audit_retention_days(mut self, days: i64) -> Self {
+        self.audit_retention_days = Some(days);
+        self
+    }
+
+    pub fn audit_sink(mut self, audit: impl AuditSink + 'static) -> Self {
+        self.audit = Some(Box::new(audit));
+        self
+    }
+
+    pub fn audit_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.audit_key = Some(key.into());
+        self
+    }
+
+    pub fn build(self) -> Result<Client, ConfigError> {
+        let limits = self.ttl_limits;
+        if limits.min < 0 || limits.min > limits.max {
+            return Err(ConfigError::Invalid(
+                "min_ttl".into(),
+                format!("must be between 0 and max_ttl ({})", limits.max),
+            ));
+        }
+        if limits.check(limits.default).is_err() {
+            return Err(ConfigError::Invalid(
+                "default_ttl".into(),
+                format!("must be between {} and {}", limits.min, limits.max),
+            ));
+        }
+        if self.user_agents.blocked.iter().any(|a| a.is_empty()) {
+            return Err(ConfigError::Invalid(
+                "blocked_user_agents".into(),
+                "contains an empty entry, which would block every agent".into(),
+            ));
+        }
+        let lockout = self.lockout;
+        for (key, value) in &[
+            ("max_user_failures", lockout.max_user_failures),
+            ("max_ip_failures", lockout.max_ip_failures),
+            ("lockout_seconds", lockout.lockout_seconds),
+        ] {
+            if *value < 1 {
+                return Err(ConfigError::Invalid(
+                    (*key).into(),
+                    "must be at least 1".into(),
+                ));
+            }
+        }
+        self.hasher
+            .params()
+            .check()
+            .map_err(|e| ConfigError::Invalid("argon2".into(), e))?;
+        self.hasher
+            .check()
+            .map_err(|e| ConfigError::Invalid("pepper".into(), e))?;
+        let retention = self.audit_retention_days.unwrap_or(DEFAULT_RETENTION_DAYS);
+        if retention < 1 {
+            return Err(ConfigError::Invalid(
+                "audit_retention_days".into(),
+                "must be at least 1".into(),
+            ));
+        }
+        let region = self.region.clone().unwrap_or_default();
+        let dynamodb_region = endpoint_region(&region, &self.dynamodb_endpoint);
+        let users = match self.users {
+            Some(users) => users,
+            None => {
+                let table = self
+                    .users_table_name
+                    .as_ref()
+                    .ok_or_else(|| ConfigError::Missing("users_table_name".into()))?;
+                let db = dynamodb_client(&dynamodb_region, &self.credentials)?;
+                Box::new(DynamoDbStore::with_client(db, table))
+            }
+        };
+        let dns = match self.dns {
+            Some(dns) => dns,
+            None => {
+                let region = endpoint_region(&region, &self.route53_endpoint);
+                let dns = match &self.credentials {
+                    Some(creds) => Route53Client::new_with(http_client()?, creds.clone(), region),
+                    None => Route53Client::new(region),
+                };
+                Box::new(Route53Provider::from(dns))
+            }
+        };
+        let audit: Option<Box<dyn AuditSink>> = match (self.audit, &self.audit_table_name) {
+            (Some(audit), _) => Some(audit),
+            (None, Some(table)) => {
+                let db = dynamodb_client(&dynamodb_region, &self.credentials)?;
+                Some(Box::new(DynamoDbAuditSink::with_client(
+                    db, table, retention,
+                )))
+            }
+            (None, None) => None,
+        };
+        // An unkeyed chain can be recomputed by anyone who can write to it, so
+        // a sink without a key is refused rather than quietly left unaudited.
+        let (audit, audit_key) = match (audit, self.audit_key) {
+            (Some(_), None) => return Err(ConfigError::Missing("audit_key".into())),
+            (Some(_), Some(key)) if key.is_empty() => {
+                return Err(ConfigError::Invalid(
+                    "audit_key".into(),
+                    "cannot be empty".into(),
+                ))
+            }
+            (audit, key) => (audit, key.unwrap_or_default()),
+        };
+        // A memory store is only kept for as long as the process, which for a
+        // Lambda function can be a single request, so it has to be asked for.
+        let attempts: Box<dyn AttemptStore> = match (self.attempts, &self.attempts_table_name) {
+            (Some(attempts), _) => attempts,
+            (None, Some(table)) => {
+                let db = dynamodb_client(&dynamodb_region, &self.credentials)?;
+                Box::new(DynamoDbAttemptStore::with_client(db, table))
+            }
+            (None, None) => return Err(ConfigError::Missing("attempt_store".into())),
+        };
+        Ok(Client {
+            users,
+            dns,
+            ttl_limits: limits,
+            audit,
+            audit_key,
+            dropped_audit_events: AtomicU64::new(0),
+            user_agents: self.user_agents,
+            attempts,
+            lockout,
+            hasher: self.hasher,
+            addresses: self.addresses,
+        })
+    }
+}
+
+// One provider shared by every AWS client the builder makes.
+#[derive(Clone)]
+struct SharedCredentials(Arc<dyn ProvideAwsCredentials + Send + Sync>);
+
+#[async_trait]
+impl ProvideAwsCredentials for SharedCredentials {
+    async fn credentials(&self) -> Result<AwsCredentials, CredentialsError> {
+        self.0.credentials().await
+    }
+}
+
+// Secret files usually end with a newline that is not part of the secret.
+fn read_secret(path: impl AsRef<Path>) -> Result<Vec<u8>, ConfigError> {
+    let mut secret = fs::read(path.as_ref())
+        .map_err(|e| ConfigError::File(format!("{}: {}", path.as_ref().display(), e)))?;
+    while secret.last() == Some(&b'\n') || secret.last() == Some(&b'\r') {
+        secret.pop();
+    }
+    Ok(secret)
+}
+
+fn endpoint_region(region: &Region, endpoint: &Option<String>) -> Region {
+    match endpoint {
+        Some(endpoint) => Region::Custom {
+            name: region.name().to_owned(),
+            endpoint: endpoint.to_owned(),
+        },
+        None => region.clone(),
+    }
+}
+
+// Users, audit events and login attempts each get a client for their table.
+fn dynamodb_client(
+    region: &Region,
+    credentials: &Option<SharedCredentials>,
+) -> Result<DynamoDbClient, ConfigError> {
+    Ok(match credentials {
+        Some(creds) => DynamoDbClient::new_with(http_client()?, creds.clone(), region.clone()),
+        None => DynamoDbClient::new(region.clone()),
+    })
+}
+
+fn http_client() -> Result<HttpClient, ConfigError> {
+    HttpClient::new().map_err(|e| ConfigError::Invalid("credentials".into(), format!("{}", e)))
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_store(path: Option<String>) -> Result<Box<dyn UserStore>, ConfigError> {
+    let path = path.ok_or_else(|| ConfigError::Missing("sqlite_path".into()))?;
+    match crate::store::SqliteUserStore::open(&path) {
+        Ok(store) => Ok(Box::new(store)),
+        Err(e) => Err(ConfigError::Invalid("sqlite_path".into(), format!("{}", e))),
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn sqlite_store(_: Option<String>) -> Result<Box<dyn UserStore>, ConfigError> {
+    Err(ConfigError::Invalid(
+        "user_store".into(),
+        "sqlite support was not compiled in".into(),
+    ))
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_audit(path: Option<String>, retention: i64) -> Result<Box<dyn AuditSink>, ConfigError> {
+    let path = path.ok_or_else(|| ConfigError::Missing("sqlite_path".into()))?;
+    match crate::audit::SqliteAuditSink::open(&path, retention) {
+        Ok(audit) => Ok(Box::new(audit)),
+        Err(e) => Err(ConfigError::Invalid("sqlite_path".into(), format!("{}", e))),
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn sqlite_audit(_: Option<String>, _: i64) -> Result<Box<dyn AuditSink>, ConfigError> {
+    Err(ConfigError::Invalid(
+        "audit_sink".into(),
+        "sqlite support was not compiled in".into(),
+    ))
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_attempts(path: Option<String>) -> Result<Box<dyn AttemptStore>, ConfigError> {
+    let path = path.ok_or_else(|| ConfigError::Missing("sqlite_path".into()))?;
+    match crate::lockout::SqliteAttemptStore::open(&path) {
+        Ok(attempts) => Ok(Box::new(attempts)),
+        Err(e) => Err(ConfigError::Invalid("sqlite_path".into(), format!("{}", e))),
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn sqlite_attempts(_: Option<String>) -> Result<Box<dyn AttemptStore>, ConfigError> {
+    Err(ConfigError::Invalid(
+        "attempt_store".into(),
+        "sqlite support was not compiled in".into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::MemoryDnsProvider;
+
+    fn memory_settings() -> Settings {
+        Settings {
+            user_store: Some("memory".into()),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn an_attempt_store_is_required() {
+        let build = |settings| {
+            ClientBuilder::from_settings(settings)
+                .unwrap()
+                .dns_provider(MemoryDnsProvider::new(Vec::new()))
+                .build()
+        };
+        match build(memory_settings()) {
+            Err(ConfigError::Missing(key)) => assert_eq!(key, "attempt_store"),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("built a client without an attempt store"),
+        }
+        let settings = Settings {
+            attempt_store: Some("memory".into()),
+            ..memory_settings()
+        };
+        assert!(build(settings).is_ok());
+    }
+
+    #[test]
+    fn an_audit_sink_needs_a_key() {
+        let build = |settings| {
+            ClientBuilder::from_settings(settings)
+                .unwrap()
+                .dns_provider(MemoryDnsProvider::new(Vec::new()))
+                .build()
+        };
+        let settings = Settings {
+            attempt_store: Some("memory".into()),
+            audit_sink: Some("memory".into()),
+            ..memory_settings()
+        };
+        match build(settings.clone()) {
+            Err(ConfigError::Missing(key)) => assert_eq!(key, "audit_key"),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("built an audit sink without a key"),
+        }
+        let settings = Settings {
+            audit_key: Some("key".into()),
+            ..settings
+        };
+        assert!(build(settings).is_ok());
+    }
+
+    #[test]
+    fn unknown_settings_are_refused() {
+        let settings: Settings = toml::from_str("user_store = \"memory\"").unwrap();
+        assert_eq!(settings.user_store.as_deref(), Some("memory"));
+        assert!(toml::from_str::<Settings>("users_table = \"users\"").is_err());
+    }
+}
@@ -0,0 +1,33 @@
+use crate::{error::ResponseError, zone::Zone};
+use async_trait::async_trait;
+
+mod memory;
+mod route53;
+
+pub use memory::MemoryDnsProvider;
+pub use route53::Route53Provider;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordSet {
+    pub name: String,
+    pub type_: String,
+    pub ttl: i64,
+    pub values: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordChange {
+    Upsert(RecordSet),
+}
+
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    async fn list_zones(&self) -> Result<Vec<Zone>, ResponseError>;
+    async fn get_records(&self, zone_id: &str, name: &str)
+        -> Result<Vec<RecordSet>, ResponseError>;
+    async fn apply_change_batch(
+        &self,
+        zone_id: &str,
+        changes: Vec<RecordChange>,
+    ) -> Result<(), ResponseError>;
+}
@@ -0,0 +1,123 @@
+use super::{DnsProvider, RecordChange, RecordSet};
+use crate::{
+    error::ResponseError,
+    zone::{normalize_name, Zone},
+};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+
+// Keeps zones and records in memory and remembers every change batch that was
+// applied, so callers can assert on exactly what would have been sent to DNS.
+// Clones share the same state.
+#[derive(Clone, Default)]
+pub struct MemoryDnsProvider {
+    inner: Arc<Mutex<MemoryDns>>,
+}
+
+#[derive(Default)]
+struct MemoryDns {
+    zones: Vec<Zone>,
+    records: Vec<(String, RecordSet)>,
+    // Names that hold an alias or routing policy record.
+    unmanaged: Vec<(String, String)>,
+    batches: Vec<(String, Vec<RecordChange>)>,
+    lookups: usize,
+}
+
+impl MemoryDnsProvider {
+    pub fn new(zones: Vec<Zone>) -> Self {
+        let provider = MemoryDnsProvider::default();
+        provider.lock().zones = zones;
+        provider
+    }
+
+    pub fn insert_record(&self, zone_id: impl AsRef<str>, record: RecordSet) {
+        upsert(&mut self.lock().records, zone_id.as_ref(), record);
+    }
+
+    // Makes `get_records` refuse the name, as Route 53 does for a name with an
+    // alias or routing policy record.
+    pub fn insert_unmanaged(&self, zone_id: impl AsRef<str>, name: impl AsRef<str>) {
+        self.lock()
+            .unmanaged
+            .push((zone_id.as_ref().to_owned(), normalize_name(name)));
+    }
+
+    pub fn records(&self, zone_id: impl AsRef<str>) -> Vec<RecordSet> {
+        self.lock()
+            .records
+            .iter()
+            .filter(|(z, _)| z == zone_id.as_ref())
+            .map(|(_, r)| r.clone())
+            .collect()
+    }
+
+    pub fn applied_batches(&self) -> Vec<(String, Vec<RecordChange>)> {
+        self.lock().batches.clone()
+    }
+
+    // How many times records were looked up.
+    pub fn lookups(&self) -> usize {
+        self.lock().lookups
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MemoryDns> {
+        self.inner.lock().expect("memory dns lock poisoned")
+    }
+}
+
+fn is_same_set(a: &RecordSet, b: &RecordSet) -> bool {
+    normalize_name(&a.name) == normalize_name(&b.name) && a.type_ == b.type_
+}
+
+fn upsert(records: &mut Vec<(String, RecordSet)>, zone_id: &str, record: RecordSet) {
+    records.retain(|(z, r)| !(z == zone_id && is_same_set(r, &record)));
+    records.push((zone_id.to_owned(), record));
+}
+
+#[async_trait]
+impl DnsProvider for MemoryDnsProvider {
+    async fn list_zones(&self) -> Result<Vec<Zone>, ResponseError> {
+        Ok(self.lock().zones.clone())
+    }
+
+    async fn get_records(
+        &self,
+        zone_id: &str,
+        name: &str,
+    ) -> Result<Vec<RecordSet>, ResponseError> {
+        self.lock().lookups += 1;
+        if self
+            .lock()
+            .unmanaged
+            .iter()
+            .any(|(z, n)| z == zone_id && *n == normalize_name(name))
+        {
+            return Err(ResponseError::UnmanagedRecord(name.to_owned()));
+        }
+        Ok(self
+            .records(zone_id)
+            .into_iter()
+            .filter(|r| normalize_name(&r.name) == normalize_name(name))
+            .collect())
+    }
+
+    async fn apply_change_batch(
+        &self,
+        zone_id: &str,
+        changes: Vec<RecordChange>,
+    ) -> Result<(), ResponseError> {
+        let mut dns = self.lock();
+        if !dns.zones.iter().any(|z| z.id == zone_id) {
+            return Err(ResponseError::Route53Error(format!(
+                "{} hosted zone does not exist",
+                zone_id
+            )));
+        }
+        for RecordChange::Upsert(r) in &changes {
+            upsert(&mut dns.records, zone_id, r.clone());
+        }
+        dns.batches.push((zone_id.to_owned(), changes));
+        Ok(())
+    }
+}
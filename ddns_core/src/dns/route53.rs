@@ -0,0 +1,168 @@
+use super::{DnsProvider, RecordChange, RecordSet};
+use crate::{
+    error::ResponseError,
+    zone::{normalize_name, Zone},
+};
+use async_trait::async_trait;
+use rusoto_core::Region;
+use rusoto_route53::{
+    Change, ChangeBatch, ChangeResourceRecordSetsRequest, ListHostedZonesRequest,
+    ListResourceRecordSetsRequest, ResourceRecord, ResourceRecordSet, Route53, Route53Client,
+};
+
+const RECORD_PAGE_SIZE: &str = "10";
+
+pub struct Route53Provider {
+    dns: Route53Client,
+}
+
+impl Route53Provider {
+    pub fn new(region: Region) -> Self {
+        Route53Provider {
+            dns: Route53Client::new(region),
+        }
+    }
+
+    async fn list_hosted_zones(
+        &self,
+        marker: Option<String>,
+    ) -> Result<(Vec<Zone>, Option<String>), ResponseError> {
+        let mut map: Vec<Zone> = Vec::new();
+        let mut req = ListHostedZonesRequest::default();
+        req.marker = marker;
+        let mut next_marker: Option<String> = None;
+        match self.dns.list_hosted_zones(req).await {
+            Ok(resp) => {
+                if resp.is_truncated {
+                    next_marker = resp.next_marker
+                }
+                for zone in resp.hosted_zones {
+                    match zone.config {
+                        Some(c) => match c.private_zone {
+                            Some(false) | None => {
+                                map.push(Zone::new(zone.name, zone.id));
+                            }
+                            _ => {}
+                        },
+                        None => {
+                            map.push(Zone::new(zone.name, zone.id));
+                        }
+                    }
+                }
+            }
+            Err(e) => return Err(ResponseError::Route53Error(format!("{}", e))),
+        }
+        Ok((map, next_marker))
+    }
+}
+
+impl From<Route53Client> for Route53Provider {
+    fn from(dns: Route53Client) -> Self {
+        Route53Provider { dns }
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Route53Provider {
+    async fn list_zones(&self) -> Result<Vec<Zone>, ResponseError> {
+        let (mut zones, mut next_marker) = self.list_hosted_zones(None).await?;
+        while next_marker.is_some() {
+            let (more_zones, new_marker) = self.list_hosted_zones(next_marker).await?;
+            next_marker = new_marker;
+            zones.extend(more_zones);
+        }
+        Ok(zones)
+    }
+
+    async fn get_records(
+        &self,
+        zone_id: &str,
+        name: &str,
+    ) -> Result<Vec<RecordSet>, ResponseError> {
+        let mut req = ListResourceRecordSetsRequest::default();
+        req.hosted_zone_id = zone_id.to_owned();
+        req.start_record_name = Some(name.to_owned());
+        // Only the sets at `name` matter, and they come first.
+        req.max_items = Some(RECORD_PAGE_SIZE.to_owned());
+        let resp = match self.dns.list_resource_record_sets(req).await {
+            Ok(resp) => resp,
+            Err(e) => return Err(ResponseError::Route53Error(format!("{}", e))),
+        };
+        let sets: Vec<ResourceRecordSet> = resp
+            .resource_record_sets
+            .into_iter()
+            .filter(|r| normalize_name(&r.name) == normalize_name(name))
+            .collect();
+        // Route 53 refuses a plain UPSERT over an alias or a weighted, latency,
+        // failover or geolocation record of the same type, so leave those to
+        // whoever made them.
+        if sets.iter().any(|r| {
+            (r.type_ == "A" || r.type_ == "AAAA")
+                && (r.alias_target.is_some() || r.set_identifier.is_some())
+        }) {
+            return Err(ResponseError::UnmanagedRecord(name.to_owned()));
+        }
+        Ok(sets
+            .into_iter()
+            .filter(|r| r.set_identifier.is_none() && r.alias_target.is_none())
+            .map(|r| RecordSet {
+                name: r.name,
+                type_: r.type_,
+                ttl: r.ttl.unwrap_or_default(),
+                values: r
+                    .resource_records
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|v| v.value)
+                    .collect(),
+            })
+            .collect())
+    }
+
+    async fn apply_change_batch(
+        &self,
+        zone_id: &str,
+        changes: Vec<RecordChange>,
+    ) -> Result<(), ResponseError> {
+        let req = ChangeResourceRecordSetsRequest {
+            change_batch: ChangeBatch {
+                comment: None,
+                changes: changes.into_iter().map(to_change).collect(),
+            },
+            hosted_zone_id: zone_id.to_owned(),
+        };
+        if let Err(e) = self.dns.change_resource_record_sets(req).await {
+            Err(ResponseError::Route53Error(format!("{}", e)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn to_change(change: RecordChange) -> Change {
+    let RecordChange::Upsert(record) = change;
+    Change {
+        action: "UPSERT".to_owned(),
+        resource_record_set: ResourceRecordSet {
+            alias_target: None,
+            failover: None,
+            geo_location: None,
+            health_check_id: None,
+            multi_value_answer: None,
+            name: record.name,
+            region: None,
+            resource_records: Some(
+                record
+                    .values
+                    .into_iter()
+                    .map(|value| ResourceRecord { value })
+                    .collect(),
+            ),
+            set_identifier: None,
+            ttl: Some(record.ttl),
+            traffic_policy_instance_id: None,
+            type_: record.type_,
+            weight: None,
+        },
+    }
+}
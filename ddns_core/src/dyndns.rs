@@ -0,0 +1,224 @@
+use crate::{client::UpdateStatus, error::ResponseError};
+use http::{Response, StatusCode};
+use lambda_http::{Body, IntoResponse};
+use std::{collections::HashMap, env, fmt, net::IpAddr, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ResponseMode {
+    #[default]
+    Json,
+    Dyn,
+}
+
+impl ResponseMode {
+    pub fn from_env() -> Self {
+        env::var("NIC_RESPONSE_MODE")
+            .ok()
+            .and_then(|mode| mode.parse().ok())
+            .unwrap_or_default()
+    }
+}
+
+impl FromStr for ResponseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("json") {
+            Ok(ResponseMode::Json)
+        } else if s.eq_ignore_ascii_case("dyn") {
+            Ok(ResponseMode::Dyn)
+        } else {
+            Err(format!("{} is not one of json or dyn", s))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReturnCode {
+    Good(Vec<IpAddr>),
+    NoChg(Vec<IpAddr>),
+    BadAuth,
+    NotFqdn,
+    NoHost,
+    NumHost,
+    Abuse,
+    BadAgent,
+    DnsErr,
+    ServerError,
+}
+
+impl fmt::Display for ReturnCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReturnCode::Good(ips) => write!(f, "good {}", join_ips(ips)),
+            ReturnCode::NoChg(ips) => write!(f, "nochg {}", join_ips(ips)),
+            ReturnCode::BadAuth => write!(f, "badauth"),
+            ReturnCode::NotFqdn => write!(f, "notfqdn"),
+            ReturnCode::NoHost => write!(f, "nohost"),
+            ReturnCode::NumHost => write!(f, "numhost"),
+            ReturnCode::Abuse => write!(f, "abuse"),
+            ReturnCode::BadAgent => write!(f, "badagent"),
+            ReturnCode::DnsErr => write!(f, "dnserr"),
+            ReturnCode::ServerError => write!(f, "911"),
+        }
+    }
+}
+
+fn join_ips(ips: &[IpAddr]) -> String {
+    ips.iter()
+        .map(|ip| format!("{}", ip))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+impl From<&ResponseError> for ReturnCode {
+    fn from(e: &ResponseError) -> Self {
+        match e {
+            ResponseError::MissingHeader(h) if h.eq_ignore_ascii_case("User-Agent") => {
+                ReturnCode::BadAgent
+            }
+            ResponseError::MissingHeader(_) => ReturnCode::BadAuth,
+            ResponseError::MissingQuery(q) if q == "hostname" => ReturnCode::NotFqdn,
+            // Bad input such as `myip` is the client's mistake and retrying it
+            // would not help, so it is not `911`, which is kept for failures on
+            // the server's side.
+            ResponseError::MissingQuery(_) => ReturnCode::NoHost,
+            ResponseError::InvalidQuery(q, _) if q == "hostname" => ReturnCode::NumHost,
+            ResponseError::InvalidQuery(_, _) => ReturnCode::NoHost,
+            ResponseError::MissingField(_) => ReturnCode::NoHost,
+            ResponseError::InvalidField(_, _) => ReturnCode::NoHost,
+            ResponseError::MalformedAuthorizationHeader => ReturnCode::BadAuth,
+            ResponseError::ParseError(_) => ReturnCode::NoHost,
+            ResponseError::Http(_) => ReturnCode::ServerError,
+            ResponseError::Base64Decode(_) => ReturnCode::BadAuth,
+            ResponseError::FromUtf8Error(_) => ReturnCode::BadAuth,
+            ResponseError::MultipleErrors(es) => match es.first() {
+                Some(e) => ReturnCode::from(e),
+                None => ReturnCode::ServerError,
+            },
+            ResponseError::UserExists => ReturnCode::NoHost,
+            ResponseError::InvalidCredentials => ReturnCode::BadAuth,
+            ResponseError::Forbidden => ReturnCode::BadAuth,
+            ResponseError::BadAgent(_) => ReturnCode::BadAgent,
+            ResponseError::TooManyAttempts(_) => ReturnCode::Abuse,
+            ResponseError::HostnameValidation(_) => ReturnCode::NoHost,
+            ResponseError::NoHostedZone(_) => ReturnCode::NoHost,
+            ResponseError::InvalidHostname(_) => ReturnCode::NotFqdn,
+            ResponseError::UnmanagedRecord(_) => ReturnCode::NoHost,
+            ResponseError::DbError(_) => ReturnCode::ServerError,
+            ResponseError::Route53Error(_) => ReturnCode::DnsErr,
+            ResponseError::NotFound(_) => ReturnCode::BadAuth,
+            ResponseError::Argon(_) => ReturnCode::ServerError,
+        }
+    }
+}
+
+pub struct DynResponse {
+    lines: Vec<ReturnCode>,
+}
+
+impl DynResponse {
+    pub fn lines(&self) -> &[ReturnCode] {
+        &self.lines
+    }
+
+    pub fn from_statuses(
+        hostnames: &[String],
+        statuses: &HashMap<String, UpdateStatus>,
+        ips: &[IpAddr],
+    ) -> Self {
+        DynResponse {
+            lines: hostnames
+                .iter()
+                .map(|h| match statuses.get(h) {
+                    Some(UpdateStatus::Unchanged) => ReturnCode::NoChg(ips.to_vec()),
+                    _ => ReturnCode::Good(ips.to_vec()),
+                })
+                .collect(),
+        }
+    }
+
+    // Errors that name a hostname only apply to that hostname's line. Every
+    // other hostname gets the first error that is not tied to a hostname, as
+    // nothing in the request was updated. When there is no such error the
+    // request is refused with a single line for the first error instead, so
+    // a hostname with nothing wrong is never given another one's code.
+    pub fn from_error(e: &ResponseError, hostnames: &[String]) -> Self {
+        let errors = match e {
+            ResponseError::MultipleErrors(es) => es.iter().collect::<Vec<&ResponseError>>(),
+            e => vec![e],
+        };
+        let general = errors.iter().find(|e| error_hostname(e).is_none());
+        let mut lines = Vec::new();
+        for host in hostnames {
+            match errors
+                .iter()
+                .find(|e| error_hostname(e) == Some(host.as_str()))
+                .or(general)
+            {
+                Some(e) => lines.push(ReturnCode::from(*e)),
+                None => break,
+            }
+        }
+        if hostnames.is_empty() || lines.len() < hostnames.len() {
+            let first = errors.first().copied().unwrap_or(e);
+            lines = vec![ReturnCode::from(first)];
+        }
+        DynResponse { lines }
+    }
+}
+
+fn error_hostname(e: &ResponseError) -> Option<&str> {
+    match e {
+        ResponseError::HostnameValidation(h) => Some(h.as_ref()),
+        ResponseError::NoHostedZone(h) => Some(h.as_ref()),
+        ResponseError::InvalidHostname(h) => Some(h.as_ref()),
+        ResponseError::UnmanagedRecord(h) => Some(h.as_ref()),
+        _ => None,
+    }
+}
+
+impl IntoResponse for DynResponse {
+    fn into_response(self) -> Response<Body> {
+        let body = self
+            .lines
+            .iter()
+            .map(|l| format!("{}", l))
+            .collect::<Vec<String>>()
+            .join("\n");
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/plain")
+            .body(Body::from(body))
+            .expect("unable to create response")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_mistakes_are_not_911() {
+        let errors = vec![
+            ResponseError::MissingQuery("username".into()),
+            ResponseError::InvalidQuery("myip".into(), "1.2.3 is not a valid IP address".into()),
+            ResponseError::InvalidQuery(
+                "myipv6".into(),
+                "1.2.3.4 is not a valid IPv6 address".into(),
+            ),
+            ResponseError::ParseError("expected value".into()),
+        ];
+        for e in &errors {
+            assert_eq!(ReturnCode::from(e), ReturnCode::NoHost, "{:?}", e);
+        }
+        let e = ResponseError::InvalidQuery("hostname".into(), "bad".into());
+        assert_eq!(ReturnCode::from(&e), ReturnCode::NumHost);
+    }
+
+    #[test]
+    fn server_failures_are_911() {
+        let e = ResponseError::DbError("timed out".into());
+        assert_eq!(format!("{}", ReturnCode::from(&e)), "911");
+    }
+}
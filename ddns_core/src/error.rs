@@ -19,7 +19,15 @@ pub enum ResponseError {
     MultipleErrors(Vec<ResponseError>),
     UserExists,
     InvalidCredentials,
+    Forbidden,
+    BadAgent(String),
+    // Seconds until another attempt is allowed.
+    TooManyAttempts(i64),
     HostnameValidation(String),
+    NoHostedZone(String),
+    InvalidHostname(String),
+    // The hostname has an alias or routing policy record.
+    UnmanagedRecord(String),
 
     DbError(String),
     Route53Error(String),
@@ -45,7 +53,15 @@ impl std::fmt::Display for ResponseError {
             ResponseError::MultipleErrors(_) => write!(f, "many errors have occured"),
             ResponseError::UserExists => write!(f, "user already exist"),
             ResponseError::InvalidCredentials => write!(f, "credentials are not valid"),
+            ResponseError::Forbidden => write!(f, "missing or invalid API key"),
+            ResponseError::BadAgent(_) => write!(f, "user agent is not allowed"),
+            ResponseError::TooManyAttempts(_) => write!(f, "too many failed attempts"),
             ResponseError::HostnameValidation(_) => write!(f, "not authorized to update hostname"),
+            ResponseError::NoHostedZone(_) => write!(f, "hostname is not in a managed hosted zone"),
+            ResponseError::InvalidHostname(_) => write!(f, "hostname is not a valid hostname"),
+            ResponseError::UnmanagedRecord(_) => {
+                write!(f, "hostname has alias or routing policy records")
+            }
             ResponseError::DbError(_) => write!(f, "error occured in database"),
             ResponseError::Route53Error(_) => write!(f, "error occured in route53"),
             ResponseError::NotFound(_) => write!(f, "item was not found"),
@@ -56,6 +72,25 @@ impl std::fmt::Display for ResponseError {
 
 impl std::error::Error for ResponseError {}
 
+#[derive(Debug, Clone)]
+pub enum ConfigError {
+    Missing(String),
+    Invalid(String, String),
+    File(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Missing(k) => write!(f, "missing setting {}", k),
+            ConfigError::Invalid(k, r) => write!(f, "invalid setting {}: {}", k, r),
+            ConfigError::File(e) => write!(f, "unable to read config file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 impl From<httpError> for ResponseError {
     fn from(e: httpError) -> Self {
         ResponseError::Http(format!("{}", e))
@@ -96,6 +131,36 @@ impl From<ResponseErrors> for ResponseError {
 }
 
 impl ResponseError {
+    // The variant's name, as recorded in the audit log.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ResponseError::MissingHeader(_) => "MissingHeader",
+            ResponseError::MissingQuery(_) => "MissingQuery",
+            ResponseError::InvalidQuery(_, _) => "InvalidQuery",
+            ResponseError::MissingField(_) => "MissingField",
+            ResponseError::InvalidField(_, _) => "InvalidField",
+            ResponseError::MalformedAuthorizationHeader => "MalformedAuthorizationHeader",
+            ResponseError::ParseError(_) => "ParseError",
+            ResponseError::Http(_) => "Http",
+            ResponseError::Base64Decode(_) => "Base64Decode",
+            ResponseError::FromUtf8Error(_) => "FromUtf8Error",
+            ResponseError::MultipleErrors(_) => "MultipleErrors",
+            ResponseError::UserExists => "UserExists",
+            ResponseError::InvalidCredentials => "InvalidCredentials",
+            ResponseError::Forbidden => "Forbidden",
+            ResponseError::BadAgent(_) => "BadAgent",
+            ResponseError::TooManyAttempts(_) => "TooManyAttempts",
+            ResponseError::HostnameValidation(_) => "HostnameValidation",
+            ResponseError::NoHostedZone(_) => "NoHostedZone",
+            ResponseError::InvalidHostname(_) => "InvalidHostname",
+            ResponseError::UnmanagedRecord(_) => "UnmanagedRecord",
+            ResponseError::DbError(_) => "DbError",
+            ResponseError::Route53Error(_) => "Route53Error",
+            ResponseError::NotFound(_) => "NotFound",
+            ResponseError::Argon(_) => "Argon",
+        }
+    }
+
     fn status(&self) -> StatusCode {
         match self {
             ResponseError::MissingHeader(_) => StatusCode::BAD_REQUEST,
@@ -108,10 +173,21 @@ impl ResponseError {
             ResponseError::Http(_) => StatusCode::BAD_REQUEST,
             ResponseError::Base64Decode(_) => StatusCode::BAD_REQUEST,
             ResponseError::FromUtf8Error(_) => StatusCode::BAD_REQUEST,
-            ResponseError::MultipleErrors(_) => StatusCode::BAD_REQUEST,
-            ResponseError::UserExists => StatusCode::BAD_REQUEST,
+            // Several hostnames failing the same way, e.g. none of them
+            // granted, keep that status.
+            ResponseError::MultipleErrors(es) => match es.first() {
+                Some(first) if es.iter().all(|e| e.status() == first.status()) => first.status(),
+                _ => StatusCode::BAD_REQUEST,
+            },
+            ResponseError::UserExists => StatusCode::CONFLICT,
             ResponseError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            ResponseError::Forbidden => StatusCode::FORBIDDEN,
+            ResponseError::BadAgent(_) => StatusCode::FORBIDDEN,
+            ResponseError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
             ResponseError::HostnameValidation(_) => StatusCode::UNAUTHORIZED,
+            ResponseError::NoHostedZone(_) => StatusCode::BAD_REQUEST,
+            ResponseError::InvalidHostname(_) => StatusCode::BAD_REQUEST,
+            ResponseError::UnmanagedRecord(_) => StatusCode::CONFLICT,
             ResponseError::DbError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ResponseError::Route53Error(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ResponseError::NotFound(_) => StatusCode::NOT_FOUND,
@@ -137,7 +213,16 @@ impl ResponseError {
             ResponseError::MultipleErrors(e) => Some(ResponseErrorInfo::from(e)),
             ResponseError::UserExists => None,
             ResponseError::InvalidCredentials => None,
+            ResponseError::Forbidden => None,
+            ResponseError::BadAgent(r) => Some(ResponseErrorInfo::from(r)),
+            ResponseError::TooManyAttempts(s) => Some(ResponseErrorInfo::from(format!(
+                "try again in {} seconds",
+                s
+            ))),
             ResponseError::HostnameValidation(h) => Some(ResponseErrorInfo::from(h)),
+            ResponseError::NoHostedZone(h) => Some(ResponseErrorInfo::from(h)),
+            ResponseError::InvalidHostname(h) => Some(ResponseErrorInfo::from(h)),
+            ResponseError::UnmanagedRecord(h) => Some(ResponseErrorInfo::from(h)),
             ResponseError::DbError(_) => None,
             ResponseError::Route53Error(_) => None,
             ResponseError::NotFound(_) => None,
@@ -1,2 +1,19 @@
+pub mod address;
+pub mod audit;
+pub mod auth;
 pub mod client;
+pub mod config;
+pub mod dns;
+pub mod dyndns;
 pub mod error;
+pub mod lockout;
+pub mod logging;
+pub mod password;
+pub mod source;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub mod store;
+pub mod token;
+pub mod user;
+pub mod user_agent;
+pub mod zone;
@@ -0,0 +1,117 @@
+use crate::error::ResponseError;
+use async_trait::async_trait;
+use std::net::IpAddr;
+
+mod dynamodb;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use dynamodb::DynamoDbAttemptStore;
+pub use memory::MemoryAttemptStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteAttemptStore;
+
+// Failed logins for one key. The count is forgotten at `expires_at`, which
+// every new failure pushes back.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Attempts {
+    pub count: i64,
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LockoutPolicy {
+    pub max_user_failures: i64,
+    pub max_ip_failures: i64,
+    // How long failures are remembered, and so how long a lockout lasts.
+    pub lockout_seconds: i64,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        LockoutPolicy {
+            max_user_failures: 5,
+            max_ip_failures: 20,
+            lockout_seconds: 900,
+        }
+    }
+}
+
+impl LockoutPolicy {
+    // Usernames are counted separately from addresses so one attacker spread
+    // over many addresses still locks the account, and one address guessing
+    // many usernames is still stopped.
+    pub(crate) fn keys(&self, username: &str, source_ip: Option<IpAddr>) -> Vec<(String, i64)> {
+        let mut keys = vec![(format!("user#{}", username), self.max_user_failures)];
+        if let Some(ip) = source_ip {
+            keys.push((format!("ip#{}", ip), self.max_ip_failures));
+        }
+        keys
+    }
+}
+
+#[async_trait]
+pub trait AttemptStore: Send + Sync {
+    // Expired counts read as no attempts.
+    async fn get_attempts(&self, key: &str, now: i64) -> Result<Attempts, ResponseError>;
+    // Counts an attempt, keeping it until `now + ttl`, and returns the count
+    // including it. Attempts are counted before the password is checked, so
+    // this must be atomic: parallel guesses each see their own count.
+    async fn add_attempt(&self, key: &str, now: i64, ttl: i64) -> Result<Attempts, ResponseError>;
+    // Takes back one attempt that turned out not to be a failed login.
+    async fn remove_attempt(&self, key: &str) -> Result<(), ResponseError>;
+    async fn clear(&self, key: &str) -> Result<(), ResponseError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_count_usernames_and_addresses_separately() {
+        let policy = LockoutPolicy::default();
+        let ip = "198.51.100.7".parse().unwrap();
+        assert_eq!(
+            policy.keys("alice", Some(ip)),
+            vec![
+                ("user#alice".to_owned(), 5),
+                ("ip#198.51.100.7".to_owned(), 20)
+            ]
+        );
+        assert_eq!(
+            policy.keys("alice", None),
+            vec![("user#alice".to_owned(), 5)]
+        );
+    }
+
+    // What every attempt store has to do, checked against each of them.
+    pub(crate) async fn check_store(store: &dyn AttemptStore) {
+        assert_eq!(store.add_attempt("k", 100, 10).await.unwrap().count, 1);
+        let attempts = store.add_attempt("k", 105, 10).await.unwrap();
+        assert_eq!(
+            attempts,
+            Attempts {
+                count: 2,
+                expires_at: 115
+            }
+        );
+        assert_eq!(store.get_attempts("k", 114).await.unwrap(), attempts);
+        assert_eq!(
+            store.get_attempts("k", 115).await.unwrap(),
+            Attempts::default()
+        );
+        assert_eq!(store.add_attempt("k", 120, 10).await.unwrap().count, 1);
+
+        store.add_attempt("j", 100, 10).await.unwrap();
+        store.add_attempt("j", 100, 10).await.unwrap();
+        store.remove_attempt("j").await.unwrap();
+        assert_eq!(store.get_attempts("j", 100).await.unwrap().count, 1);
+        store.clear("j").await.unwrap();
+        assert_eq!(store.get_attempts("j", 100).await.unwrap().count, 0);
+        store.remove_attempt("j").await.unwrap();
+        assert_eq!(store.get_attempts("j", 100).await.unwrap().count, 0);
+        // Other keys were left alone.
+        assert_eq!(store.get_attempts("k", 120).await.unwrap().count, 1);
+    }
+}
@@ -0,0 +1,196 @@
+use super::{AttemptStore, Attempts};
+use crate::{
+    error::ResponseError,
+    store::{AttributeValueExt, MapAttributeValueExt},
+};
+use async_trait::async_trait;
+use rusoto_core::{Region, RusotoError};
+use rusoto_dynamodb::{
+    AttributeValue, DeleteItemInput, DynamoDb, DynamoDbClient, GetItemInput, PutItemError,
+    PutItemInput, UpdateItemError, UpdateItemInput, UpdateItemOutput,
+};
+use std::collections::HashMap;
+
+// How many times a count that expires or is restarted concurrently is tried
+// again before giving up.
+const RESTART_ATTEMPTS: usize = 3;
+
+// The table is keyed by `key`, with TTL enabled on `expires_at`. DynamoDB can
+// take a while to delete expired items, so they are also checked on read.
+pub struct DynamoDbAttemptStore {
+    db: DynamoDbClient,
+    attempts_table_name: String,
+}
+
+impl DynamoDbAttemptStore {
+    pub fn new(region: Region, attempts_table_name: impl AsRef<str>) -> Self {
+        DynamoDbAttemptStore::with_client(DynamoDbClient::new(region), attempts_table_name)
+    }
+
+    pub fn with_client(db: DynamoDbClient, attempts_table_name: impl AsRef<str>) -> Self {
+        DynamoDbAttemptStore {
+            db,
+            attempts_table_name: attempts_table_name.as_ref().to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl AttemptStore for DynamoDbAttemptStore {
+    async fn get_attempts(&self, key: &str, now: i64) -> Result<Attempts, ResponseError> {
+        let mut input = GetItemInput::default();
+        input.table_name = self.attempts_table_name.clone();
+        input.key = attempts_key(key);
+        input.consistent_read = Some(true);
+        match self.db.get_item(input).await {
+            Ok(resp) => match resp.item {
+                Some(item) => {
+                    let attempts = to_attempts(&item)?;
+                    if attempts.expires_at > now {
+                        Ok(attempts)
+                    } else {
+                        Ok(Attempts::default())
+                    }
+                }
+                None => Ok(Attempts::default()),
+            },
+            Err(e) => Err(ResponseError::DbError(format!("{}", e))),
+        }
+    }
+
+    // Counts up while the item is live. A missing or expired item fails the
+    // condition and is started again at 1, but only if it is still missing or
+    // expired, so a concurrent attempt that restarted it first is counted on
+    // instead of overwritten.
+    async fn add_attempt(&self, key: &str, now: i64, ttl: i64) -> Result<Attempts, ResponseError> {
+        for _ in 0..RESTART_ATTEMPTS {
+            match self.count_attempt(key, now, ttl).await {
+                Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {}
+                Ok(resp) => return to_attempts(&resp.attributes.unwrap_or_default()),
+                Err(e) => return Err(ResponseError::DbError(format!("{}", e))),
+            }
+            let attempts = Attempts {
+                count: 1,
+                expires_at: now + ttl,
+            };
+            match self.restart_attempts(key, now, attempts).await {
+                Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => {}
+                Ok(_) => return Ok(attempts),
+                Err(e) => return Err(ResponseError::DbError(format!("{}", e))),
+            }
+        }
+        Err(ResponseError::DbError(format!(
+            "{} login attempts kept changing",
+            key
+        )))
+    }
+
+    async fn remove_attempt(&self, key: &str) -> Result<(), ResponseError> {
+        let mut values = HashMap::new();
+        values.insert(":one".to_owned(), AttributeValue::from_number(1));
+        values.insert(":zero".to_owned(), AttributeValue::from_number(0));
+        let mut input = UpdateItemInput::default();
+        input.table_name = self.attempts_table_name.clone();
+        input.key = attempts_key(key);
+        input.update_expression = Some("SET #count = #count - :one".into());
+        input.condition_expression = Some("#count > :zero".into());
+        input.expression_attribute_names = Some(count_name());
+        input.expression_attribute_values = Some(values);
+        match self.db.update_item(input).await {
+            Ok(_) => Ok(()),
+            Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(()),
+            Err(e) => Err(ResponseError::DbError(format!("{}", e))),
+        }
+    }
+
+    async fn clear(&self, key: &str) -> Result<(), ResponseError> {
+        let mut input = DeleteItemInput::default();
+        input.table_name = self.attempts_table_name.clone();
+        input.key = attempts_key(key);
+        match self.db.delete_item(input).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ResponseError::DbError(format!("{}", e))),
+        }
+    }
+}
+
+impl DynamoDbAttemptStore {
+    async fn count_attempt(
+        &self,
+        key: &str,
+        now: i64,
+        ttl: i64,
+    ) -> Result<UpdateItemOutput, RusotoError<UpdateItemError>> {
+        let mut values = HashMap::new();
+        values.insert(":one".to_owned(), AttributeValue::from_number(1));
+        values.insert(":now".to_owned(), AttributeValue::from_number(now));
+        values.insert(
+            ":expires".to_owned(),
+            AttributeValue::from_number(now + ttl),
+        );
+        let mut input = UpdateItemInput::default();
+        input.table_name = self.attempts_table_name.clone();
+        input.key = attempts_key(key);
+        input.update_expression = Some("ADD #count :one SET expires_at = :expires".into());
+        input.condition_expression = Some("expires_at > :now".into());
+        input.expression_attribute_names = Some(count_name());
+        input.expression_attribute_values = Some(values);
+        input.return_values = Some("ALL_NEW".into());
+        self.db.update_item(input).await
+    }
+
+    async fn restart_attempts(
+        &self,
+        key: &str,
+        now: i64,
+        attempts: Attempts,
+    ) -> Result<(), RusotoError<PutItemError>> {
+        let mut item = attempts_key(key);
+        item.insert(
+            "count".to_owned(),
+            AttributeValue::from_number(attempts.count),
+        );
+        item.insert(
+            "expires_at".to_owned(),
+            AttributeValue::from_number(attempts.expires_at),
+        );
+        let mut values = HashMap::new();
+        values.insert(":now".to_owned(), AttributeValue::from_number(now));
+        let mut names = HashMap::new();
+        names.insert("#key".to_owned(), "key".to_owned());
+        let mut input = PutItemInput::default();
+        input.table_name = self.attempts_table_name.clone();
+        input.item = item;
+        input.condition_expression =
+            Some("attribute_not_exists(#key) OR expires_at <= :now".into());
+        input.expression_attribute_names = Some(names);
+        input.expression_attribute_values = Some(values);
+        self.db.put_item(input).await.map(|_| ())
+    }
+}
+
+fn count_name() -> HashMap<String, String> {
+    let mut names = HashMap::new();
+    names.insert("#count".to_owned(), "count".to_owned());
+    names
+}
+
+fn attempts_key(key: impl AsRef<str>) -> HashMap<String, AttributeValue> {
+    let mut map = HashMap::new();
+    map.insert(
+        "key".into(),
+        AttributeValue::from_string(key.as_ref().to_owned()),
+    );
+    map
+}
+
+fn to_attempts(item: &HashMap<String, AttributeValue>) -> Result<Attempts, ResponseError> {
+    Ok(Attempts {
+        count: item
+            .get_optional_number_att_value("count")?
+            .unwrap_or_default(),
+        expires_at: item
+            .get_optional_number_att_value("expires_at")?
+            .unwrap_or_default(),
+    })
+}
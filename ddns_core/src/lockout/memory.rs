@@ -0,0 +1,74 @@
+use super::{AttemptStore, Attempts};
+use crate::error::ResponseError;
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+// Clones share the same counts. Expired counts are dropped as they are read.
+#[derive(Clone, Default)]
+pub struct MemoryAttemptStore {
+    attempts: Arc<Mutex<HashMap<String, Attempts>>>,
+}
+
+impl MemoryAttemptStore {
+    pub fn new() -> Self {
+        MemoryAttemptStore::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Attempts>> {
+        self.attempts
+            .lock()
+            .expect("memory attempt store lock poisoned")
+    }
+}
+
+#[async_trait]
+impl AttemptStore for MemoryAttemptStore {
+    async fn get_attempts(&self, key: &str, now: i64) -> Result<Attempts, ResponseError> {
+        let mut attempts = self.lock();
+        match attempts.get(key) {
+            Some(a) if a.expires_at > now => Ok(*a),
+            Some(_) => {
+                attempts.remove(key);
+                Ok(Attempts::default())
+            }
+            None => Ok(Attempts::default()),
+        }
+    }
+
+    async fn add_attempt(&self, key: &str, now: i64, ttl: i64) -> Result<Attempts, ResponseError> {
+        let mut attempts = self.lock();
+        let entry = attempts.entry(key.to_owned()).or_default();
+        if entry.expires_at <= now {
+            entry.count = 0;
+        }
+        entry.count += 1;
+        entry.expires_at = now + ttl;
+        Ok(*entry)
+    }
+
+    async fn remove_attempt(&self, key: &str) -> Result<(), ResponseError> {
+        if let Some(entry) = self.lock().get_mut(key) {
+            entry.count = (entry.count - 1).max(0);
+        }
+        Ok(())
+    }
+
+    async fn clear(&self, key: &str) -> Result<(), ResponseError> {
+        self.lock().remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lockout::tests::check_store;
+
+    #[tokio::test]
+    async fn behaves_like_an_attempt_store() {
+        check_store(&MemoryAttemptStore::new()).await;
+    }
+}
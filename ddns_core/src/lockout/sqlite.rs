@@ -0,0 +1,172 @@
+use super::{AttemptStore, Attempts};
+use crate::{
+    error::ResponseError,
+    sqlite::{blocking, db_error},
+};
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+pub struct SqliteAttemptStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteAttemptStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ResponseError> {
+        let conn = Connection::open(path).map_err(db_error)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS login_attempts (
+                key TEXT PRIMARY KEY,
+                count INTEGER NOT NULL,
+                expires_at INTEGER NOT NULL
+            )",
+            params![],
+        )
+        .map_err(db_error)?;
+        Ok(SqliteAttemptStore {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+}
+
+#[async_trait]
+impl AttemptStore for SqliteAttemptStore {
+    async fn get_attempts(&self, key: &str, now: i64) -> Result<Attempts, ResponseError> {
+        let key = key.to_owned();
+        let attempts = blocking(&self.conn, move |conn| {
+            conn.query_row(
+                "SELECT count, expires_at FROM login_attempts WHERE key = ?1 AND expires_at > ?2",
+                params![key, now],
+                |row| {
+                    Ok(Attempts {
+                        count: row.get(0)?,
+                        expires_at: row.get(1)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(db_error)
+        })
+        .await?;
+        Ok(attempts.unwrap_or_default())
+    }
+
+    // Expired rows are restarted in place rather than deleted separately. The
+    // write and the read back share a transaction, so another connection to
+    // the same file cannot count an attempt in between.
+    async fn add_attempt(&self, key: &str, now: i64, ttl: i64) -> Result<Attempts, ResponseError> {
+        let key = key.to_owned();
+        blocking(&self.conn, move |conn| {
+            let tx = conn.unchecked_transaction().map_err(db_error)?;
+            tx.execute(
+                "INSERT INTO login_attempts (key, count, expires_at) VALUES (?1, 1, ?3)
+                ON CONFLICT (key) DO UPDATE SET
+                    count = CASE WHEN expires_at > ?2 THEN count + 1 ELSE 1 END,
+                    expires_at = ?3",
+                params![key, now, now + ttl],
+            )
+            .map_err(db_error)?;
+            let attempts = tx
+                .query_row(
+                    "SELECT count, expires_at FROM login_attempts WHERE key = ?1",
+                    params![key],
+                    |row| {
+                        Ok(Attempts {
+                            count: row.get(0)?,
+                            expires_at: row.get(1)?,
+                        })
+                    },
+                )
+                .map_err(db_error)?;
+            tx.commit().map_err(db_error)?;
+            Ok(attempts)
+        })
+        .await
+    }
+
+    async fn remove_attempt(&self, key: &str) -> Result<(), ResponseError> {
+        let key = key.to_owned();
+        blocking(&self.conn, move |conn| {
+            conn.execute(
+                "UPDATE login_attempts SET count = count - 1 WHERE key = ?1 AND count > 0",
+                params![key],
+            )
+            .map_err(db_error)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn clear(&self, key: &str) -> Result<(), ResponseError> {
+        let key = key.to_owned();
+        blocking(&self.conn, move |conn| {
+            conn.execute("DELETE FROM login_attempts WHERE key = ?1", params![key])
+                .map_err(db_error)
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lockout::tests::check_store;
+    use std::{env, fs, path::PathBuf, process};
+
+    // A database file for one test, left over from an earlier run or not.
+    fn temp_db(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("ddns-attempts-{}-{}.db", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn behaves_like_an_attempt_store() {
+        check_store(&SqliteAttemptStore::open(":memory:").unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn counts_survive_reopening() {
+        let path = temp_db("reopen");
+        let store = SqliteAttemptStore::open(&path).unwrap();
+        store.add_attempt("k", 100, 10).await.unwrap();
+        store.add_attempt("k", 101, 10).await.unwrap();
+        drop(store);
+        let store = SqliteAttemptStore::open(&path).unwrap();
+        assert_eq!(
+            store.get_attempts("k", 105).await.unwrap(),
+            Attempts {
+                count: 2,
+                expires_at: 111
+            }
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Two connections to one file stand in for two server processes.
+    #[tokio::test]
+    async fn parallel_attempts_each_get_their_own_count() {
+        let path = temp_db("parallel");
+        let stores = vec![
+            Arc::new(SqliteAttemptStore::open(&path).unwrap()),
+            Arc::new(SqliteAttemptStore::open(&path).unwrap()),
+        ];
+        let tasks: Vec<_> = (0..20)
+            .map(|i| {
+                let store = stores[i % 2].clone();
+                tokio::spawn(async move { store.add_attempt("k", 100, 10).await.unwrap().count })
+            })
+            .collect();
+        let mut counts = Vec::new();
+        for task in tasks {
+            counts.push(task.await.unwrap());
+        }
+        counts.sort_unstable();
+        assert_eq!(counts, (1..=20).collect::<Vec<i64>>());
+        fs::remove_file(&path).unwrap();
+    }
+}
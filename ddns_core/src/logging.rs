@@ -0,0 +1,8 @@
+use env_logger::{Builder, Env};
+
+// The library reports what it could not do, but did not fail the request
+// over, through the `log` macros. Each binary calls this first to send them
+// to stderr, which Lambda keeps, at `warn` unless `RUST_LOG` says otherwise.
+pub fn init() {
+    let _ = Builder::from_env(Env::default().default_filter_or("warn")).try_init();
+}
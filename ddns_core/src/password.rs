@@ -0,0 +1,423 @@
+use crate::error::ResponseError;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaChaRng;
+#[cfg(test)]
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+const SALT_LENGTH: usize = 64;
+const HASH_LENGTH: u32 = 32;
+
+// Argon2id cost parameters. `mem_cost` is in KiB. The defaults are the ones
+// passwords were always hashed with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HashParams {
+    pub mem_cost: u32,
+    pub time_cost: u32,
+    pub lanes: u32,
+}
+
+impl Default for HashParams {
+    fn default() -> Self {
+        HashParams {
+            mem_cost: 4096,
+            time_cost: 3,
+            lanes: 1,
+        }
+    }
+}
+
+// Cheap enough for tests to hash with freely. Never use it for real passwords.
+#[cfg(any(test, feature = "test-util"))]
+pub const TEST_PARAMS: HashParams = HashParams {
+    mem_cost: 8,
+    time_cost: 1,
+    lanes: 1,
+};
+
+#[cfg(any(test, feature = "test-util"))]
+pub fn test_hasher() -> Hasher {
+    Hasher::new(TEST_PARAMS)
+}
+
+impl HashParams {
+    pub fn check(&self) -> Result<(), String> {
+        if self.time_cost < 1 {
+            return Err("time_cost must be at least 1".into());
+        }
+        if self.lanes < 1 {
+            return Err("lanes must be at least 1".into());
+        }
+        if self.mem_cost < 8 * self.lanes {
+            return Err(format!("mem_cost must be at least {}", 8 * self.lanes));
+        }
+        Ok(())
+    }
+
+    fn config<'a>(&self, secret: &'a [u8]) -> argon2::Config<'a> {
+        let mut config = argon2::Config::default();
+        config.variant = argon2::Variant::Argon2id;
+        config.version = argon2::Version::Version13;
+        config.mem_cost = self.mem_cost;
+        config.time_cost = self.time_cost;
+        config.lanes = self.lanes;
+        config.hash_length = HASH_LENGTH;
+        config.secret = secret;
+        config
+    }
+
+    fn hash(&self, pass: impl AsRef<str>, secret: &[u8]) -> Result<String, ResponseError> {
+        let hash = argon2::hash_encoded(pass.as_ref().as_bytes(), &salt(), &self.config(secret))?;
+        Ok(hash)
+    }
+
+    // Reads the parameters back out of an encoded hash. Hashes that are not
+    // argon2id version 19 have none that are current.
+    pub fn from_encoded(encoded: &str) -> Option<HashParams> {
+        let parts: Vec<&str> = encoded.split('$').collect();
+        if parts.len() != 6 || parts[1] != "argon2id" || parts[2] != "v=19" {
+            return None;
+        }
+        let mut params = HashParams {
+            mem_cost: 0,
+            time_cost: 0,
+            lanes: 0,
+        };
+        for param in parts[3].split(',') {
+            let mut kv = param.splitn(2, '=');
+            match (kv.next(), kv.next().and_then(|v| v.parse().ok())) {
+                (Some("m"), Some(v)) => params.mem_cost = v,
+                (Some("t"), Some(v)) => params.time_cost = v,
+                (Some("p"), Some(v)) => params.lanes = v,
+                _ => return None,
+            }
+        }
+        Some(params)
+    }
+
+    // An encoded hash with these parameters that no password matches. Checking
+    // a password against it costs the same as checking a real hash, without
+    // having to hash anything to make it.
+    fn unmatchable(&self) -> String {
+        let mut rng = ChaChaRng::from_entropy();
+        let mut hash: [u8; HASH_LENGTH as usize] = [0; HASH_LENGTH as usize];
+        rng.fill(&mut hash);
+        format!(
+            "$argon2id$v=19$m={},t={},p={}${}${}",
+            self.mem_cost,
+            self.time_cost,
+            self.lanes,
+            base64::encode_config(&salt()[..], base64::STANDARD_NO_PAD),
+            base64::encode_config(&hash[..], base64::STANDARD_NO_PAD),
+        )
+    }
+}
+
+// Hashes new passwords with the current parameters and pepper, and checks
+// stored ones against the pepper they were made with. The pepper is passed to
+// argon2 as its secret, so it never ends up in the stored hash; only its id is
+// kept next to the hash. Retired peppers can still check passwords but are
+// never used for new hashes.
+#[derive(Clone, Default)]
+pub struct Hasher {
+    params: HashParams,
+    pepper_id: Option<String>,
+    peppers: HashMap<String, Vec<u8>>,
+    // How many passwords this hasher and its clones have checked.
+    #[cfg(test)]
+    pub(crate) verified: Arc<AtomicUsize>,
+}
+
+impl Hasher {
+    pub fn new(params: HashParams) -> Self {
+        Hasher {
+            params,
+            ..Hasher::default()
+        }
+    }
+
+    pub fn params(&self) -> &HashParams {
+        &self.params
+    }
+
+    pub fn pepper_id(&self) -> Option<&str> {
+        self.pepper_id.as_deref()
+    }
+
+    pub fn with_params(mut self, params: HashParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    pub fn with_pepper(mut self, id: impl AsRef<str>, secret: impl Into<Vec<u8>>) -> Self {
+        self.pepper_id = Some(id.as_ref().to_owned());
+        self.peppers.insert(id.as_ref().to_owned(), secret.into());
+        self
+    }
+
+    pub fn with_retired_pepper(mut self, id: impl AsRef<str>, secret: impl Into<Vec<u8>>) -> Self {
+        self.peppers.insert(id.as_ref().to_owned(), secret.into());
+        self
+    }
+
+    // Only the peppers; the parameters have their own check.
+    pub fn check(&self) -> Result<(), String> {
+        for (id, secret) in &self.peppers {
+            if id.is_empty() {
+                return Err("a pepper id is empty".into());
+            }
+            if secret.is_empty() {
+                return Err(format!("pepper {} is empty", id));
+            }
+        }
+        Ok(())
+    }
+
+    // The encoded hash and the id of the pepper it was made with.
+    pub fn hash(&self, pass: impl AsRef<str>) -> Result<(String, Option<String>), ResponseError> {
+        let hash = self.params.hash(pass, self.secret(self.pepper_id())?)?;
+        Ok((hash, self.pepper_id.clone()))
+    }
+
+    pub fn verify(
+        &self,
+        encoded: &str,
+        pepper_id: Option<&str>,
+        pass: impl AsRef<str>,
+    ) -> Result<bool, ResponseError> {
+        #[cfg(test)]
+        self.verified.fetch_add(1, Ordering::SeqCst);
+        let verify = argon2::verify_encoded_ext(
+            encoded,
+            pass.as_ref().as_bytes(),
+            self.secret(pepper_id)?,
+            &[],
+        )?;
+        Ok(verify)
+    }
+
+    // `hash` on tokio's blocking threads, for async callers.
+    pub async fn hash_async(
+        &self,
+        pass: impl AsRef<str>,
+    ) -> Result<(String, Option<String>), ResponseError> {
+        let pass = pass.as_ref().to_owned();
+        self.blocking(move |hasher| hasher.hash(pass)).await
+    }
+
+    // `verify` on tokio's blocking threads, for async callers.
+    pub async fn verify_async(
+        &self,
+        encoded: &str,
+        pepper_id: Option<&str>,
+        pass: impl AsRef<str>,
+    ) -> Result<bool, ResponseError> {
+        let encoded = encoded.to_owned();
+        let pepper_id = pepper_id.map(String::from);
+        let pass = pass.as_ref().to_owned();
+        self.blocking(move |hasher| hasher.verify(&encoded, pepper_id.as_deref(), pass))
+            .await
+    }
+
+    // A hash can be tuned to take half a second, which on an async worker
+    // would hold up every request scheduled on it, so hashing runs on tokio's
+    // blocking threads.
+    pub(crate) async fn blocking<T: Send + 'static>(
+        &self,
+        run: impl FnOnce(&Hasher) -> Result<T, ResponseError> + Send + 'static,
+    ) -> Result<T, ResponseError> {
+        let hasher = self.clone();
+        tokio::task::spawn_blocking(move || run(&hasher))
+            .await
+            .map_err(|e| ResponseError::Argon(format!("{}", e)))?
+    }
+
+    // True when a stored hash was made with the current parameters and pepper.
+    pub fn is_current(&self, encoded: &str, pepper_id: Option<&str>) -> bool {
+        pepper_id == self.pepper_id() && HashParams::from_encoded(encoded) == Some(self.params)
+    }
+
+    pub(crate) fn unmatchable(&self) -> String {
+        self.params.unmatchable()
+    }
+
+    // A hash made with a pepper that is no longer configured cannot be
+    // checked at all, which is a configuration error rather than a bad
+    // password.
+    fn secret(&self, pepper_id: Option<&str>) -> Result<&[u8], ResponseError> {
+        match pepper_id {
+            Some(id) => match self.peppers.get(id) {
+                Some(secret) => Ok(secret),
+                None => Err(ResponseError::Argon(format!("unknown pepper {}", id))),
+            },
+            None => Ok(&[]),
+        }
+    }
+}
+
+fn salt() -> [u8; SALT_LENGTH] {
+    let mut rng = ChaChaRng::from_entropy();
+    let mut salt: [u8; SALT_LENGTH] = [0; SALT_LENGTH];
+    rng.fill(&mut salt);
+    salt
+}
+
+// Raises `time_cost` at the given memory until one hash takes at least
+// `target` on this machine, and returns those parameters with how long a hash
+// took.
+pub fn calibrate(
+    target: Duration,
+    mem_cost: u32,
+    lanes: u32,
+) -> Result<(HashParams, Duration), ResponseError> {
+    let mut params = HashParams {
+        mem_cost,
+        time_cost: 1,
+        lanes,
+    };
+    params.check().map_err(ResponseError::Argon)?;
+    loop {
+        let start = Instant::now();
+        params.hash("calibration password", &[])?;
+        let took = start.elapsed();
+        if took >= target || params.time_cost >= 100 {
+            return Ok((params, took));
+        }
+        params.time_cost += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::Client,
+        config::ClientBuilder,
+        dns::MemoryDnsProvider,
+        lockout::MemoryAttemptStore,
+        store::{MemoryUserStore, PutMode, UserStore},
+        user::{test_user, User},
+        zone::Zone,
+    };
+
+    const OLD: HashParams = TEST_PARAMS;
+    const NEW: HashParams = HashParams {
+        mem_cost: 16,
+        time_cost: 2,
+        lanes: 1,
+    };
+
+    // alice, with her password hashed by `hasher`.
+    fn user(hasher: &Hasher) -> User {
+        let mut user = test_user();
+        user.set_password("secret", hasher).unwrap();
+        user
+    }
+
+    // A client made by `builder`, with alice's password hashed by `stored`.
+    async fn client(stored: &Hasher, builder: ClientBuilder) -> (Client, MemoryUserStore) {
+        let users = MemoryUserStore::new();
+        users.put_user(user(stored), PutMode::Create).await.unwrap();
+        let client = builder
+            .user_store(users.clone())
+            .dns_provider(MemoryDnsProvider::new(vec![Zone::new("example.com", "Z1")]))
+            .attempt_store(MemoryAttemptStore::new())
+            .build()
+            .unwrap();
+        (client, users)
+    }
+
+    async fn login(client: &Client) -> Result<User, ResponseError> {
+        let hostnames = vec!["host.example.com".to_owned()];
+        client
+            .validate_user("alice", "secret", "test/1.0", None, &hostnames)
+            .await
+    }
+
+    #[test]
+    fn params_are_read_back_from_hashes() {
+        let (hash, _) = Hasher::new(NEW).hash("secret").unwrap();
+        assert_eq!(HashParams::from_encoded(&hash), Some(NEW));
+        assert_eq!(HashParams::from_encoded(&OLD.unmatchable()), Some(OLD));
+        let argon2i = hash.replacen("argon2id", "argon2i", 1);
+        assert_eq!(HashParams::from_encoded(&argon2i), None);
+        assert_eq!(HashParams::from_encoded("$2b$12$bcrypt"), None);
+    }
+
+    #[test]
+    fn only_hashes_with_other_params_need_rehashing() {
+        let user = user(&Hasher::new(OLD));
+        assert!(!user.needs_rehash(&Hasher::new(OLD)));
+        assert!(user.needs_rehash(&Hasher::new(NEW)));
+        assert!(user.needs_rehash(&Hasher::new(OLD).with_pepper("p1", "pepper")));
+    }
+
+    #[tokio::test]
+    async fn login_rehashes_outdated_passwords() {
+        let (client, users) = client(&Hasher::new(OLD), Client::builder().hash_params(NEW)).await;
+        login(&client).await.unwrap();
+        let stored = users.get_user("alice").await.unwrap();
+        assert_eq!(HashParams::from_encoded(&stored.password), Some(NEW));
+
+        // The new hash is current, so the next login leaves it alone.
+        login(&client).await.unwrap();
+        let again = users.get_user("alice").await.unwrap();
+        assert_eq!(again.password, stored.password);
+    }
+
+    #[tokio::test]
+    async fn login_leaves_current_hashes_alone() {
+        let (client, users) = client(&Hasher::new(OLD), Client::builder().hash_params(OLD)).await;
+        let before = users.get_user("alice").await.unwrap();
+        login(&client).await.unwrap();
+        let after = users.get_user("alice").await.unwrap();
+        assert_eq!(after.password, before.password);
+    }
+
+    #[tokio::test]
+    async fn retired_peppers_still_log_in_and_are_rehashed() {
+        let stored = Hasher::new(OLD).with_pepper("p1", "first");
+        let builder = Client::builder()
+            .hash_params(OLD)
+            .pepper("p2", "second")
+            .retired_pepper("p1", "first");
+        let (client, users) = client(&stored, builder).await;
+        login(&client).await.unwrap();
+        let user = users.get_user("alice").await.unwrap();
+        assert_eq!(user.pepper_id.as_deref(), Some("p2"));
+        let current = Hasher::new(OLD).with_pepper("p2", "second");
+        assert!(current
+            .verify(&user.password, user.pepper_id.as_deref(), "secret")
+            .unwrap());
+        assert!(!user.needs_rehash(&current));
+    }
+
+    #[tokio::test]
+    async fn unknown_peppers_fail_without_a_panic() {
+        let stored = Hasher::new(OLD).with_pepper("gone", "secret pepper");
+        let hasher = Hasher::new(OLD).with_pepper("p2", "second");
+        let user = user(&stored);
+        match hasher.verify(&user.password, user.pepper_id.as_deref(), "secret") {
+            Err(ResponseError::Argon(e)) => assert!(e.contains("gone")),
+            other => panic!("expected an unknown pepper, got {:?}", other),
+        }
+
+        let builder = Client::builder().hash_params(OLD).pepper("p2", "second");
+        let (client, users) = client(&stored, builder).await;
+        let before = users.get_user("alice").await.unwrap();
+        match login(&client).await {
+            Err(ResponseError::Argon(_)) => {}
+            other => panic!("expected an unknown pepper, got {:?}", other.map(|_| ())),
+        }
+        // Nothing was rewritten.
+        let after = users.get_user("alice").await.unwrap();
+        assert_eq!(after.pepper_id.as_deref(), Some("gone"));
+        assert_eq!(after.password, before.password);
+    }
+}
@@ -0,0 +1,115 @@
+use http::header::HeaderMap;
+use lambda_http::{request::RequestContext, Request, RequestExt};
+use std::{env, net::IpAddr, str::FromStr};
+
+// Where a request came from, used to count failed logins per address and as
+// the update address when none is given.
+pub fn trusted_proxies() -> usize {
+    env::var("NIC_TRUSTED_PROXIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+// With trusted proxies in front of the API, the caller is the entry that the
+// outermost trusted proxy appended to X-Forwarded-For. Entries further left
+// are supplied by the caller and cannot be trusted.
+pub fn client_ip(
+    headers: &HeaderMap,
+    source_ip: Option<IpAddr>,
+    trusted_proxies: usize,
+) -> Option<IpAddr> {
+    if trusted_proxies == 0 {
+        return source_ip;
+    }
+    let chain: Vec<&str> = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .collect();
+    if chain.len() < trusted_proxies {
+        return None;
+    }
+    IpAddr::from_str(chain[chain.len() - trusted_proxies]).ok()
+}
+
+// The address API Gateway saw the request come from.
+pub fn context_source_ip(request: &Request) -> Option<IpAddr> {
+    match request.request_context() {
+        RequestContext::ApiGateway(ctx) => IpAddr::from_str(&ctx.identity.source_ip).ok(),
+        _ => None,
+    }
+}
+
+// What a Lambda handler should use as the caller's address.
+pub fn lambda_source_ip(request: &Request) -> Option<IpAddr> {
+    client_ip(
+        request.headers(),
+        context_source_ip(request),
+        trusted_proxies(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::header::HeaderValue;
+
+    const PEER: &str = "192.0.2.10";
+
+    fn forwarded(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("X-Forwarded-For", HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(IpAddr::from_str(ip).unwrap())
+    }
+
+    #[test]
+    fn untrusted_peers_cannot_forward() {
+        let headers = forwarded(&["203.0.113.9"]);
+        assert_eq!(client_ip(&headers, ip(PEER), 0), ip(PEER));
+        assert_eq!(client_ip(&HeaderMap::new(), None, 0), None);
+    }
+
+    #[test]
+    fn stops_at_the_first_untrusted_hop() {
+        // The caller sent the first entry, the one trusted proxy the second.
+        let headers = forwarded(&["198.51.100.1, 203.0.113.9"]);
+        assert_eq!(client_ip(&headers, ip(PEER), 1), ip("203.0.113.9"));
+        // Behind two, the last entry is the outer proxy's address.
+        let headers = forwarded(&["198.51.100.1", "203.0.113.9, 192.0.2.20"]);
+        assert_eq!(client_ip(&headers, ip(PEER), 2), ip("203.0.113.9"));
+        assert_eq!(client_ip(&headers, ip(PEER), 3), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn too_few_hops_have_no_address() {
+        let headers = forwarded(&["203.0.113.9"]);
+        assert_eq!(client_ip(&headers, ip(PEER), 2), None);
+        assert_eq!(client_ip(&HeaderMap::new(), ip(PEER), 1), None);
+    }
+
+    #[test]
+    fn malformed_entries() {
+        // Past the trusted hops, anything the caller wrote is ignored.
+        let headers = forwarded(&["not an address, 203.0.113.9"]);
+        assert_eq!(client_ip(&headers, ip(PEER), 1), ip("203.0.113.9"));
+        // Empty entries are skipped rather than counted as hops.
+        let headers = forwarded(&["203.0.113.9, , "]);
+        assert_eq!(client_ip(&headers, ip(PEER), 1), ip("203.0.113.9"));
+        // An entry that is not a bare address gives no address at all, rather
+        // than falling back to the proxy's.
+        for value in &["198.51.100.1, garbage", "203.0.113.9:443", "unknown"] {
+            let headers = forwarded(&[*value]);
+            assert_eq!(client_ip(&headers, ip(PEER), 1), None, "{}", value);
+        }
+    }
+}
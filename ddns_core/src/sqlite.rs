@@ -0,0 +1,18 @@
+use crate::error::ResponseError;
+use rusqlite::Connection;
+use std::sync::{Arc, Mutex};
+
+// rusqlite blocks, so statements run on tokio's blocking threads.
+pub(crate) async fn blocking<T: Send + 'static>(
+    conn: &Arc<Mutex<Connection>>,
+    run: impl FnOnce(&Connection) -> Result<T, ResponseError> + Send + 'static,
+) -> Result<T, ResponseError> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || run(&conn.lock().expect("sqlite lock poisoned")))
+        .await
+        .map_err(db_error)?
+}
+
+pub(crate) fn db_error(e: impl std::fmt::Display) -> ResponseError {
+    ResponseError::DbError(format!("{}", e))
+}
@@ -0,0 +1,285 @@
+use crate::{
+    error::{ResponseError, ResponseErrors},
+    password::Hasher,
+    token::Token,
+    user::{HostState, User},
+};
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+
+mod dynamodb;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use dynamodb::DynamoDbStore;
+pub(crate) use dynamodb::{AttributeValueExt, MapAttributeValueExt};
+pub use memory::MemoryUserStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteUserStore;
+
+// `Create` fails with `ResponseError::UserExists` instead of replacing a user
+// that is already stored, so concurrent sign-ups cannot clobber each other.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PutMode {
+    Create,
+    Overwrite,
+}
+
+// A page of users, in no particular order across stores: DynamoDB returns
+// them in scan order. `cursor` is set when more users may follow and is passed
+// back to fetch the next page.
+pub struct UserPage {
+    pub users: Vec<User>,
+    pub cursor: Option<String>,
+}
+
+// The attributes of a user to change. Only these are written, so an update
+// cannot undo a concurrent change to anything else on the user. Hosts and
+// tokens have their own methods and are never part of it.
+#[derive(Debug, Clone, Default)]
+pub struct UserUpdate {
+    pub add_domains: HashSet<String>,
+    pub remove_domains: HashSet<String>,
+    // The new hash and the id of the pepper it was made with.
+    pub password: Option<(String, Option<String>)>,
+    pub user_agents: Option<HashSet<String>>,
+    pub allow_private_addresses: Option<bool>,
+    pub updated_at: i64,
+}
+
+impl UserUpdate {
+    pub async fn set_password(
+        &mut self,
+        pass: impl AsRef<str>,
+        hasher: &Hasher,
+    ) -> Result<(), ResponseError> {
+        self.password = Some(hasher.hash_async(pass).await?);
+        Ok(())
+    }
+
+    // Stores check this against the user as it is when the update is
+    // written, so concurrent removals cannot leave a user without domains.
+    pub(crate) fn check(&self, user: &User) -> Result<(), ResponseError> {
+        let mut errs = ResponseErrors::default();
+        for domain in &self.remove_domains {
+            if !user.domains().contains(domain) {
+                errs.add(ResponseError::InvalidField(
+                    "remove_domains".into(),
+                    format!("{} is not one of the user's domains", domain),
+                ));
+            }
+        }
+        if !self.remove_domains.is_empty()
+            && self.add_domains.is_empty()
+            && user
+                .domains()
+                .iter()
+                .all(|d| self.remove_domains.contains(d))
+        {
+            errs.add(ResponseError::InvalidField(
+                "remove_domains".into(),
+                "would leave the user without domains".into(),
+            ));
+        }
+        Ok(errs.into_result(())?)
+    }
+}
+
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    async fn get_user(&self, username: &str) -> Result<User, ResponseError>;
+    async fn put_user(&self, user: User, mode: PutMode) -> Result<(), ResponseError>;
+    async fn delete_user(&self, username: &str) -> Result<(), ResponseError>;
+    // Applies `update` to an existing user and returns the user as stored
+    // afterwards. A missing user is `NotFound`, never recreated. An update
+    // that fails `UserUpdate::check` is refused without writing anything.
+    async fn update_user(&self, username: &str, update: UserUpdate) -> Result<User, ResponseError>;
+    // Merges `hosts` into the user's host states and drops the `stale` ones
+    // without touching anything else on the user, so it cannot undo a
+    // concurrent admin change.
+    async fn record_hosts(
+        &self,
+        username: &str,
+        hosts: HashMap<String, HostState>,
+        stale: Vec<String>,
+    ) -> Result<(), ResponseError>;
+    // Swaps the password hash and its pepper id only while the hash is still
+    // `current`, so a rehash cannot undo a password change made in the
+    // meantime. Returns whether it was swapped.
+    async fn replace_password(
+        &self,
+        username: &str,
+        current: &str,
+        new: &str,
+        pepper_id: Option<&str>,
+    ) -> Result<bool, ResponseError>;
+    // Like `record_hosts`, these only touch the one token. `put_token` refuses
+    // a user's token past `MAX_TOKENS` in the same write.
+    async fn put_token(&self, username: &str, id: &str, token: Token) -> Result<(), ResponseError>;
+    async fn delete_token(&self, username: &str, id: &str) -> Result<(), ResponseError>;
+    async fn record_token_use(
+        &self,
+        username: &str,
+        id: &str,
+        at: i64,
+    ) -> Result<(), ResponseError>;
+    async fn list_users(
+        &self,
+        limit: usize,
+        cursor: Option<String>,
+    ) -> Result<UserPage, ResponseError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{token::MAX_TOKENS, user::test_user};
+
+    fn user(username: &str) -> User {
+        User {
+            username: username.to_owned(),
+            ..test_user()
+        }
+    }
+
+    fn state(last_ip: &str) -> HostState {
+        HostState {
+            last_ip: last_ip.into(),
+            last_update_at: 1,
+            last_user_agent: "test/1.0".into(),
+            last_result: format!("good {}", last_ip),
+            ttl: 300,
+            checked_at: 1,
+        }
+    }
+
+    // What the stores that keep whole users have to do, checked against each
+    // of them. They also page in username order, which DynamoDB does not.
+    pub(crate) async fn check_store(store: &dyn UserStore) {
+        // Creating refuses to replace a user, overwriting does not.
+        store
+            .put_user(user("alice"), PutMode::Create)
+            .await
+            .unwrap();
+        let created = store.get_user("alice").await.unwrap().password;
+        match store.put_user(user("alice"), PutMode::Create).await {
+            Err(ResponseError::UserExists) => {}
+            other => panic!("expected UserExists, got {:?}", other),
+        }
+        assert_eq!(store.get_user("alice").await.unwrap().password, created);
+        let replacement = user("alice");
+        let replaced = replacement.password.clone();
+        store
+            .put_user(replacement, PutMode::Overwrite)
+            .await
+            .unwrap();
+        assert_eq!(store.get_user("alice").await.unwrap().password, replaced);
+
+        // Updates change only what they name.
+        let mut update = UserUpdate::default();
+        update.add_domains.insert("other.example.com".to_owned());
+        update.user_agents = Some(vec!["router/1.0".to_owned()].into_iter().collect());
+        update.updated_at = 10;
+        let updated = store.update_user("alice", update).await.unwrap();
+        assert_eq!(updated.domains().len(), 2);
+        assert!(updated.user_agents().contains("router/1.0"));
+        assert_eq!(updated.updated_at, 10);
+        assert_eq!(updated.password, replaced);
+        let stored = store.get_user("alice").await.unwrap();
+        assert_eq!(stored.domains(), updated.domains());
+
+        // An update checked against the stored user is refused whole.
+        let mut update = UserUpdate::default();
+        update.remove_domains = stored.domains().clone();
+        match store.update_user("alice", update).await.map(|_| ()) {
+            Err(ResponseError::InvalidField(field, _)) => assert_eq!(field, "remove_domains"),
+            other => panic!("expected remove_domains to be refused, got {:?}", other),
+        }
+        assert_eq!(store.get_user("alice").await.unwrap().domains().len(), 2);
+        match store
+            .update_user("bob", UserUpdate::default())
+            .await
+            .map(|_| ())
+        {
+            Err(ResponseError::NotFound(_)) => {}
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+
+        // Host states are merged, and stale ones dropped.
+        let mut hosts = HashMap::new();
+        hosts.insert("host.example.com".to_owned(), state("93.184.216.34"));
+        store
+            .record_hosts("alice", hosts, Vec::new())
+            .await
+            .unwrap();
+        let mut hosts = HashMap::new();
+        hosts.insert("other.example.com".to_owned(), state("93.184.216.35"));
+        store
+            .record_hosts("alice", hosts, vec!["host.example.com".to_owned()])
+            .await
+            .unwrap();
+        let stored = store.get_user("alice").await.unwrap();
+        assert_eq!(stored.hosts().len(), 1);
+        assert_eq!(stored.hosts()["other.example.com"].last_ip, "93.184.216.35");
+
+        // A password is only replaced while it is still the expected hash.
+        assert!(!store
+            .replace_password("alice", "stale", "new", None)
+            .await
+            .unwrap());
+        assert!(store
+            .replace_password("alice", &replaced, "new", Some("p1"))
+            .await
+            .unwrap());
+        let stored = store.get_user("alice").await.unwrap();
+        assert_eq!(stored.password, "new");
+        assert_eq!(stored.pepper_id.as_deref(), Some("p1"));
+        assert_eq!(stored.hosts().len(), 1);
+
+        // Tokens are capped as they are written.
+        let hostnames = stored.domains().clone();
+        let mut ids = Vec::new();
+        for _ in 0..MAX_TOKENS {
+            let (id, _, token) = Token::generate(hostnames.clone(), 1);
+            store.put_token("alice", &id, token).await.unwrap();
+            ids.push(id);
+        }
+        let (id, _, token) = Token::generate(hostnames, 1);
+        match store.put_token("alice", &id, token).await {
+            Err(ResponseError::InvalidField(field, _)) => assert_eq!(field, "tokens"),
+            other => panic!("expected the cap, got {:?}", other),
+        }
+        store.record_token_use("alice", &ids[0], 5).await.unwrap();
+        let stored = store.get_user("alice").await.unwrap();
+        assert_eq!(stored.tokens().len(), MAX_TOKENS);
+        assert_eq!(stored.tokens()[&ids[0]].last_used_at, Some(5));
+        store.delete_token("alice", &ids[0]).await.unwrap();
+        match store.delete_token("alice", &ids[0]).await {
+            Err(ResponseError::NotFound(_)) => {}
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+
+        // Pages follow on from their cursor.
+        for username in &["dave", "bob", "carol"] {
+            store
+                .put_user(user(username), PutMode::Create)
+                .await
+                .unwrap();
+        }
+        let page = store.list_users(3, None).await.unwrap();
+        let names: Vec<&str> = page.users.iter().map(|u| u.username()).collect();
+        assert_eq!(names, vec!["alice", "bob", "carol"]);
+        assert_eq!(page.cursor.as_deref(), Some("carol"));
+        let page = store.list_users(3, page.cursor).await.unwrap();
+        let names: Vec<&str> = page.users.iter().map(|u| u.username()).collect();
+        assert_eq!(names, vec!["dave"]);
+        assert_eq!(page.cursor, None);
+
+        store.delete_user("dave").await.unwrap();
+        match store.delete_user("dave").await {
+            Err(ResponseError::NotFound(_)) => {}
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+    }
+}
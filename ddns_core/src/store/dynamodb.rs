@@ -0,0 +1,922 @@
+use super::{PutMode, UserPage, UserStore, UserUpdate};
+use crate::{
+    error::ResponseError,
+    token::{too_many_tokens, Token, MAX_TOKENS},
+    user::{HostState, User},
+};
+use async_trait::async_trait;
+use rusoto_core::{Region, RusotoError};
+use rusoto_dynamodb::{
+    AttributeValue, DeleteItemError, DeleteItemInput, DynamoDb, DynamoDbClient, GetItemInput,
+    PutItemError, PutItemInput, ScanInput, UpdateItemError, UpdateItemInput,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::{TryFrom, TryInto},
+};
+
+// How many times an update that removes domains is retried when the user's
+// domains changed after they were read.
+const UPDATE_ATTEMPTS: usize = 3;
+
+pub struct DynamoDbStore {
+    db: DynamoDbClient,
+    users_table_name: String,
+}
+
+impl DynamoDbStore {
+    pub fn new(region: Region, users_table_name: impl AsRef<str>) -> Self {
+        DynamoDbStore {
+            db: DynamoDbClient::new(region),
+            users_table_name: users_table_name.as_ref().to_owned(),
+        }
+    }
+
+    pub fn with_client(db: DynamoDbClient, users_table_name: impl AsRef<str>) -> Self {
+        DynamoDbStore {
+            db,
+            users_table_name: users_table_name.as_ref().to_owned(),
+        }
+    }
+}
+
+impl DynamoDbStore {
+    // Sets each entry inside the existing map attribute `attr` and removes the
+    // `removes` keys. A user without the map yet has nothing to set into, so
+    // the whole map is written instead, and if another request created it in
+    // the meantime the first form is tried again. With `max_entries`, a map
+    // that already holds that many fails the condition as well.
+    async fn merge_into_map(
+        &self,
+        username: &str,
+        attr: &str,
+        entries: HashMap<String, AttributeValue>,
+        removes: Vec<String>,
+        max_entries: Option<usize>,
+    ) -> Result<(), ResponseError> {
+        for _ in 0..2 {
+            match self
+                .set_in_map(username, attr, &entries, &removes, max_entries)
+                .await
+            {
+                Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {}
+                res => return res.map_err(|e| ResponseError::DbError(format!("{}", e))),
+            }
+            match self.create_map(username, attr, &entries).await {
+                Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {}
+                res => return res.map_err(|e| ResponseError::DbError(format!("{}", e))),
+            }
+        }
+        Err(ResponseError::NotFound(format!("{} user", username)))
+    }
+
+    async fn set_in_map(
+        &self,
+        username: &str,
+        attr: &str,
+        entries: &HashMap<String, AttributeValue>,
+        removes: &[String],
+        max_entries: Option<usize>,
+    ) -> Result<(), RusotoError<UpdateItemError>> {
+        let mut names = HashMap::new();
+        let mut values = HashMap::new();
+        let mut sets = Vec::new();
+        names.insert("#map".to_owned(), attr.to_owned());
+        for (i, (key, value)) in entries.iter().enumerate() {
+            names.insert(format!("#k{}", i), key.to_owned());
+            values.insert(format!(":v{}", i), value.clone());
+            sets.push(format!("#map.#k{} = :v{}", i, i));
+        }
+        let mut update = format!("SET {}", sets.join(", "));
+        if !removes.is_empty() {
+            let mut paths = Vec::new();
+            for (i, key) in removes.iter().enumerate() {
+                names.insert(format!("#r{}", i), key.to_owned());
+                paths.push(format!("#map.#r{}", i));
+            }
+            update.push_str(&format!(" REMOVE {}", paths.join(", ")));
+        }
+        let mut input = UpdateItemInput::default();
+        input.table_name = self.users_table_name.clone();
+        input.key = user_key(username);
+        input.update_expression = Some(update);
+        input.condition_expression = match max_entries {
+            Some(max) => {
+                values.insert(":max".to_owned(), AttributeValue::from_number(max as i64));
+                Some("attribute_exists(#map) AND size(#map) < :max".into())
+            }
+            None => Some("attribute_exists(#map)".into()),
+        };
+        input.expression_attribute_names = Some(names);
+        input.expression_attribute_values = Some(values);
+        self.db.update_item(input).await.map(|_| ())
+    }
+
+    async fn create_map(
+        &self,
+        username: &str,
+        attr: &str,
+        entries: &HashMap<String, AttributeValue>,
+    ) -> Result<(), RusotoError<UpdateItemError>> {
+        let mut names = HashMap::new();
+        names.insert("#map".to_owned(), attr.to_owned());
+        let mut map = AttributeValue::default();
+        map.m = Some(entries.clone());
+        let mut values = HashMap::new();
+        values.insert(":map".to_owned(), map);
+        let mut input = UpdateItemInput::default();
+        input.table_name = self.users_table_name.clone();
+        input.key = user_key(username);
+        input.update_expression = Some("SET #map = :map".into());
+        input.condition_expression =
+            Some("attribute_exists(username) AND attribute_not_exists(#map)".into());
+        input.expression_attribute_names = Some(names);
+        input.expression_attribute_values = Some(values);
+        self.db.update_item(input).await.map(|_| ())
+    }
+
+    // Runs an update against an existing user and returns the user as stored
+    // afterwards.
+    async fn update_attributes(
+        &self,
+        username: &str,
+        update: String,
+        names: HashMap<String, String>,
+        values: HashMap<String, AttributeValue>,
+    ) -> Result<User, ResponseError> {
+        match self
+            .update_attributes_if(
+                username,
+                update,
+                "attribute_exists(username)",
+                names,
+                values,
+            )
+            .await?
+        {
+            Some(user) => Ok(user),
+            None => Err(ResponseError::NotFound(format!("{} user", username))),
+        }
+    }
+
+    // Like `update_attributes`, but gives `None` when `condition` fails.
+    async fn update_attributes_if(
+        &self,
+        username: &str,
+        update: String,
+        condition: &str,
+        names: HashMap<String, String>,
+        values: HashMap<String, AttributeValue>,
+    ) -> Result<Option<User>, ResponseError> {
+        let mut input = UpdateItemInput::default();
+        input.table_name = self.users_table_name.clone();
+        input.key = user_key(username);
+        input.update_expression = Some(update);
+        input.condition_expression = Some(condition.to_owned());
+        if !names.is_empty() {
+            input.expression_attribute_names = Some(names);
+        }
+        if !values.is_empty() {
+            input.expression_attribute_values = Some(values);
+        }
+        input.return_values = Some("ALL_NEW".into());
+        match self.db.update_item(input).await {
+            Ok(resp) => match resp.attributes {
+                Some(item) => item.try_into().map(Some),
+                None => Err(ResponseError::DbError("update returned no user".into())),
+            },
+            Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(None),
+            Err(e) => Err(ResponseError::DbError(format!("{}", e))),
+        }
+    }
+
+    // Runs an update against one existing token. A missing token fails the
+    // condition.
+    async fn update_token(
+        &self,
+        username: &str,
+        id: &str,
+        update: &str,
+        values: Option<HashMap<String, AttributeValue>>,
+    ) -> Result<(), ResponseError> {
+        let mut names = HashMap::new();
+        names.insert("#tokens".to_owned(), "tokens".to_owned());
+        names.insert("#id".to_owned(), id.to_owned());
+        let mut input = UpdateItemInput::default();
+        input.table_name = self.users_table_name.clone();
+        input.key = user_key(username);
+        input.update_expression = Some(update.into());
+        input.condition_expression = Some("attribute_exists(#tokens.#id)".into());
+        input.expression_attribute_names = Some(names);
+        input.expression_attribute_values = values;
+        match self.db.update_item(input).await {
+            Ok(_) => Ok(()),
+            Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {
+                Err(ResponseError::NotFound(format!("{} token", id)))
+            }
+            Err(e) => Err(ResponseError::DbError(format!("{}", e))),
+        }
+    }
+}
+
+#[async_trait]
+impl UserStore for DynamoDbStore {
+    async fn get_user(&self, username: &str) -> Result<User, ResponseError> {
+        let mut input = GetItemInput::default();
+        input.table_name = self.users_table_name.clone();
+        input.key.insert(
+            "username".into(),
+            AttributeValue::from_string(username.to_owned()),
+        );
+        match self.db.get_item(input).await {
+            Ok(resp) => match resp.item {
+                Some(item) => item.try_into(),
+                None => Err(ResponseError::NotFound(format!("{} user", username))),
+            },
+            Err(e) => Err(ResponseError::DbError(format!("{}", e))),
+        }
+    }
+
+    async fn put_user(&self, user: User, mode: PutMode) -> Result<(), ResponseError> {
+        let mut input = PutItemInput::default();
+        input.item = user.into();
+        input.table_name = self.users_table_name.clone();
+        if mode == PutMode::Create {
+            input.condition_expression = Some("attribute_not_exists(username)".into());
+        }
+        match self.db.put_item(input).await {
+            Ok(_) => Ok(()),
+            Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => {
+                Err(ResponseError::UserExists)
+            }
+            Err(e) => Err(ResponseError::DbError(format!("{}", e))),
+        }
+    }
+
+    async fn delete_user(&self, username: &str) -> Result<(), ResponseError> {
+        let mut input = DeleteItemInput::default();
+        input.table_name = self.users_table_name.clone();
+        input.key = user_key(username);
+        input.condition_expression = Some("attribute_exists(username)".into());
+        match self.db.delete_item(input).await {
+            Ok(_) => Ok(()),
+            Err(RusotoError::Service(DeleteItemError::ConditionalCheckFailed(_))) => {
+                Err(ResponseError::NotFound(format!("{} user", username)))
+            }
+            Err(e) => Err(ResponseError::DbError(format!("{}", e))),
+        }
+    }
+
+    async fn update_user(&self, username: &str, update: UserUpdate) -> Result<User, ResponseError> {
+        if update.remove_domains.is_empty() {
+            let (mut expression, names, mut values) = update_expression(&update, None, Vec::new());
+            if !update.add_domains.is_empty() {
+                values.insert(
+                    ":add_domains".to_owned(),
+                    AttributeValue::from_string_set(update.add_domains),
+                );
+                expression.push_str(" ADD domains :add_domains");
+            }
+            return self
+                .update_attributes(username, expression, names, values)
+                .await;
+        }
+
+        // Removals are checked against the domains as read, and only written
+        // while they are still those domains. One update cannot both add to
+        // and delete from a set, so the new set replaces the old one.
+        for _ in 0..UPDATE_ATTEMPTS {
+            let user = self.get_user(username).await?;
+            update.check(&user)?;
+            let mut domains = user.domains().clone();
+            domains.retain(|d| !update.remove_domains.contains(d));
+            domains.extend(update.add_domains.iter().cloned());
+            // Their TTLs are dropped too, naming only the ones that exist, as
+            // removing from a missing map fails.
+            let mut removes = Vec::new();
+            let mut ttl_names = HashMap::new();
+            let ttls = update
+                .remove_domains
+                .iter()
+                .filter(|d| user.domain_ttls.contains_key(*d));
+            for (i, domain) in ttls.enumerate() {
+                ttl_names.insert(format!("#d{}", i), domain.to_owned());
+                removes.push(format!("#ttls.#d{}", i));
+            }
+            if !ttl_names.is_empty() {
+                ttl_names.insert("#ttls".to_owned(), "domain_ttls".to_owned());
+            }
+            let (expression, mut names, mut values) =
+                update_expression(&update, Some(domains), removes);
+            names.extend(ttl_names);
+            values.insert(
+                ":old_domains".to_owned(),
+                AttributeValue::from_string_set(user.domains().clone()),
+            );
+            let condition = "attribute_exists(username) AND domains = :old_domains";
+            if let Some(user) = self
+                .update_attributes_if(username, expression, condition, names, values)
+                .await?
+            {
+                return Ok(user);
+            }
+        }
+        Err(ResponseError::DbError(format!(
+            "{} domains kept changing",
+            username
+        )))
+    }
+
+    async fn record_hosts(
+        &self,
+        username: &str,
+        hosts: HashMap<String, HostState>,
+        stale: Vec<String>,
+    ) -> Result<(), ResponseError> {
+        let entries = hosts
+            .into_iter()
+            .map(|(host, state)| (host, AttributeValue::from(state)))
+            .collect();
+        self.merge_into_map(username, "hosts", entries, stale, None)
+            .await
+    }
+
+    async fn replace_password(
+        &self,
+        username: &str,
+        current: &str,
+        new: &str,
+        pepper_id: Option<&str>,
+    ) -> Result<bool, ResponseError> {
+        let mut values = HashMap::new();
+        values.insert(
+            ":current".to_owned(),
+            AttributeValue::from_string(current.to_owned()),
+        );
+        values.insert(
+            ":new".to_owned(),
+            AttributeValue::from_string(new.to_owned()),
+        );
+        let update = match pepper_id {
+            Some(id) => {
+                values.insert(
+                    ":pepper".to_owned(),
+                    AttributeValue::from_string(id.to_owned()),
+                );
+                "SET #password = :new, pepper_id = :pepper"
+            }
+            None => "SET #password = :new REMOVE pepper_id",
+        };
+        // `password` is a reserved word in DynamoDB expressions.
+        let mut names = HashMap::new();
+        names.insert("#password".to_owned(), "password".to_owned());
+        let mut input = UpdateItemInput::default();
+        input.table_name = self.users_table_name.clone();
+        input.key = user_key(username);
+        input.update_expression = Some(update.into());
+        input.condition_expression = Some("#password = :current".into());
+        input.expression_attribute_names = Some(names);
+        input.expression_attribute_values = Some(values);
+        match self.db.update_item(input).await {
+            Ok(_) => Ok(true),
+            Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(false),
+            Err(e) => Err(ResponseError::DbError(format!("{}", e))),
+        }
+    }
+
+    async fn put_token(&self, username: &str, id: &str, token: Token) -> Result<(), ResponseError> {
+        let mut entries = HashMap::new();
+        entries.insert(id.to_owned(), AttributeValue::from(token));
+        match self
+            .merge_into_map(username, "tokens", entries, Vec::new(), Some(MAX_TOKENS))
+            .await
+        {
+            // Either the user is gone or their tokens are full.
+            Err(ResponseError::NotFound(_)) => {
+                self.get_user(username).await?;
+                Err(too_many_tokens())
+            }
+            res => res,
+        }
+    }
+
+    async fn delete_token(&self, username: &str, id: &str) -> Result<(), ResponseError> {
+        self.update_token(username, id, "REMOVE #tokens.#id", None)
+            .await
+    }
+
+    async fn record_token_use(
+        &self,
+        username: &str,
+        id: &str,
+        at: i64,
+    ) -> Result<(), ResponseError> {
+        let mut values = HashMap::new();
+        values.insert(":at".to_owned(), AttributeValue::from_number(at));
+        self.update_token(
+            username,
+            id,
+            "SET #tokens.#id.last_used_at = :at",
+            Some(values),
+        )
+        .await
+    }
+
+    // A scan is not ordered by username, but the cursor is still the last key
+    // DynamoDB evaluated so pages never overlap.
+    async fn list_users(
+        &self,
+        limit: usize,
+        cursor: Option<String>,
+    ) -> Result<UserPage, ResponseError> {
+        let mut input = ScanInput::default();
+        input.table_name = self.users_table_name.clone();
+        input.limit = Some(limit as i64);
+        input.exclusive_start_key = cursor.map(user_key);
+        match self.db.scan(input).await {
+            Ok(resp) => {
+                let mut users = Vec::new();
+                for item in resp.items.unwrap_or_default() {
+                    users.push(item.try_into()?);
+                }
+                let cursor = match resp.last_evaluated_key {
+                    Some(key) => Some(key.get_string_att_value("username")?),
+                    None => None,
+                };
+                Ok(UserPage { users, cursor })
+            }
+            Err(e) => Err(ResponseError::DbError(format!("{}", e))),
+        }
+    }
+}
+
+// The `SET` and `REMOVE` clauses for everything in `update` but the domains,
+// which are only set when `domains` replaces them. `removes` are added to the
+// `REMOVE` clause.
+fn update_expression(
+    update: &UserUpdate,
+    domains: Option<HashSet<String>>,
+    mut removes: Vec<String>,
+) -> (
+    String,
+    HashMap<String, String>,
+    HashMap<String, AttributeValue>,
+) {
+    let mut names = HashMap::new();
+    let mut values = HashMap::new();
+    let mut sets = vec!["updated_at = :updated_at".to_owned()];
+    values.insert(
+        ":updated_at".to_owned(),
+        AttributeValue::from_number(update.updated_at),
+    );
+    if let Some(domains) = domains {
+        values.insert(
+            ":domains".to_owned(),
+            AttributeValue::from_string_set(domains),
+        );
+        sets.push("domains = :domains".to_owned());
+    }
+    if let Some((password, pepper_id)) = &update.password {
+        // `password` is a reserved word in DynamoDB expressions.
+        names.insert("#password".to_owned(), "password".to_owned());
+        values.insert(
+            ":password".to_owned(),
+            AttributeValue::from_string(password.to_owned()),
+        );
+        sets.push("#password = :password".to_owned());
+        match pepper_id {
+            Some(id) => {
+                values.insert(
+                    ":pepper_id".to_owned(),
+                    AttributeValue::from_string(id.to_owned()),
+                );
+                sets.push("pepper_id = :pepper_id".to_owned());
+            }
+            None => removes.push("pepper_id".to_owned()),
+        }
+    }
+    match &update.user_agents {
+        // DynamoDB has no empty sets.
+        Some(agents) if agents.is_empty() => removes.push("user_agents".to_owned()),
+        Some(agents) => {
+            values.insert(
+                ":user_agents".to_owned(),
+                AttributeValue::from_string_set(agents.clone()),
+            );
+            sets.push("user_agents = :user_agents".to_owned());
+        }
+        None => {}
+    }
+    match update.allow_private_addresses {
+        Some(true) => {
+            let mut allow = AttributeValue::default();
+            allow.bool = Some(true);
+            values.insert(":allow".to_owned(), allow);
+            sets.push("allow_private_addresses = :allow".to_owned());
+        }
+        Some(false) => removes.push("allow_private_addresses".to_owned()),
+        None => {}
+    }
+    let mut expression = format!("SET {}", sets.join(", "));
+    if !removes.is_empty() {
+        expression.push_str(&format!(" REMOVE {}", removes.join(", ")));
+    }
+    (expression, names, values)
+}
+
+fn user_key(username: impl AsRef<str>) -> HashMap<String, AttributeValue> {
+    let mut key = HashMap::new();
+    key.insert(
+        "username".into(),
+        AttributeValue::from_string(username.as_ref().to_owned()),
+    );
+    key
+}
+
+impl TryFrom<HashMap<String, AttributeValue>> for User {
+    type Error = ResponseError;
+
+    fn try_from(value: HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
+        Ok(User {
+            username: value.get_string_att_value("username")?,
+            password: value.get_string_att_value("password")?,
+            pepper_id: value.get_optional_string_att_value("pepper_id")?,
+            domains: value.get_string_set_att_value("domains")?,
+            ttl: value.get_optional_number_att_value("record_ttl")?,
+            domain_ttls: value.get_number_map_att_value("domain_ttls")?,
+            created_at: value
+                .get_optional_number_att_value("created_at")?
+                .unwrap_or_default(),
+            updated_at: value
+                .get_optional_number_att_value("updated_at")?
+                .unwrap_or_default(),
+            hosts: match value.get("hosts") {
+                Some(att) => match &att.m {
+                    Some(m) => {
+                        let mut hosts = HashMap::new();
+                        for (host, state) in m {
+                            hosts.insert(host.to_owned(), HostState::try_from(state)?);
+                        }
+                        hosts
+                    }
+                    None => return Err(ResponseError::DbError("hosts is not of type map".into())),
+                },
+                None => HashMap::new(),
+            },
+            user_agents: match value.get("user_agents") {
+                Some(att) => att.get_string_set()?,
+                None => HashSet::new(),
+            },
+            tokens: match value.get("tokens") {
+                Some(att) => match &att.m {
+                    Some(m) => {
+                        let mut tokens = HashMap::new();
+                        for (id, token) in m {
+                            tokens.insert(id.to_owned(), Token::try_from(token)?);
+                        }
+                        tokens
+                    }
+                    None => return Err(ResponseError::DbError("tokens is not of type map".into())),
+                },
+                None => HashMap::new(),
+            },
+            allow_private_addresses: match value.get("allow_private_addresses") {
+                Some(att) => match att.bool {
+                    Some(allow) => allow,
+                    None => {
+                        return Err(ResponseError::DbError(
+                            "allow_private_addresses is not of type bool".into(),
+                        ))
+                    }
+                },
+                None => false,
+            },
+        })
+    }
+}
+
+impl TryFrom<&AttributeValue> for HostState {
+    type Error = ResponseError;
+
+    fn try_from(value: &AttributeValue) -> Result<Self, Self::Error> {
+        match &value.m {
+            Some(m) => Ok(HostState {
+                last_ip: m.get_string_att_value("last_ip")?,
+                last_update_at: m
+                    .get_optional_number_att_value("last_update_at")?
+                    .unwrap_or_default(),
+                last_user_agent: m.get_string_att_value("last_user_agent")?,
+                last_result: m.get_string_att_value("last_result")?,
+                ttl: m.get_optional_number_att_value("ttl")?.unwrap_or_default(),
+                checked_at: m
+                    .get_optional_number_att_value("checked_at")?
+                    .unwrap_or_default(),
+            }),
+            None => Err(ResponseError::DbError("not of type map".into())),
+        }
+    }
+}
+
+impl From<HostState> for AttributeValue {
+    fn from(state: HostState) -> Self {
+        let mut m = HashMap::new();
+        m.insert(
+            "last_ip".to_owned(),
+            AttributeValue::from_string(state.last_ip),
+        );
+        m.insert(
+            "last_update_at".to_owned(),
+            AttributeValue::from_number(state.last_update_at),
+        );
+        m.insert(
+            "last_user_agent".to_owned(),
+            AttributeValue::from_string(state.last_user_agent),
+        );
+        m.insert(
+            "last_result".to_owned(),
+            AttributeValue::from_string(state.last_result),
+        );
+        m.insert("ttl".to_owned(), AttributeValue::from_number(state.ttl));
+        m.insert(
+            "checked_at".to_owned(),
+            AttributeValue::from_number(state.checked_at),
+        );
+        let mut att = AttributeValue::default();
+        att.m = Some(m);
+        att
+    }
+}
+
+impl TryFrom<&AttributeValue> for Token {
+    type Error = ResponseError;
+
+    fn try_from(value: &AttributeValue) -> Result<Self, Self::Error> {
+        match &value.m {
+            Some(m) => Ok(Token {
+                hash: m.get_string_att_value("hash")?,
+                hostnames: m.get_string_set_att_value("hostnames")?,
+                created_at: m
+                    .get_optional_number_att_value("created_at")?
+                    .unwrap_or_default(),
+                last_used_at: m.get_optional_number_att_value("last_used_at")?,
+            }),
+            None => Err(ResponseError::DbError("not of type map".into())),
+        }
+    }
+}
+
+impl From<Token> for AttributeValue {
+    fn from(token: Token) -> Self {
+        let mut m = HashMap::new();
+        m.insert("hash".to_owned(), AttributeValue::from_string(token.hash));
+        m.insert(
+            "hostnames".to_owned(),
+            AttributeValue::from_string_set(token.hostnames),
+        );
+        m.insert(
+            "created_at".to_owned(),
+            AttributeValue::from_number(token.created_at),
+        );
+        if let Some(at) = token.last_used_at {
+            m.insert("last_used_at".to_owned(), AttributeValue::from_number(at));
+        }
+        let mut att = AttributeValue::default();
+        att.m = Some(m);
+        att
+    }
+}
+
+fn hosts_att(hosts: HashMap<String, HostState>) -> AttributeValue {
+    let mut att = AttributeValue::default();
+    att.m = Some(
+        hosts
+            .into_iter()
+            .map(|(host, state)| (host, AttributeValue::from(state)))
+            .collect(),
+    );
+    att
+}
+
+impl Into<HashMap<String, AttributeValue>> for User {
+    fn into(self) -> HashMap<String, AttributeValue> {
+        let mut map = HashMap::new();
+        map.insert(
+            "username".to_owned(),
+            AttributeValue::from_string(self.username),
+        );
+        map.insert(
+            "password".to_owned(),
+            AttributeValue::from_string(self.password),
+        );
+        if let Some(pepper_id) = self.pepper_id {
+            map.insert(
+                "pepper_id".to_owned(),
+                AttributeValue::from_string(pepper_id),
+            );
+        }
+        map.insert(
+            "domains".to_owned(),
+            AttributeValue::from_string_set(self.domains),
+        );
+        if let Some(ttl) = self.ttl {
+            map.insert("record_ttl".to_owned(), AttributeValue::from_number(ttl));
+        }
+        if !self.domain_ttls.is_empty() {
+            map.insert(
+                "domain_ttls".to_owned(),
+                AttributeValue::from_number_map(self.domain_ttls),
+            );
+        }
+        map.insert(
+            "created_at".to_owned(),
+            AttributeValue::from_number(self.created_at),
+        );
+        map.insert(
+            "updated_at".to_owned(),
+            AttributeValue::from_number(self.updated_at),
+        );
+        if !self.hosts.is_empty() {
+            map.insert("hosts".to_owned(), hosts_att(self.hosts));
+        }
+        if !self.user_agents.is_empty() {
+            map.insert(
+                "user_agents".to_owned(),
+                AttributeValue::from_string_set(self.user_agents),
+            );
+        }
+        if !self.tokens.is_empty() {
+            let mut tokens = AttributeValue::default();
+            tokens.m = Some(
+                self.tokens
+                    .into_iter()
+                    .map(|(id, token)| (id, AttributeValue::from(token)))
+                    .collect(),
+            );
+            map.insert("tokens".to_owned(), tokens);
+        }
+        if self.allow_private_addresses {
+            let mut allow = AttributeValue::default();
+            allow.bool = Some(true);
+            map.insert("allow_private_addresses".to_owned(), allow);
+        }
+        map
+    }
+}
+
+pub(crate) trait AttributeValueExt {
+    type Error;
+    fn get_string(&self) -> Result<String, Self::Error>;
+    fn from_string(value: String) -> Self;
+    fn get_string_list(&self) -> Result<Vec<String>, Self::Error>;
+    fn from_string_list(value: Vec<String>) -> Self;
+    fn get_string_set(&self) -> Result<HashSet<String>, Self::Error>;
+    fn from_string_set(value: HashSet<String>) -> Self;
+    fn get_number(&self) -> Result<i64, Self::Error>;
+    fn from_number(value: i64) -> Self;
+    fn get_number_map(&self) -> Result<HashMap<String, i64>, Self::Error>;
+    fn from_number_map(value: HashMap<String, i64>) -> Self;
+}
+
+impl AttributeValueExt for AttributeValue {
+    type Error = ResponseError;
+
+    fn get_string(&self) -> Result<String, Self::Error> {
+        match &self.s {
+            Some(v) => Ok(v.to_owned()),
+            None => Err(ResponseError::DbError("not of type string".into())),
+        }
+    }
+
+    fn from_string(value: String) -> Self {
+        let mut att = AttributeValue::default();
+        att.s = Some(value);
+        att
+    }
+
+    fn get_string_list(&self) -> Result<Vec<String>, Self::Error> {
+        match &self.l {
+            Some(v) => v.iter().map(|att| att.get_string()).collect(),
+            None => Err(ResponseError::DbError("not of type list".into())),
+        }
+    }
+
+    fn from_string_list(value: Vec<String>) -> Self {
+        let mut att = AttributeValue::default();
+        att.l = Some(value.into_iter().map(AttributeValue::from_string).collect());
+        att
+    }
+
+    fn get_string_set(&self) -> Result<HashSet<String>, Self::Error> {
+        match &self.ss {
+            Some(v) => Ok(v.iter().map(|s| s.to_owned()).collect::<HashSet<String>>()),
+            None => Err(ResponseError::DbError("not of type string set".into())),
+        }
+    }
+
+    fn from_string_set(value: HashSet<String>) -> Self {
+        let mut att = AttributeValue::default();
+        att.ss = Some(value.iter().map(|s| s.to_owned()).collect());
+        att
+    }
+
+    fn get_number(&self) -> Result<i64, Self::Error> {
+        match &self.n {
+            Some(v) => v
+                .parse()
+                .map_err(|_| ResponseError::DbError(format!("{} is not a whole number", v))),
+            None => Err(ResponseError::DbError("not of type number".into())),
+        }
+    }
+
+    fn from_number(value: i64) -> Self {
+        let mut att = AttributeValue::default();
+        att.n = Some(format!("{}", value));
+        att
+    }
+
+    fn get_number_map(&self) -> Result<HashMap<String, i64>, Self::Error> {
+        match &self.m {
+            Some(v) => {
+                let mut map = HashMap::new();
+                for (key, att) in v {
+                    map.insert(key.to_owned(), att.get_number()?);
+                }
+                Ok(map)
+            }
+            None => Err(ResponseError::DbError("not of type map".into())),
+        }
+    }
+
+    fn from_number_map(value: HashMap<String, i64>) -> Self {
+        let mut att = AttributeValue::default();
+        att.m = Some(
+            value
+                .into_iter()
+                .map(|(k, v)| (k, AttributeValue::from_number(v)))
+                .collect(),
+        );
+        att
+    }
+}
+
+pub(crate) trait MapAttributeValueExt<K: AsRef<str>> {
+    type Error;
+    fn get_string_att_value(&self, key: K) -> Result<String, Self::Error>;
+    fn get_optional_string_att_value(&self, key: K) -> Result<Option<String>, Self::Error>;
+    fn get_string_list_att_value(&self, key: K) -> Result<Vec<String>, Self::Error>;
+    fn get_string_set_att_value(&self, key: K) -> Result<HashSet<String>, Self::Error>;
+    fn get_optional_number_att_value(&self, key: K) -> Result<Option<i64>, Self::Error>;
+    fn get_number_map_att_value(&self, key: K) -> Result<HashMap<String, i64>, Self::Error>;
+}
+
+impl<K: AsRef<str>> MapAttributeValueExt<K> for HashMap<String, AttributeValue> {
+    type Error = ResponseError;
+
+    fn get_string_att_value(&self, key: K) -> Result<String, Self::Error> {
+        match self.get(key.as_ref()) {
+            Some(att) => att.get_string(),
+            None => Err(ResponseError::DbError(format!(
+                "{} not in map",
+                key.as_ref()
+            ))),
+        }
+    }
+
+    fn get_optional_string_att_value(&self, key: K) -> Result<Option<String>, Self::Error> {
+        match self.get(key.as_ref()) {
+            Some(att) => Ok(Some(att.get_string()?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_string_list_att_value(&self, key: K) -> Result<Vec<String>, Self::Error> {
+        match self.get(key.as_ref()) {
+            Some(att) => att.get_string_list(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn get_string_set_att_value(&self, key: K) -> Result<HashSet<String>, Self::Error> {
+        match self.get(key.as_ref()) {
+            Some(att) => att.get_string_set(),
+            None => Err(ResponseError::DbError(format!(
+                "{} not in map",
+                key.as_ref()
+            ))),
+        }
+    }
+
+    fn get_optional_number_att_value(&self, key: K) -> Result<Option<i64>, Self::Error> {
+        match self.get(key.as_ref()) {
+            Some(att) => Ok(Some(att.get_number()?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_number_map_att_value(&self, key: K) -> Result<HashMap<String, i64>, Self::Error> {
+        match self.get(key.as_ref()) {
+            Some(att) => att.get_number_map(),
+            None => Ok(HashMap::new()),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use super::{PutMode, UserPage, UserStore, UserUpdate};
+use crate::{
+    error::ResponseError,
+    token::{too_many_tokens, Token, MAX_TOKENS},
+    user::{HostState, User},
+};
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+// Clones share the same users, so a copy can be kept to inspect what was
+// stored after the store has been handed to a client.
+#[derive(Clone, Default)]
+pub struct MemoryUserStore {
+    users: Arc<Mutex<HashMap<String, User>>>,
+}
+
+impl MemoryUserStore {
+    pub fn new() -> Self {
+        MemoryUserStore::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, User>> {
+        self.users.lock().expect("memory user store lock poisoned")
+    }
+}
+
+#[async_trait]
+impl UserStore for MemoryUserStore {
+    async fn get_user(&self, username: &str) -> Result<User, ResponseError> {
+        match self.lock().get(username) {
+            Some(user) => Ok(user.clone()),
+            None => Err(ResponseError::NotFound(format!("{} user", username))),
+        }
+    }
+
+    async fn put_user(&self, user: User, mode: PutMode) -> Result<(), ResponseError> {
+        let mut users = self.lock();
+        if mode == PutMode::Create && users.contains_key(&user.username) {
+            return Err(ResponseError::UserExists);
+        }
+        users.insert(user.username.clone(), user);
+        Ok(())
+    }
+
+    async fn delete_user(&self, username: &str) -> Result<(), ResponseError> {
+        match self.lock().remove(username) {
+            Some(_) => Ok(()),
+            None => Err(ResponseError::NotFound(format!("{} user", username))),
+        }
+    }
+
+    async fn update_user(&self, username: &str, update: UserUpdate) -> Result<User, ResponseError> {
+        match self.lock().get_mut(username) {
+            Some(user) => {
+                update.check(user)?;
+                user.apply(update);
+                Ok(user.clone())
+            }
+            None => Err(ResponseError::NotFound(format!("{} user", username))),
+        }
+    }
+
+    async fn record_hosts(
+        &self,
+        username: &str,
+        hosts: HashMap<String, HostState>,
+        stale: Vec<String>,
+    ) -> Result<(), ResponseError> {
+        match self.lock().get_mut(username) {
+            Some(user) => {
+                for host in &stale {
+                    user.hosts.remove(host);
+                }
+                user.hosts.extend(hosts);
+                Ok(())
+            }
+            None => Err(ResponseError::NotFound(format!("{} user", username))),
+        }
+    }
+
+    async fn replace_password(
+        &self,
+        username: &str,
+        current: &str,
+        new: &str,
+        pepper_id: Option<&str>,
+    ) -> Result<bool, ResponseError> {
+        match self.lock().get_mut(username) {
+            Some(user) if user.password == current => {
+                user.password = new.to_owned();
+                user.pepper_id = pepper_id.map(String::from);
+                Ok(true)
+            }
+            Some(_) => Ok(false),
+            None => Err(ResponseError::NotFound(format!("{} user", username))),
+        }
+    }
+
+    async fn put_token(&self, username: &str, id: &str, token: Token) -> Result<(), ResponseError> {
+        match self.lock().get_mut(username) {
+            Some(user) if user.tokens.len() >= MAX_TOKENS => Err(too_many_tokens()),
+            Some(user) => {
+                user.tokens.insert(id.to_owned(), token);
+                Ok(())
+            }
+            None => Err(ResponseError::NotFound(format!("{} user", username))),
+        }
+    }
+
+    async fn delete_token(&self, username: &str, id: &str) -> Result<(), ResponseError> {
+        match self
+            .lock()
+            .get_mut(username)
+            .and_then(|user| user.tokens.remove(id))
+        {
+            Some(_) => Ok(()),
+            None => Err(ResponseError::NotFound(format!("{} token", id))),
+        }
+    }
+
+    async fn record_token_use(
+        &self,
+        username: &str,
+        id: &str,
+        at: i64,
+    ) -> Result<(), ResponseError> {
+        match self
+            .lock()
+            .get_mut(username)
+            .and_then(|user| user.tokens.get_mut(id))
+        {
+            Some(token) => {
+                token.last_used_at = Some(at);
+                Ok(())
+            }
+            None => Err(ResponseError::NotFound(format!("{} token", id))),
+        }
+    }
+
+    async fn list_users(
+        &self,
+        limit: usize,
+        cursor: Option<String>,
+    ) -> Result<UserPage, ResponseError> {
+        let users = self.lock();
+        let mut names: Vec<&String> = users
+            .keys()
+            .filter(|name| match &cursor {
+                Some(c) => *name > c,
+                None => true,
+            })
+            .collect();
+        names.sort();
+        let more = names.len() > limit;
+        let page: Vec<User> = names
+            .into_iter()
+            .take(limit)
+            .filter_map(|name| users.get(name).cloned())
+            .collect();
+        let cursor = match page.last() {
+            Some(last) if more => Some(last.username.clone()),
+            _ => None,
+        };
+        Ok(UserPage {
+            users: page,
+            cursor,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::check_store;
+
+    #[tokio::test]
+    async fn behaves_like_a_user_store() {
+        check_store(&MemoryUserStore::new()).await;
+    }
+}
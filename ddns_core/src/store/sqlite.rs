@@ -0,0 +1,286 @@
+use super::{PutMode, UserPage, UserStore, UserUpdate};
+use crate::{
+    error::ResponseError,
+    sqlite::{blocking, db_error},
+    token::{too_many_tokens, Token, MAX_TOKENS},
+    user::{HostState, User},
+};
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+// Users are kept as JSON documents keyed by username, so new fields on `User`
+// do not need a schema migration.
+pub struct SqliteUserStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteUserStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ResponseError> {
+        let conn = Connection::open(path).map_err(db_error)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS users (
+                username TEXT PRIMARY KEY,
+                user TEXT NOT NULL
+            )",
+            params![],
+        )
+        .map_err(db_error)?;
+        Ok(SqliteUserStore {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    // Reads, changes and writes back one user while holding the lock, so
+    // nothing else can change it in between.
+    async fn modify<T: Send + 'static>(
+        &self,
+        username: &str,
+        change: impl FnOnce(&mut User) -> Result<T, ResponseError> + Send + 'static,
+    ) -> Result<T, ResponseError> {
+        let username = username.to_owned();
+        blocking(&self.conn, move |conn| {
+            let raw: Option<String> = conn
+                .query_row(
+                    "SELECT user FROM users WHERE username = ?1",
+                    params![username],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(db_error)?;
+            let mut user: User = match raw {
+                Some(raw) => serde_json::from_str(&raw).map_err(db_error)?,
+                None => return Err(ResponseError::NotFound(format!("{} user", username))),
+            };
+            let result = change(&mut user)?;
+            let raw = serde_json::to_string(&user).map_err(db_error)?;
+            conn.execute(
+                "UPDATE users SET user = ?2 WHERE username = ?1",
+                params![username, raw],
+            )
+            .map_err(db_error)?;
+            Ok(result)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl UserStore for SqliteUserStore {
+    async fn get_user(&self, username: &str) -> Result<User, ResponseError> {
+        let key = username.to_owned();
+        let raw: Option<String> = blocking(&self.conn, move |conn| {
+            conn.query_row(
+                "SELECT user FROM users WHERE username = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error)
+        })
+        .await?;
+        match raw {
+            Some(raw) => serde_json::from_str(&raw).map_err(db_error),
+            None => Err(ResponseError::NotFound(format!("{} user", username))),
+        }
+    }
+
+    async fn put_user(&self, user: User, mode: PutMode) -> Result<(), ResponseError> {
+        let raw = serde_json::to_string(&user).map_err(db_error)?;
+        let sql = match mode {
+            PutMode::Create => "INSERT OR IGNORE INTO users (username, user) VALUES (?1, ?2)",
+            PutMode::Overwrite => "INSERT OR REPLACE INTO users (username, user) VALUES (?1, ?2)",
+        };
+        let changed = blocking(&self.conn, move |conn| {
+            conn.execute(sql, params![user.username, raw])
+                .map_err(db_error)
+        })
+        .await?;
+        if changed == 0 {
+            return Err(ResponseError::UserExists);
+        }
+        Ok(())
+    }
+
+    async fn delete_user(&self, username: &str) -> Result<(), ResponseError> {
+        let key = username.to_owned();
+        let changed = blocking(&self.conn, move |conn| {
+            conn.execute("DELETE FROM users WHERE username = ?1", params![key])
+                .map_err(db_error)
+        })
+        .await?;
+        if changed == 0 {
+            return Err(ResponseError::NotFound(format!("{} user", username)));
+        }
+        Ok(())
+    }
+
+    async fn update_user(&self, username: &str, update: UserUpdate) -> Result<User, ResponseError> {
+        self.modify(username, move |user| {
+            update.check(user)?;
+            user.apply(update);
+            Ok(user.clone())
+        })
+        .await
+    }
+
+    async fn record_hosts(
+        &self,
+        username: &str,
+        hosts: HashMap<String, HostState>,
+        stale: Vec<String>,
+    ) -> Result<(), ResponseError> {
+        self.modify(username, move |user| {
+            for host in &stale {
+                user.hosts.remove(host);
+            }
+            user.hosts.extend(hosts);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn replace_password(
+        &self,
+        username: &str,
+        current: &str,
+        new: &str,
+        pepper_id: Option<&str>,
+    ) -> Result<bool, ResponseError> {
+        let (current, new) = (current.to_owned(), new.to_owned());
+        let pepper_id = pepper_id.map(String::from);
+        self.modify(username, move |user| {
+            if user.password != current {
+                return Ok(false);
+            }
+            user.password = new;
+            user.pepper_id = pepper_id;
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn put_token(&self, username: &str, id: &str, token: Token) -> Result<(), ResponseError> {
+        let id = id.to_owned();
+        self.modify(username, move |user| {
+            if user.tokens.len() >= MAX_TOKENS {
+                return Err(too_many_tokens());
+            }
+            user.tokens.insert(id, token);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_token(&self, username: &str, id: &str) -> Result<(), ResponseError> {
+        let id = id.to_owned();
+        self.modify(username, move |user| match user.tokens.remove(&id) {
+            Some(_) => Ok(()),
+            None => Err(ResponseError::NotFound(format!("{} token", id))),
+        })
+        .await
+    }
+
+    async fn record_token_use(
+        &self,
+        username: &str,
+        id: &str,
+        at: i64,
+    ) -> Result<(), ResponseError> {
+        let id = id.to_owned();
+        self.modify(username, move |user| match user.tokens.get_mut(&id) {
+            Some(token) => {
+                token.last_used_at = Some(at);
+                Ok(())
+            }
+            None => Err(ResponseError::NotFound(format!("{} token", id))),
+        })
+        .await
+    }
+
+    async fn list_users(
+        &self,
+        limit: usize,
+        cursor: Option<String>,
+    ) -> Result<UserPage, ResponseError> {
+        let mut users = blocking(&self.conn, move |conn| {
+            let mut stmt = conn
+                .prepare("SELECT user FROM users WHERE username > ?1 ORDER BY username LIMIT ?2")
+                .map_err(db_error)?;
+            let rows = stmt
+                .query_map(
+                    params![cursor.unwrap_or_default(), limit as i64 + 1],
+                    |row| row.get::<_, String>(0),
+                )
+                .map_err(db_error)?;
+            let mut users = Vec::new();
+            for raw in rows {
+                let user: User = serde_json::from_str(&raw.map_err(db_error)?).map_err(db_error)?;
+                users.push(user);
+            }
+            Ok(users)
+        })
+        .await?;
+        let more = users.len() > limit;
+        users.truncate(limit);
+        let cursor = match users.last() {
+            Some(last) if more => Some(last.username.clone()),
+            _ => None,
+        };
+        Ok(UserPage { users, cursor })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{store::tests::check_store, user::test_user};
+    use std::{env, fs, process};
+
+    #[tokio::test]
+    async fn behaves_like_a_user_store() {
+        check_store(&SqliteUserStore::open(":memory:").unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn users_survive_reopening() {
+        let path = env::temp_dir().join(format!("ddns-users-{}.db", process::id()));
+        let _ = fs::remove_file(&path);
+        let store = SqliteUserStore::open(&path).unwrap();
+        let user = test_user();
+        let password = user.password.clone();
+        store.put_user(user, PutMode::Create).await.unwrap();
+        drop(store);
+        let store = SqliteUserStore::open(&path).unwrap();
+        assert_eq!(store.get_user("alice").await.unwrap().password, password);
+        match store.put_user(test_user(), PutMode::Create).await {
+            Err(ResponseError::UserExists) => {}
+            other => panic!("expected UserExists, got {:?}", other),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    // Each write reads the user and writes it back whole, so parallel writes
+    // to one user must not lose each other's changes.
+    #[tokio::test]
+    async fn parallel_writes_to_one_user_are_all_kept() {
+        let store = Arc::new(SqliteUserStore::open(":memory:").unwrap());
+        store.put_user(test_user(), PutMode::Create).await.unwrap();
+        let hostnames = test_user().domains().clone();
+        let tasks: Vec<_> = (0..10)
+            .map(|_| {
+                let store = store.clone();
+                let (id, _, token) = Token::generate(hostnames.clone(), 1);
+                tokio::spawn(async move { store.put_token("alice", &id, token).await })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        assert_eq!(store.get_user("alice").await.unwrap().tokens().len(), 10);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{
+    error::ResponseError,
+    zone::{check_grant, normalize_name},
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaChaRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+const ID_LENGTH: usize = 8;
+const SECRET_LENGTH: usize = 32;
+pub const MAX_TOKENS: usize = 20;
+
+// An update token lets a router update a few of a user's hostnames without
+// knowing the account password. It is handed out once as `<id>.<secret>`;
+// only a hash of the secret is stored. The secret is random, so a plain
+// SHA-256 is enough and checking it costs nothing like argon2.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Token {
+    pub(crate) hash: String,
+    pub(crate) hostnames: HashSet<String>,
+    pub(crate) created_at: i64,
+    #[serde(default)]
+    pub(crate) last_used_at: Option<i64>,
+}
+
+// What listing a user's tokens returns; it never includes the hash.
+#[derive(Serialize)]
+pub struct TokenInfo {
+    pub id: String,
+    pub hostnames: Vec<String>,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<i64>,
+}
+
+// A token that was just made, the only time its secret is available.
+#[derive(Serialize)]
+pub struct NewToken {
+    #[serde(flatten)]
+    pub info: TokenInfo,
+    pub token: String,
+}
+
+impl Token {
+    // Returns the token with its id and the full value to hand out.
+    pub(crate) fn generate(hostnames: HashSet<String>, now: i64) -> (String, String, Token) {
+        let mut rng = ChaChaRng::from_entropy();
+        let mut id: [u8; ID_LENGTH] = [0; ID_LENGTH];
+        rng.fill(&mut id);
+        let mut secret: [u8; SECRET_LENGTH] = [0; SECRET_LENGTH];
+        rng.fill(&mut secret);
+        let id = hex(&id);
+        let secret = base64::encode_config(&secret[..], base64::URL_SAFE_NO_PAD);
+        let token = Token {
+            hash: hash_secret(&secret),
+            hostnames: hostnames.iter().map(normalize_name).collect(),
+            created_at: now,
+            last_used_at: None,
+        };
+        (id.clone(), format!("{}.{}", id, secret), token)
+    }
+
+    // Compares every byte so the time taken does not depend on how much of
+    // the hash was right.
+    pub(crate) fn matches(&self, secret: &str) -> bool {
+        let given = hash_secret(secret);
+        given.len() == self.hash.len()
+            && given
+                .bytes()
+                .zip(self.hash.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+
+    pub(crate) fn allows(&self, host: impl AsRef<str>) -> bool {
+        self.hostnames.contains(&normalize_name(host))
+    }
+
+    pub fn info(&self, id: impl AsRef<str>) -> TokenInfo {
+        let mut hostnames: Vec<String> = self.hostnames.iter().cloned().collect();
+        hostnames.sort();
+        TokenInfo {
+            id: id.as_ref().to_owned(),
+            hostnames,
+            created_at: self.created_at,
+            last_used_at: self.last_used_at,
+        }
+    }
+}
+
+// Splits `<id>.<secret>`. Anything else is not a token and is treated as a
+// password.
+pub(crate) fn split_token(raw: &str) -> Option<(&str, &str)> {
+    let mut parts = raw.splitn(2, '.');
+    match (parts.next(), parts.next()) {
+        (Some(id), Some(secret)) if id.len() == ID_LENGTH * 2 && !secret.is_empty() => {
+            Some((id, secret))
+        }
+        _ => None,
+    }
+}
+
+// Stores refuse a token past `MAX_TOKENS` with this as they write it.
+pub(crate) fn too_many_tokens() -> ResponseError {
+    ResponseError::InvalidField(
+        "tokens".into(),
+        format!("user already has {} tokens", MAX_TOKENS),
+    )
+}
+
+// Tokens are for single hostnames, so wildcard grants are refused.
+pub fn check_token_hostname(host: impl AsRef<str>) -> Result<(), String> {
+    check_grant(host.as_ref())?;
+    if host.as_ref().contains('*') {
+        return Err("cannot be a wildcard".into());
+    }
+    Ok(())
+}
+
+fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(hostnames: &[&str]) -> (String, String, Token) {
+        let hostnames = hostnames.iter().map(|h| (*h).to_owned()).collect();
+        Token::generate(hostnames, 1)
+    }
+
+    #[test]
+    fn generated_tokens_split_into_id_and_secret() {
+        let (id, value, token) = token(&["host.example.com"]);
+        let (split_id, secret) = split_token(&value).unwrap();
+        assert_eq!(split_id, id);
+        assert!(token.matches(secret));
+        assert!(!token.matches("guess"));
+        assert!(!token.matches(&secret[1..]));
+    }
+
+    #[test]
+    fn only_id_dot_secret_is_a_token() {
+        let id = "0123456789abcdef";
+        assert_eq!(split_token(&format!("{}.s3cret", id)), Some((id, "s3cret")));
+        // The secret may itself contain dots.
+        assert_eq!(split_token(&format!("{}.a.b", id)), Some((id, "a.b")));
+        assert_eq!(split_token(&format!("{}.", id)), None);
+        assert_eq!(split_token("0123.s3cret"), None);
+        assert_eq!(split_token("hunter2"), None);
+    }
+
+    #[test]
+    fn tokens_allow_only_their_hostnames() {
+        let (_, _, token) = token(&["Host.Example.com."]);
+        assert!(token.allows("host.example.com"));
+        assert!(token.allows("HOST.example.com."));
+        assert!(!token.allows("other.example.com"));
+        assert!(!token.allows("sub.host.example.com"));
+    }
+
+    #[test]
+    fn token_hostnames_cannot_be_wildcards() {
+        assert_eq!(check_token_hostname("host.example.com"), Ok(()));
+        assert!(check_token_hostname("*.example.com").is_err());
+        assert!(check_token_hostname("example..com").is_err());
+    }
+}
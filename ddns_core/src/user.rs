@@ -0,0 +1,407 @@
+use crate::{
+    error::ResponseError,
+    password::Hasher,
+    store::UserUpdate,
+    token::{Token, TokenInfo},
+    zone::{grant_matches, grant_specificity},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct User {
+    pub(crate) username: String,
+    pub(crate) password: String,
+    // The pepper `password` was hashed with, if any.
+    #[serde(default)]
+    pub(crate) pepper_id: Option<String>,
+    pub(crate) domains: HashSet<String>,
+    #[serde(default)]
+    pub(crate) ttl: Option<i64>,
+    #[serde(default)]
+    pub(crate) domain_ttls: HashMap<String, i64>,
+    // Epoch seconds. Users stored before these existed read as 0.
+    #[serde(default)]
+    pub(crate) created_at: i64,
+    #[serde(default)]
+    pub(crate) updated_at: i64,
+    // The outcome of the last update request for each hostname.
+    #[serde(default)]
+    pub(crate) hosts: HashMap<String, HostState>,
+    // When set, the only user agents this user may update with.
+    #[serde(default)]
+    pub(crate) user_agents: HashSet<String>,
+    // Update tokens by id.
+    #[serde(default)]
+    pub(crate) tokens: HashMap<String, Token>,
+    // Lets the user publish private and other reserved addresses.
+    #[serde(default)]
+    pub(crate) allow_private_addresses: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostState {
+    // Comma separated, as requested.
+    pub last_ip: String,
+    pub last_update_at: i64,
+    pub last_user_agent: String,
+    // The dyndns return code the hostname got, e.g. `good 1.2.3.4` or `dnserr`.
+    pub last_result: String,
+    // The TTL the records were written or found with.
+    #[serde(default)]
+    pub ttl: i64,
+    // When DNS was last asked for the records, rather than trusting this state.
+    #[serde(default)]
+    pub checked_at: i64,
+}
+
+// What the admin API returns for a user; it never includes the password hash.
+#[derive(Serialize)]
+pub struct UserInfo {
+    pub username: String,
+    pub domains: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<i64>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub ttls: HashMap<String, i64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub user_agents: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub allow_private_addresses: bool,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl From<&User> for UserInfo {
+    fn from(user: &User) -> Self {
+        let mut domains: Vec<String> = user.domains.iter().cloned().collect();
+        domains.sort();
+        let mut user_agents: Vec<String> = user.user_agents.iter().cloned().collect();
+        user_agents.sort();
+        UserInfo {
+            username: user.username.clone(),
+            domains,
+            ttl: user.ttl,
+            ttls: user.domain_ttls.clone(),
+            user_agents,
+            allow_private_addresses: user.allow_private_addresses,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
+pub const MIN_PASSWORD_LENGTH: usize = 7;
+
+// How many hostnames' outcomes are kept per user, so a `**.` grant cannot grow
+// the user past DynamoDB's item size limit.
+pub const MAX_RECORDED_HOSTS: usize = 100;
+
+// The rules every new password has to meet, wherever it is set.
+pub fn check_password(password: &str) -> Result<(), String> {
+    if password.len() < MIN_PASSWORD_LENGTH {
+        return Err(format!(
+            "is less than {} characters long",
+            MIN_PASSWORD_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+// alice, whose password is "secret" and who may update host.example.com.
+#[cfg(any(test, feature = "test-util"))]
+pub fn test_user() -> User {
+    let mut domains = HashSet::new();
+    domains.insert("host.example.com".to_owned());
+    User::new("alice", "secret", domains, &crate::password::test_hasher()).unwrap()
+}
+
+// Stands in for usernames that do not exist, so rejecting one costs the same
+// argon2 work as rejecting a wrong password. No password matches it, so
+// nothing can log in as it.
+pub(crate) fn dummy_user(hasher: &Hasher) -> User {
+    User {
+        username: String::new(),
+        password: hasher.unmatchable(),
+        pepper_id: hasher.pepper_id().map(String::from),
+        domains: HashSet::new(),
+        ttl: None,
+        domain_ttls: HashMap::new(),
+        created_at: 0,
+        updated_at: 0,
+        hosts: HashMap::new(),
+        user_agents: HashSet::new(),
+        tokens: HashMap::new(),
+        allow_private_addresses: false,
+    }
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+impl User {
+    pub fn new(
+        username: impl AsRef<str>,
+        password: impl AsRef<str>,
+        domains: HashSet<String>,
+        hasher: &Hasher,
+    ) -> Result<Self, ResponseError> {
+        let mut user = User {
+            username: username.as_ref().to_owned(),
+            password: String::new(),
+            pepper_id: None,
+            domains,
+            ttl: None,
+            domain_ttls: HashMap::new(),
+            created_at: now(),
+            updated_at: 0,
+            hosts: HashMap::new(),
+            user_agents: HashSet::new(),
+            tokens: HashMap::new(),
+            allow_private_addresses: false,
+        };
+        user.set_password(password.as_ref(), hasher)?;
+        Ok(user)
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn domains(&self) -> &HashSet<String> {
+        &self.domains
+    }
+
+    pub fn hosts(&self) -> &HashMap<String, HostState> {
+        &self.hosts
+    }
+
+    pub fn user_agents(&self) -> &HashSet<String> {
+        &self.user_agents
+    }
+
+    pub fn tokens(&self) -> &HashMap<String, Token> {
+        &self.tokens
+    }
+
+    // Oldest first.
+    pub fn token_infos(&self) -> Vec<TokenInfo> {
+        let mut infos: Vec<TokenInfo> = self
+            .tokens
+            .iter()
+            .map(|(id, token)| token.info(id))
+            .collect();
+        infos.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        infos
+    }
+
+    pub fn allow_private_addresses(&self) -> bool {
+        self.allow_private_addresses
+    }
+
+    pub fn set_allow_private_addresses(&mut self, allow: bool) {
+        self.allow_private_addresses = allow;
+    }
+
+    pub fn set_user_agents(&mut self, user_agents: HashSet<String>) {
+        self.user_agents = user_agents;
+    }
+
+    pub fn add_domain(&mut self, domain: impl AsRef<str>) {
+        self.domains.insert(domain.as_ref().to_owned());
+    }
+
+    pub fn remove_domain(&mut self, domain: impl AsRef<str>) -> bool {
+        self.domain_ttls.remove(domain.as_ref());
+        self.domains.remove(domain.as_ref())
+    }
+
+    pub fn set_password(
+        &mut self,
+        pass: impl AsRef<str>,
+        hasher: &Hasher,
+    ) -> Result<(), ResponseError> {
+        let (password, pepper_id) = hasher.hash(pass)?;
+        self.password = password;
+        self.pepper_id = pepper_id;
+        Ok(())
+    }
+
+    // What the stores that keep whole users do for `UserStore::update_user`.
+    pub(crate) fn apply(&mut self, update: UserUpdate) {
+        for domain in &update.remove_domains {
+            self.remove_domain(domain);
+        }
+        for domain in &update.add_domains {
+            self.add_domain(domain);
+        }
+        if let Some((password, pepper_id)) = update.password {
+            self.password = password;
+            self.pepper_id = pepper_id;
+        }
+        if let Some(agents) = update.user_agents {
+            self.user_agents = agents;
+        }
+        if let Some(allow) = update.allow_private_addresses {
+            self.allow_private_addresses = allow;
+        }
+        self.updated_at = update.updated_at;
+    }
+
+    // True when the stored hash was made with other parameters or another
+    // pepper than `hasher` would use now.
+    pub(crate) fn needs_rehash(&self, hasher: &Hasher) -> bool {
+        !hasher.is_current(&self.password, self.pepper_id.as_deref())
+    }
+
+    pub(crate) async fn compare_password(
+        &self,
+        raw_pass: impl AsRef<str>,
+        hasher: &Hasher,
+    ) -> Result<bool, ResponseError> {
+        hasher
+            .verify_async(&self.password, self.pepper_id.as_deref(), raw_pass)
+            .await
+    }
+
+    pub(crate) fn has_domain(&self, domain: impl AsRef<str>) -> bool {
+        self.domains
+            .iter()
+            .any(|grant| grant_matches(grant, domain.as_ref()))
+    }
+
+    // The recorded hosts to drop when `recorded` is merged in: those the user
+    // may no longer update, then the least recently updated ones past
+    // `MAX_RECORDED_HOSTS`.
+    pub(crate) fn stale_hosts(&self, recorded: &HashMap<String, HostState>) -> Vec<String> {
+        let mut stale = Vec::new();
+        let mut kept = Vec::new();
+        for (host, state) in &self.hosts {
+            if recorded.contains_key(host) {
+                continue;
+            }
+            if self.has_domain(host) {
+                kept.push((state.last_update_at, host));
+            } else {
+                stale.push(host.to_owned());
+            }
+        }
+        let room = MAX_RECORDED_HOSTS.saturating_sub(recorded.len());
+        if kept.len() > room {
+            kept.sort_by(|a, b| b.cmp(a));
+            stale.extend(kept.drain(room..).map(|(_, host)| host.to_owned()));
+        }
+        stale
+    }
+
+    pub fn set_ttl(&mut self, ttl: Option<i64>) {
+        self.ttl = ttl;
+    }
+
+    pub fn set_domain_ttl(&mut self, domain: impl AsRef<str>, ttl: Option<i64>) {
+        match ttl {
+            Some(ttl) => {
+                self.domain_ttls.insert(domain.as_ref().to_owned(), ttl);
+            }
+            None => {
+                self.domain_ttls.remove(domain.as_ref());
+            }
+        }
+    }
+
+    // A TTL set for the most specific grant covering the domain applies, then
+    // the user's TTL.
+    pub(crate) fn ttl_for(&self, domain: impl AsRef<str>) -> Option<i64> {
+        self.domain_ttls
+            .iter()
+            .filter(|(grant, _)| grant_matches(grant, domain.as_ref()))
+            .max_by_key(|(grant, _)| grant_specificity(grant))
+            .map(|(_, ttl)| *ttl)
+            .or(self.ttl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(domains: &[&str]) -> User {
+        User {
+            domains: domains.iter().map(|d| (*d).to_owned()).collect(),
+            ..test_user()
+        }
+    }
+
+    fn state(at: i64) -> HostState {
+        HostState {
+            last_ip: "93.184.216.34".into(),
+            last_update_at: at,
+            last_user_agent: "curl".into(),
+            last_result: "good 93.184.216.34".into(),
+            ttl: 300,
+            checked_at: at,
+        }
+    }
+
+    #[test]
+    fn stale_hosts_drops_revoked_hosts() {
+        let mut user = user(&["**.lab.example.com"]);
+        user.hosts.insert("a.lab.example.com".into(), state(1));
+        user.hosts.insert("old.example.com".into(), state(2));
+        assert_eq!(user.stale_hosts(&HashMap::new()), vec!["old.example.com"]);
+    }
+
+    #[test]
+    fn stale_hosts_keeps_the_most_recent() {
+        let mut user = user(&["**.lab.example.com"]);
+        for i in 0..MAX_RECORDED_HOSTS {
+            user.hosts
+                .insert(format!("h{}.lab.example.com", i), state(i as i64));
+        }
+        assert!(user.stale_hosts(&HashMap::new()).is_empty());
+
+        let mut recorded = HashMap::new();
+        recorded.insert("h5.lab.example.com".to_owned(), state(1000));
+        recorded.insert("new.lab.example.com".to_owned(), state(1000));
+        assert_eq!(user.stale_hosts(&recorded), vec!["h0.lab.example.com"]);
+    }
+
+    #[test]
+    fn ttl_for_uses_the_most_specific_grant() {
+        let grants = [
+            "**.example.com",
+            "**.lab.example.com",
+            "*.lab.example.com",
+            "a.lab.example.com",
+            "other.org",
+        ];
+        let mut user = user(&grants);
+        user.set_ttl(Some(120));
+        user.set_domain_ttl("**.example.com", Some(200));
+        user.set_domain_ttl("**.lab.example.com", Some(400));
+        user.set_domain_ttl("*.lab.example.com", Some(600));
+        user.set_domain_ttl("a.lab.example.com", Some(900));
+        assert_eq!(user.ttl_for("a.lab.example.com"), Some(900));
+        assert_eq!(user.ttl_for("b.lab.example.com"), Some(600));
+        assert_eq!(user.ttl_for("x.b.lab.example.com"), Some(400));
+        assert_eq!(user.ttl_for("www.example.com"), Some(200));
+        // Without a TTL for the grant, the user's TTL applies.
+        assert_eq!(user.ttl_for("other.org"), Some(120));
+    }
+
+    #[test]
+    fn ttl_for_is_unset_without_any_ttl() {
+        let mut user = user(&["host.example.com"]);
+        assert_eq!(user.ttl_for("host.example.com"), None);
+        user.set_domain_ttl("host.example.com", Some(600));
+        user.set_domain_ttl("host.example.com", None);
+        assert_eq!(user.ttl_for("host.example.com"), None);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{error::ResponseError, user::User};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AgentFormat {
+    #[default]
+    Any,
+    // `Company-Device-Version`, as Dyn asks update clients to send.
+    Dyn,
+}
+
+impl FromStr for AgentFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("any") {
+            Ok(AgentFormat::Any)
+        } else if s.eq_ignore_ascii_case("dyn") {
+            Ok(AgentFormat::Dyn)
+        } else {
+            Err(format!("{} is not one of any or dyn", s))
+        }
+    }
+}
+
+// Agents are matched by case-insensitive prefix, so `curl` covers every curl
+// version.
+#[derive(Debug, Clone, Default)]
+pub struct UserAgentPolicy {
+    pub format: AgentFormat,
+    pub blocked: Vec<String>,
+}
+
+impl UserAgentPolicy {
+    // A user with allowed agents may only use those, and they are exempt from
+    // the format and block list so a known client that breaks the rules can
+    // still be let through for that user.
+    pub fn check(&self, user: &User, agent: &str) -> Result<(), ResponseError> {
+        if !user.user_agents.is_empty() {
+            if user.user_agents.iter().any(|a| agent_matches(a, agent)) {
+                return Ok(());
+            }
+            return Err(ResponseError::BadAgent(format!(
+                "{} is not allowed for this user",
+                agent
+            )));
+        }
+        if let Some(blocked) = self.blocked.iter().find(|b| agent_matches(b, agent)) {
+            return Err(ResponseError::BadAgent(format!(
+                "{} is blocked ({})",
+                agent, blocked
+            )));
+        }
+        if self.format == AgentFormat::Dyn && !is_dyn_format(agent) {
+            return Err(ResponseError::BadAgent(format!(
+                "{} is not in the form Company-Device-Version",
+                agent
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn agent_matches(pattern: &str, agent: &str) -> bool {
+    agent.len() >= pattern.len()
+        && agent.is_char_boundary(pattern.len())
+        && agent[..pattern.len()].eq_ignore_ascii_case(pattern)
+}
+
+// The device may itself contain dashes; the version is the last part and has
+// to contain a digit.
+fn is_dyn_format(agent: &str) -> bool {
+    let parts: Vec<&str> = agent.split('-').collect();
+    parts.len() >= 3
+        && parts.iter().all(|p| !p.trim().is_empty())
+        && parts[parts.len() - 1].chars().any(|c| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::test_user;
+
+    fn user(agents: &[&str]) -> User {
+        User {
+            user_agents: agents.iter().map(|a| (*a).to_owned()).collect(),
+            ..test_user()
+        }
+    }
+
+    fn policy() -> UserAgentPolicy {
+        UserAgentPolicy {
+            format: AgentFormat::Dyn,
+            blocked: vec!["curl".into()],
+        }
+    }
+
+    #[test]
+    fn rules_apply_without_an_allow_list() {
+        let user = user(&[]);
+        assert!(policy().check(&user, "acme-router-1.2").is_ok());
+        assert!(policy().check(&user, "CURL/7.68.0").is_err());
+        assert!(policy().check(&user, "router").is_err());
+    }
+
+    #[test]
+    fn allow_list_bypasses_the_block_list() {
+        let user = user(&["curl/7"]);
+        assert!(policy().check(&user, "curl/7.68.0").is_ok());
+        assert!(policy().check(&user, "curl/8.0.0").is_err());
+    }
+
+    #[test]
+    fn allow_list_bypasses_the_dyn_format() {
+        let user = user(&["router"]);
+        assert!(policy().check(&user, "Router").is_ok());
+        // Only the listed agents, even ones the rules would allow.
+        assert!(policy().check(&user, "acme-router-1.2").is_err());
+    }
+}
@@ -0,0 +1,228 @@
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    pub name: String,
+    pub id: String,
+}
+
+impl Zone {
+    pub fn new(name: impl AsRef<str>, id: impl AsRef<str>) -> Self {
+        Zone {
+            name: normalize_name(name),
+            id: id.as_ref().to_owned(),
+        }
+    }
+
+    pub fn contains(&self, host: impl AsRef<str>) -> bool {
+        in_zone(host, &self.name)
+    }
+}
+
+pub fn normalize_name(name: impl AsRef<str>) -> String {
+    name.as_ref().trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn labels(name: &str) -> Vec<String> {
+    let name = normalize_name(name);
+    if name.is_empty() {
+        return Vec::new();
+    }
+    name.split('.').map(|l| l.to_owned()).collect()
+}
+
+// A host is in a zone when the zone's labels are a suffix of the host's
+// labels, so `evilexample.com` is not in `example.com`.
+pub fn in_zone(host: impl AsRef<str>, zone: impl AsRef<str>) -> bool {
+    let host = labels(host.as_ref());
+    let zone = labels(zone.as_ref());
+    if zone.is_empty() || host.len() < zone.len() {
+        return false;
+    }
+    host[host.len() - zone.len()..] == zone[..]
+}
+
+enum Wildcard {
+    One,
+    Subtree,
+}
+
+// A grant is either a hostname, `*.<name>` for hosts exactly one label below
+// `<name>`, or `**.<name>` for every host anywhere below `<name>`.
+fn split_grant(grant: &str) -> (Option<Wildcard>, &str) {
+    if let Some(base) = grant.strip_prefix("**.") {
+        (Some(Wildcard::Subtree), base)
+    } else if let Some(base) = grant.strip_prefix("*.") {
+        (Some(Wildcard::One), base)
+    } else {
+        (None, grant)
+    }
+}
+
+// The name a grant sits under, which is what has to be in a hosted zone.
+pub fn grant_base(grant: &str) -> &str {
+    split_grant(grant).1
+}
+
+// Past its wildcard, a grant has to be a hostname `check_hostname` accepts.
+pub fn check_grant(grant: impl AsRef<str>) -> Result<(), String> {
+    let base = grant_base(grant.as_ref());
+    if labels(base).iter().any(|l| l.contains('*')) {
+        return Err("may only use * or ** as the whole first label".into());
+    }
+    check_hostname(base)
+}
+
+// What `/nic/update` accepts as a hostname: at least two labels of letters,
+// digits and hyphens, so grants and wildcards like `*.example.com` are not
+// hostnames.
+pub fn check_hostname(host: impl AsRef<str>) -> Result<(), String> {
+    let name = normalize_name(host);
+    let labels = labels(&name);
+    if name.len() > 253 || labels.len() < 2 {
+        return Err("is not a fully qualified domain name".into());
+    }
+    for label in &labels {
+        if label.is_empty()
+            || label.len() > 63
+            || label.starts_with('-')
+            || label.ends_with('-')
+            || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(format!("has an invalid label {:?}", label));
+        }
+    }
+    Ok(())
+}
+
+pub fn grant_matches(grant: impl AsRef<str>, host: impl AsRef<str>) -> bool {
+    let (wildcard, base) = split_grant(grant.as_ref());
+    let base = labels(base);
+    let host = labels(host.as_ref());
+    if base.is_empty() || host.len() < base.len() || host[host.len() - base.len()..] != base[..] {
+        return false;
+    }
+    match wildcard {
+        None => host.len() == base.len(),
+        Some(Wildcard::One) => host.len() == base.len() + 1,
+        Some(Wildcard::Subtree) => host.len() > base.len(),
+    }
+}
+
+// Exact grants are the most specific, then `*.` grants, then `**.` grants;
+// among grants of one kind the deeper name wins.
+pub fn grant_specificity(grant: impl AsRef<str>) -> (usize, usize) {
+    let (wildcard, base) = split_grant(grant.as_ref());
+    let rank = match wildcard {
+        None => 2,
+        Some(Wildcard::One) => 1,
+        Some(Wildcard::Subtree) => 0,
+    };
+    (rank, labels(base).len())
+}
+
+pub fn find_zone(host: impl AsRef<str>, zones: &[Zone]) -> Option<&Zone> {
+    zones
+        .iter()
+        .filter(|z| z.contains(host.as_ref()))
+        .max_by_key(|z| labels(&z.name).len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zones() -> Vec<Zone> {
+        vec![
+            Zone::new("example.com", "Z1"),
+            Zone::new("b.example.com.", "Z2"),
+            Zone::new("other.org", "Z3"),
+        ]
+    }
+
+    #[test]
+    fn in_zone_matches_whole_labels() {
+        assert!(in_zone("example.com", "example.com"));
+        assert!(in_zone("a.example.com", "example.com"));
+        assert!(!in_zone("evilexample.com", "example.com"));
+        assert!(!in_zone("example.com.evil.org", "example.com"));
+        assert!(!in_zone("com", "example.com"));
+        assert!(!in_zone("a.example.com", ""));
+    }
+
+    #[test]
+    fn in_zone_ignores_case_and_trailing_dots() {
+        assert!(in_zone("A.Example.COM.", "example.com"));
+        assert!(in_zone("a.example.com", "EXAMPLE.com."));
+    }
+
+    #[test]
+    fn find_zone_prefers_most_specific() {
+        let zones = zones();
+        assert_eq!(find_zone("a.example.com", &zones).unwrap().id, "Z1");
+        assert_eq!(find_zone("b.example.com", &zones).unwrap().id, "Z2");
+        assert_eq!(find_zone("a.b.example.com", &zones).unwrap().id, "Z2");
+        assert_eq!(find_zone("a.other.org", &zones).unwrap().id, "Z3");
+    }
+
+    #[test]
+    fn find_zone_rejects_lookalikes() {
+        let zones = zones();
+        assert!(find_zone("evilexample.com", &zones).is_none());
+        assert!(find_zone("ab.example.com.evil", &zones).is_none());
+        assert_eq!(find_zone("xb.example.com", &zones).unwrap().id, "Z1");
+    }
+
+    #[test]
+    fn grant_matches_exact_and_wildcards() {
+        assert!(grant_matches("host.example.com", "host.example.com"));
+        assert!(!grant_matches("host.example.com", "a.host.example.com"));
+        assert!(grant_matches("*.lab.example.com", "a.lab.example.com"));
+        assert!(!grant_matches("*.lab.example.com", "lab.example.com"));
+        assert!(!grant_matches("*.lab.example.com", "a.b.lab.example.com"));
+        assert!(grant_matches("**.lab.example.com", "a.b.lab.example.com"));
+        assert!(!grant_matches("**.lab.example.com", "lab.example.com"));
+        assert!(!grant_matches("*.example.com", "a.evilexample.com"));
+    }
+
+    #[test]
+    fn grant_matches_ignores_case_and_trailing_dots() {
+        assert!(grant_matches("Host.Example.com.", "host.example.COM"));
+        assert!(grant_matches("*.LAB.example.com", "A.lab.example.com."));
+        assert!(grant_matches("**.lab.example.com.", "a.B.Lab.Example.Com"));
+    }
+
+    #[test]
+    fn check_grant_rejects_bad_grants() {
+        assert!(check_grant("host.example.com").is_ok());
+        assert!(check_grant("*.example.com").is_ok());
+        assert!(check_grant("**.Example.Com.").is_ok());
+        assert!(check_grant("").is_err());
+        assert!(check_grant("*.").is_err());
+        assert!(check_grant("a..example.com").is_err());
+        assert!(check_grant("a.*.example.com").is_err());
+        assert!(check_grant("*a.example.com").is_err());
+        assert!(check_grant("**.bad label_.example.com").is_err());
+        assert!(check_grant("*.-lab.example.com").is_err());
+        assert!(check_grant(format!("*.{}.com", "a".repeat(64))).is_err());
+        assert!(check_grant("*.com").is_err());
+    }
+
+    #[test]
+    fn check_hostname_requires_fqdn() {
+        assert!(check_hostname("host.example.com").is_ok());
+        assert!(check_hostname("Host-1.Example.com.").is_ok());
+        assert!(check_hostname("*.lab.example.com").is_err());
+        assert!(check_hostname("**.lab.example.com").is_err());
+        assert!(check_hostname("localhost").is_err());
+        assert!(check_hostname("a..example.com").is_err());
+        assert!(check_hostname("-a.example.com").is_err());
+        assert!(check_hostname("a_b.example.com").is_err());
+        assert!(check_hostname(format!("{}.com", "a".repeat(64))).is_err());
+    }
+
+    #[test]
+    fn find_zone_ignores_case_and_trailing_dots() {
+        let zones = zones();
+        assert_eq!(find_zone("A.B.Example.Com.", &zones).unwrap().id, "Z2");
+        assert_eq!(find_zone("HOST.EXAMPLE.COM", &zones).unwrap().id, "Z1");
+    }
+}
@@ -0,0 +1,1016 @@
+use ddns_core::{
+    address::check_address,
+    audit::{AuditEntry, ANONYMOUS},
+    auth::parse_authorization,
+    client::{Client, UpdateStatus},
+    dyndns::{DynResponse, ResponseMode},
+    error::{ResponseError, ResponseErrors},
+    user::now,
+    zone::{check_hostname, normalize_name},
+};
+use http::{
+    header::{HeaderMap, HeaderValue},
+    StatusCode,
+};
+use lambda_http::{Body, IntoResponse, Request, RequestExt, Response, StrMap};
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    net::IpAddr,
+    str::FromStr,
+};
+
+const MAX_HOSTNAMES: usize = 20;
+
+pub async fn nic(
+    client: &Client,
+    request: &Request,
+    source_ip: Option<IpAddr>,
+    mode: ResponseMode,
+) -> Response<Body> {
+    let hostnames = split_hostnames(&request.query_string_parameters());
+    let mut logged_in = None;
+    let result = update(client, request, source_ip, &mut logged_in).await;
+    let lines = match &result {
+        Ok((req, statuses)) => DynResponse::from_statuses(&req.hostnames, statuses, &req.ips),
+        Err(e) => DynResponse::from_error(e, &hostnames),
+    };
+    client
+        .audit(audit_entry(
+            request,
+            source_ip,
+            logged_in,
+            hostnames,
+            &lines,
+            result.as_ref().err(),
+        ))
+        .await;
+    match (result, mode) {
+        (Ok(_), ResponseMode::Json) => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/plain")
+            .body(Body::from("OK"))
+            .expect("unable to create response"),
+        (Err(e), ResponseMode::Json) => e.into_response(),
+        (_, ResponseMode::Dyn) => lines.into_response(),
+    }
+}
+
+// Built from the raw request so that attempts which fail to parse are recorded
+// with whatever they did send. Only `logged_in`, the user whose credentials
+// were accepted, gets the event in their own chain.
+fn audit_entry(
+    request: &Request,
+    source_ip: Option<IpAddr>,
+    logged_in: Option<String>,
+    hostnames: Vec<String>,
+    lines: &DynResponse,
+    error: Option<&ResponseError>,
+) -> AuditEntry {
+    let headers = request.headers();
+    let queries = request.query_string_parameters();
+    let (username, claimed_username) = match logged_in {
+        Some(username) => (username, None),
+        None => match headers.get("Authorization").map(parse_authorization) {
+            Some(Ok(creds)) => (ANONYMOUS.into(), Some(creds.username)),
+            _ => (
+                ANONYMOUS.into(),
+                queries.get("username").map(|u| u.to_owned()),
+            ),
+        },
+    };
+    let mut requested = split_query(&queries, "myip");
+    requested.extend(split_query(&queries, "myipv6"));
+    AuditEntry {
+        at: now(),
+        username,
+        claimed_username,
+        hostnames,
+        requested_ip: if requested.is_empty() {
+            None
+        } else {
+            Some(requested.join(","))
+        },
+        source_ip: source_ip.map(|ip| format!("{}", ip)),
+        user_agent: headers
+            .get("User-Agent")
+            .and_then(|u| u.to_str().ok())
+            .map(|u| u.to_owned()),
+        outcome: lines.lines().iter().map(|l| format!("{}", l)).collect(),
+        error: error.map(|e| e.kind().to_owned()),
+    }
+}
+
+// Sets `logged_in` once the credentials have been accepted, even if the
+// request fails after that.
+async fn update(
+    client: &Client,
+    request: &Request,
+    source_ip: Option<IpAddr>,
+    logged_in: &mut Option<String>,
+) -> Result<(NicRequest, HashMap<String, UpdateStatus>), ResponseError> {
+    let mut req = parse_request(request, source_ip)?;
+    let user = client
+        .validate_user(
+            &req.username,
+            &req.password,
+            &req.user_agent,
+            source_ip,
+            &req.hostnames,
+        )
+        .await;
+    match &user {
+        Ok(user) => *logged_in = Some(user.username().to_owned()),
+        Err(e) if !is_login_failure(e) => *logged_in = Some(req.username.clone()),
+        Err(_) => {}
+    }
+    let user = user?;
+    // Whether reserved addresses are allowed depends on the user.
+    if client.address_policy().restricts(&user) {
+        mem::take(&mut req.reserved).into_result(())?;
+    }
+    let statuses = client
+        .update_hostnames(&user, &req.hostnames, &req.ips, &req.user_agent)
+        .await?;
+    Ok((req, statuses))
+}
+
+#[derive(Default)]
+struct NicRequest {
+    hostnames: Vec<String>,
+    ips: Vec<IpAddr>,
+    // Why each reserved address in `ips` would be refused.
+    reserved: ResponseErrors,
+    user_agent: String,
+    username: String,
+    password: String,
+}
+
+fn parse_request(
+    request: &Request,
+    source_ip: Option<IpAddr>,
+) -> Result<NicRequest, ResponseErrors> {
+    let mut errs = ResponseErrors::default();
+    let mut req = NicRequest::default();
+
+    let headers = request.headers();
+
+    match headers.get_header_value("User-Agent") {
+        Ok(u) => match u.to_str() {
+            Ok(agent) => req.user_agent = agent.into(),
+            Err(e) => errs.add(e.into()),
+        },
+        Err(e) => errs.add(e),
+    };
+
+    let queries = request.query_string_parameters();
+
+    // An update token in the query stands in for the password, with the
+    // username from the query too, for clients that cannot set an
+    // Authorization header. A request with both is refused rather than one
+    // quietly winning over the other.
+    match (
+        headers.get_header_value("Authorization"),
+        queries.get("token"),
+    ) {
+        (Ok(_), Some(_)) => errs.add(ResponseError::InvalidQuery(
+            "token".into(),
+            "cannot be sent with an Authorization header".into(),
+        )),
+        (Ok(a), None) => match parse_authorization(a) {
+            Ok(creds) => {
+                req.username = creds.username;
+                req.password = creds.password;
+            }
+            Err(e) => errs.add(e),
+        },
+        (Err(_), Some(token)) => {
+            req.password = token.to_owned();
+            match queries.get("username") {
+                Some(username) => req.username = username.to_owned(),
+                None => errs.add(ResponseError::MissingQuery("username".into())),
+            }
+        }
+        (Err(e), None) => errs.add(e),
+    };
+
+    let hostnames = split_hostnames(&queries);
+    if hostnames.is_empty() {
+        errs.add(ResponseError::MissingQuery("hostname".into()));
+    } else if hostnames.len() > MAX_HOSTNAMES {
+        errs.add(ResponseError::InvalidQuery(
+            "hostname".into(),
+            format!("more than {} entries", MAX_HOSTNAMES),
+        ));
+    } else {
+        let mut set = HashSet::new();
+        for h in &hostnames {
+            if !set.contains(h) {
+                set.insert(h);
+            } else {
+                errs.add(ResponseError::InvalidQuery(
+                    "hostname".into(),
+                    "duplicate entries".into(),
+                ));
+                break;
+            }
+        }
+        // Grants like `*.example.com` are not hostnames, and must not match
+        // themselves.
+        for h in &hostnames {
+            if check_hostname(h).is_err() {
+                errs.add(ResponseError::InvalidHostname(h.to_owned()));
+            }
+        }
+    }
+    req.hostnames = hostnames;
+
+    let mut found_ip = false;
+    for key in &["myip", "myipv6"] {
+        for raw in split_query(&queries, key) {
+            found_ip = true;
+            match IpAddr::from_str(&raw) {
+                Ok(IpAddr::V4(_)) if *key == "myipv6" => {
+                    errs.add(ResponseError::InvalidQuery(
+                        (*key).into(),
+                        format!("{} is not a valid IPv6 address", raw),
+                    ));
+                }
+                Ok(ip) => {
+                    if !req.ips.contains(&ip) {
+                        req.ips.push(ip);
+                        if let Err(e) = check_address(key, ip) {
+                            req.reserved.add(e);
+                        }
+                    }
+                }
+                Err(_) => {
+                    errs.add(ResponseError::InvalidQuery(
+                        (*key).into(),
+                        format!("{} is not a valid IP address", raw),
+                    ));
+                }
+            };
+        }
+    }
+    if !found_ip {
+        match source_ip {
+            Some(ip) => {
+                req.ips.push(ip);
+                if let Err(e) = check_address("source address", ip) {
+                    req.reserved.add(e);
+                }
+            }
+            None => errs.add(ResponseError::MissingQuery("myip".into())),
+        }
+    }
+
+    errs.into_result(req)
+}
+
+// Errors `validate_user` returns before the credentials were accepted. Any
+// other error comes from checking what the user asked for.
+fn is_login_failure(e: &ResponseError) -> bool {
+    matches!(
+        e,
+        ResponseError::InvalidCredentials
+            | ResponseError::TooManyAttempts(_)
+            | ResponseError::DbError(_)
+            | ResponseError::Argon(_)
+    )
+}
+
+// Hostnames are compared, keyed and sent to DNS the way zones and grants
+// match them: without case or a trailing dot.
+fn split_hostnames(queries: &StrMap) -> Vec<String> {
+    split_query(queries, "hostname")
+        .iter()
+        .map(normalize_name)
+        .collect()
+}
+
+fn split_query(queries: &StrMap, key: &str) -> Vec<String> {
+    match queries.get_all(key) {
+        Some(groups) => groups
+            .into_iter()
+            .flat_map(|group| {
+                group
+                    .split(',')
+                    .map(|s| s.to_owned())
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<String>>()
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+trait HeaderMapExt {
+    fn get_header_value(&self, key: &str) -> Result<&HeaderValue, ResponseError>;
+}
+
+impl HeaderMapExt for HeaderMap {
+    fn get_header_value(&self, key: &str) -> Result<&HeaderValue, ResponseError> {
+        self.get(key)
+            .ok_or_else(|| ResponseError::MissingHeader(key.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ddns_core::{
+        audit::{AuditQuery, MemoryAuditSink},
+        dns::{MemoryDnsProvider, RecordChange, RecordSet},
+        lockout::MemoryAttemptStore,
+        password::TEST_PARAMS,
+        store::{MemoryUserStore, PutMode, UserStore, UserUpdate},
+        user::test_user,
+        zone::Zone,
+    };
+
+    const SOURCE_IP: &str = "198.51.100.7";
+
+    // A client with one user, alice, who may update host.example.com.
+    async fn client(dns: MemoryDnsProvider) -> Client {
+        let users = MemoryUserStore::new();
+        users.put_user(test_user(), PutMode::Create).await.unwrap();
+        Client::builder()
+            .user_store(users)
+            .dns_provider(dns)
+            .attempt_store(MemoryAttemptStore::new())
+            .hash_params(TEST_PARAMS)
+            .audit_sink(MemoryAuditSink::new())
+            .audit_key("key")
+            .build()
+            .unwrap()
+    }
+
+    fn dns() -> MemoryDnsProvider {
+        MemoryDnsProvider::new(vec![Zone::new("example.com", "Z1")])
+    }
+
+    fn update(username: &str, password: &str, hostname: &str, myip: &str) -> Request {
+        let mut queries = HashMap::new();
+        queries.insert("hostname".to_owned(), vec![hostname.to_owned()]);
+        queries.insert("myip".to_owned(), vec![myip.to_owned()]);
+        let creds = base64::encode(format!("{}:{}", username, password));
+        http::Request::builder()
+            .uri("/nic/update")
+            .header("User-Agent", "test/1.0")
+            .header("Authorization", format!("Basic {}", creds))
+            .body(Body::Empty)
+            .unwrap()
+            .with_query_string_parameters(queries)
+    }
+
+    // An update with the token, and the username if any, in the query.
+    fn token_update(username: Option<&str>, token: &str, hostname: &str) -> Request {
+        let mut queries = HashMap::new();
+        queries.insert("hostname".to_owned(), vec![hostname.to_owned()]);
+        queries.insert("myip".to_owned(), vec!["93.184.216.34".to_owned()]);
+        queries.insert("token".to_owned(), vec![token.to_owned()]);
+        if let Some(username) = username {
+            queries.insert("username".to_owned(), vec![username.to_owned()]);
+        }
+        http::Request::builder()
+            .uri("/nic/update")
+            .header("User-Agent", "test/1.0")
+            .body(Body::Empty)
+            .unwrap()
+            .with_query_string_parameters(queries)
+    }
+
+    async fn send(client: &Client, request: Request, mode: ResponseMode) -> (StatusCode, String) {
+        let source_ip = Some(IpAddr::from_str(SOURCE_IP).unwrap());
+        let response = nic(client, &request, source_ip, mode).await;
+        let body = String::from_utf8(response.body().as_ref().to_vec()).unwrap();
+        (response.status(), body)
+    }
+
+    #[tokio::test]
+    async fn unknown_user_looks_like_wrong_password() {
+        for mode in &[ResponseMode::Json, ResponseMode::Dyn] {
+            let client = client(dns()).await;
+            let unknown = update("mallory", "secret", "host.example.com", "93.184.216.34");
+            let wrong = update("alice", "guess", "host.example.com", "93.184.216.34");
+            let unknown = send(&client, unknown, *mode).await;
+            let wrong = send(&client, wrong, *mode).await;
+            assert_eq!(unknown, wrong, "{:?}", mode);
+        }
+    }
+
+    #[tokio::test]
+    async fn bad_credentials_are_badauth_or_401() {
+        let client = client(dns()).await;
+        let req = update("mallory", "secret", "host.example.com", "93.184.216.34");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "badauth".to_owned())
+        );
+        let req = update("alice", "guess", "host.example.com", "93.184.216.34");
+        let (status, _) = send(&client, req, ResponseMode::Json).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn repeated_failures_are_abuse_or_429() {
+        let client = client(dns()).await;
+        for _ in 0..5 {
+            let req = update("alice", "guess", "host.example.com", "93.184.216.34");
+            assert_eq!(
+                send(&client, req, ResponseMode::Dyn).await,
+                (StatusCode::OK, "badauth".to_owned())
+            );
+        }
+        // Locked out, the right password is not even checked.
+        let req = update("alice", "secret", "host.example.com", "93.184.216.34");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "abuse".to_owned())
+        );
+        let req = update("alice", "secret", "host.example.com", "93.184.216.34");
+        let (status, _) = send(&client, req, ResponseMode::Json).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn addresses_are_locked_out_across_usernames() {
+        let client = client(dns()).await;
+        for i in 0..20 {
+            let username = format!("user{}", i);
+            let req = update(&username, "guess", "host.example.com", "93.184.216.34");
+            send(&client, req, ResponseMode::Dyn).await;
+        }
+        let req = update("alice", "secret", "host.example.com", "93.184.216.34");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "abuse".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn successful_logins_are_not_counted() {
+        let client = client(dns()).await;
+        for _ in 0..25 {
+            let req = update("alice", "secret", "host.example.com", "93.184.216.34");
+            let (status, _) = send(&client, req, ResponseMode::Json).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_logins_are_not_audited_as_the_user() {
+        let client = client(dns()).await;
+        let req = update("alice", "guess", "host.example.com", "93.184.216.34");
+        send(&client, req, ResponseMode::Json).await;
+        let req = update("alice", "secret", "host.example.com", "93.184.216.34");
+        send(&client, req, ResponseMode::Json).await;
+
+        let query = AuditQuery {
+            limit: 10,
+            ..AuditQuery::default()
+        };
+        let events = client.query_audit(&query).await.unwrap().events;
+        let logged: Vec<(&str, Option<&str>)> = events
+            .iter()
+            .map(|e| {
+                (
+                    e.entry.username.as_str(),
+                    e.entry.claimed_username.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(logged, vec![(ANONYMOUS, Some("alice")), ("alice", None)]);
+        assert!(events.iter().all(|e| client.audit_intact(e)));
+    }
+
+    #[tokio::test]
+    async fn new_address_is_good() {
+        let dns = dns();
+        let client = client(dns.clone()).await;
+        let req = update("alice", "secret", "host.example.com", "93.184.216.34");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "good 93.184.216.34".to_owned())
+        );
+        assert_eq!(
+            dns.records("Z1"),
+            vec![RecordSet {
+                name: "host.example.com".into(),
+                type_: "A".into(),
+                ttl: 300,
+                values: vec!["93.184.216.34".into()],
+            }]
+        );
+        assert_eq!(dns.applied_batches().len(), 1);
+        let user = client.get_user("alice").await.unwrap();
+        assert_eq!(user.hosts()["host.example.com"].last_ip, "93.184.216.34");
+
+        let req = update("alice", "secret", "host.example.com", "93.184.216.35");
+        assert_eq!(
+            send(&client, req, ResponseMode::Json).await,
+            (StatusCode::OK, "OK".to_owned())
+        );
+        assert_eq!(
+            dns.records("Z1")[0].values,
+            vec!["93.184.216.35".to_owned()]
+        );
+    }
+
+    #[tokio::test]
+    async fn repeated_address_is_nochg_without_a_lookup() {
+        let dns = dns();
+        let client = client(dns.clone()).await;
+        let req = update("alice", "secret", "host.example.com", "93.184.216.34");
+        send(&client, req, ResponseMode::Dyn).await;
+        assert_eq!(dns.lookups(), 1);
+        let req = update("alice", "secret", "host.example.com", "93.184.216.34");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "nochg 93.184.216.34".to_owned())
+        );
+        assert_eq!(dns.lookups(), 1);
+        assert_eq!(dns.applied_batches().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn same_address_is_nochg() {
+        let dns = dns();
+        dns.insert_record(
+            "Z1",
+            RecordSet {
+                name: "host.example.com.".into(),
+                type_: "A".into(),
+                ttl: 300,
+                values: vec!["93.184.216.34".into()],
+            },
+        );
+        let client = client(dns.clone()).await;
+        let req = update("alice", "secret", "host.example.com", "93.184.216.34");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "nochg 93.184.216.34".to_owned())
+        );
+        assert!(dns.applied_batches().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stored_ttls_are_clamped_to_the_limits() {
+        let dns = dns();
+        let client = client(dns.clone()).await;
+        // Set before the minimum was raised to its default of 60.
+        let mut user = client.get_user("alice").await.unwrap();
+        user.set_ttl(Some(30));
+        client.put_user(user, PutMode::Overwrite).await.unwrap();
+        let req = update("alice", "secret", "host.example.com", "93.184.216.34");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "good 93.184.216.34".to_owned())
+        );
+        assert_eq!(dns.records("Z1")[0].ttl, 60);
+    }
+
+    #[tokio::test]
+    async fn hostnames_differing_in_case_are_duplicates() {
+        let dns = dns();
+        let client = client(dns.clone()).await;
+        let req = update(
+            "alice",
+            "secret",
+            "Host.example.com,host.example.com.",
+            "93.184.216.34",
+        );
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "numhost\nnumhost".to_owned())
+        );
+        assert!(dns.applied_batches().is_empty());
+    }
+
+    const IPV6: &str = "2606:2800:220:1:248:1893:25c8:1946";
+
+    // Alice updating host.example.com with the given address queries.
+    fn update_ips(ips: &[(&str, &str)]) -> Request {
+        let mut queries: HashMap<String, Vec<String>> = HashMap::new();
+        queries.insert("hostname".to_owned(), vec!["host.example.com".to_owned()]);
+        for (key, value) in ips {
+            queries
+                .entry((*key).to_owned())
+                .or_default()
+                .push((*value).to_owned());
+        }
+        http::Request::builder()
+            .uri("/nic/update")
+            .header("User-Agent", "test/1.0")
+            .header(
+                "Authorization",
+                format!("Basic {}", base64::encode("alice:secret")),
+            )
+            .body(Body::Empty)
+            .unwrap()
+            .with_query_string_parameters(queries)
+    }
+
+    fn record(type_: &str, values: &[&str]) -> RecordSet {
+        RecordSet {
+            name: "host.example.com".into(),
+            type_: type_.into(),
+            ttl: 300,
+            values: values.iter().map(|v| (*v).to_owned()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn myipv6_is_written_as_aaaa() {
+        let dns = dns();
+        let client = client(dns.clone()).await;
+        let req = update_ips(&[("myipv6", IPV6)]);
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, format!("good {}", IPV6))
+        );
+        assert_eq!(dns.records("Z1"), vec![record("AAAA", &[IPV6])]);
+    }
+
+    #[tokio::test]
+    async fn mixed_addresses_write_a_and_aaaa_together() {
+        let both = format!("93.184.216.34,{}", IPV6);
+        let requests = vec![
+            update_ips(&[("myip", &both)]),
+            update_ips(&[("myip", "93.184.216.34"), ("myipv6", IPV6)]),
+        ];
+        for req in requests {
+            let dns = dns();
+            let client = client(dns.clone()).await;
+            assert_eq!(
+                send(&client, req, ResponseMode::Dyn).await,
+                (StatusCode::OK, format!("good {}", both))
+            );
+            let written = vec![record("A", &["93.184.216.34"]), record("AAAA", &[IPV6])];
+            assert_eq!(dns.records("Z1"), written);
+            // Both record sets go to DNS in one batch.
+            let batches = dns.applied_batches();
+            assert_eq!(batches.len(), 1);
+            assert_eq!(batches[0].1.len(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn addresses_must_be_valid() {
+        let client = client(dns()).await;
+        let req = update_ips(&[("myipv6", "93.184.216.34")]);
+        let (status, body) = send(&client, req, ResponseMode::Json).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("myipv6"), "{}", body);
+        // Sending it again would not help, so it is not `911`.
+        let req = update_ips(&[("myipv6", "93.184.216.34")]);
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "nohost".to_owned())
+        );
+        let req = update("alice", "secret", "host.example.com", "93.184.216");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "nohost".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn ipv4_updates_leave_aaaa_records_alone() {
+        let dns = dns();
+        dns.insert_record("Z1", record("AAAA", &[IPV6]));
+        let client = client(dns.clone()).await;
+        let req = update_ips(&[("myip", "93.184.216.34")]);
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "good 93.184.216.34".to_owned())
+        );
+        assert_eq!(
+            dns.records("Z1"),
+            vec![record("AAAA", &[IPV6]), record("A", &["93.184.216.34"])]
+        );
+        assert_eq!(
+            dns.applied_batches()[0].1,
+            vec![RecordChange::Upsert(record("A", &["93.184.216.34"]))]
+        );
+    }
+
+    #[tokio::test]
+    async fn hostname_not_granted_is_nohost() {
+        let dns = dns();
+        let client = client(dns.clone()).await;
+        let req = update("alice", "secret", "other.example.com", "93.184.216.34");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "nohost".to_owned())
+        );
+        let req = update("alice", "secret", "other.example.com", "93.184.216.34");
+        let (status, _) = send(&client, req, ResponseMode::Json).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(dns.applied_batches().is_empty());
+    }
+
+    #[tokio::test]
+    async fn hostnames_not_granted_are_401() {
+        let client = client(dns()).await;
+        let req = update(
+            "alice",
+            "secret",
+            "a.example.com,b.example.com",
+            "93.184.216.34",
+        );
+        let (status, _) = send(&client, req, ResponseMode::Json).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn granted_hostnames_do_not_share_errors() {
+        let dns = dns();
+        let client = client(dns.clone()).await;
+        let req = update(
+            "alice",
+            "secret",
+            "host.example.com,other.example.com",
+            "93.184.216.34",
+        );
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "nohost".to_owned())
+        );
+        assert!(dns.applied_batches().is_empty());
+    }
+
+    #[tokio::test]
+    async fn hostname_outside_hosted_zones_is_nohost() {
+        let dns = MemoryDnsProvider::new(vec![Zone::new("example.org", "Z2")]);
+        let client = client(dns.clone()).await;
+        let req = update("alice", "secret", "host.example.com", "93.184.216.34");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "nohost".to_owned())
+        );
+        assert!(dns.applied_batches().is_empty());
+    }
+
+    #[tokio::test]
+    async fn alias_records_are_nohost() {
+        let dns = dns();
+        dns.insert_unmanaged("Z1", "host.example.com");
+        let client = client(dns.clone()).await;
+        let req = update("alice", "secret", "host.example.com", "93.184.216.34");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "nohost".to_owned())
+        );
+        let req = update("alice", "secret", "host.example.com", "93.184.216.34");
+        let (status, _) = send(&client, req, ResponseMode::Json).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(dns.applied_batches().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reserved_addresses_name_their_source() {
+        let client = client(dns()).await;
+        let req = update("alice", "secret", "host.example.com", "10.0.0.1");
+        let (status, body) = send(&client, req, ResponseMode::Json).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            body.contains("myip 10.0.0.1 is a private address"),
+            "{}",
+            body
+        );
+
+        let req = update("alice", "secret", "host.example.com", "fd00::1");
+        let (_, body) = send(&client, req, ResponseMode::Json).await;
+        assert!(
+            body.contains("myip fd00::1 is a unique local address"),
+            "{}",
+            body
+        );
+
+        // Without myip the source address is used, and named as such.
+        let req = update("alice", "secret", "host.example.com", "");
+        let (_, body) = send(&client, req, ResponseMode::Json).await;
+        assert!(
+            body.contains("source address 198.51.100.7 is a documentation address"),
+            "{}",
+            body
+        );
+    }
+
+    #[tokio::test]
+    async fn reserved_addresses_are_nohost_in_dyn_mode() {
+        let client = client(dns()).await;
+        let req = update("alice", "secret", "host.example.com", "192.168.1.1");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "nohost".to_owned())
+        );
+        // Audited like any other bad query.
+        let query = AuditQuery {
+            limit: 10,
+            ..AuditQuery::default()
+        };
+        let events = client.query_audit(&query).await.unwrap().events;
+        assert_eq!(events[0].entry.error.as_deref(), Some("InvalidQuery"));
+    }
+
+    fn request(hostname: &str) -> Request {
+        let mut queries = HashMap::new();
+        queries.insert("hostname".to_owned(), vec![hostname.to_owned()]);
+        queries.insert("myip".to_owned(), vec!["93.184.216.34".to_owned()]);
+        http::Request::builder()
+            .uri("/nic/update")
+            .header("User-Agent", "test/1.0")
+            .header("Authorization", "Basic dXNlcjpwYXNz")
+            .body(Body::Empty)
+            .unwrap()
+            .with_query_string_parameters(queries)
+    }
+
+    fn errors(request: &Request) -> Vec<ResponseError> {
+        match parse_request(request, None).map_err(ResponseError::from) {
+            Ok(_) => Vec::new(),
+            Err(ResponseError::MultipleErrors(es)) => es,
+            Err(e) => vec![e],
+        }
+    }
+
+    #[test]
+    fn accepts_hostnames() {
+        let req = parse_request(&request("host.example.com"), None)
+            .ok()
+            .unwrap();
+        assert_eq!(req.hostnames, vec!["host.example.com".to_owned()]);
+        assert_eq!(req.username, "user");
+    }
+
+    #[test]
+    fn hostnames_are_normalized() {
+        let req = parse_request(&request("Host.Example.com."), None)
+            .ok()
+            .unwrap();
+        assert_eq!(req.hostnames, vec!["host.example.com".to_owned()]);
+        // The same record twice would make DNS refuse the whole batch.
+        match errors(&request("A.example.com,a.example.com.")).as_slice() {
+            [ResponseError::InvalidQuery(key, _)] => assert_eq!(key, "hostname"),
+            es => panic!("unexpected errors: {:?}", es),
+        }
+    }
+
+    #[test]
+    fn rejects_wildcards() {
+        for host in &["*.lab.example.com", "**.lab.example.com", "a.*.example.com"] {
+            match errors(&request(host)).as_slice() {
+                [ResponseError::InvalidHostname(h)] => assert_eq!(h, host),
+                es => panic!("unexpected errors for {}: {:?}", host, es),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_labels() {
+        for host in &[
+            "localhost",
+            "a..example.com",
+            "-a.example.com",
+            "a b.example.com",
+        ] {
+            match errors(&request(host)).as_slice() {
+                [ResponseError::InvalidHostname(h)] => assert_eq!(h, host),
+                es => panic!("unexpected errors for {}: {:?}", host, es),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_hostnames_are_notfqdn() {
+        let req = request("good.example.com,*.lab.example.com");
+        let hostnames = split_hostnames(&req.query_string_parameters());
+        let err = ResponseError::from(parse_request(&req, None).err().unwrap());
+        let lines = DynResponse::from_error(&err, &hostnames);
+        let lines: Vec<String> = lines.lines().iter().map(|l| format!("{}", l)).collect();
+        assert_eq!(lines, vec!["notfqdn"]);
+    }
+
+    // Alice may also update other.example.com, but her token only host.
+    async fn token_client() -> (Client, String) {
+        let client = client(dns()).await;
+        let mut update = UserUpdate::default();
+        update.add_domains.insert("other.example.com".to_owned());
+        client.update_user("alice", update).await.unwrap();
+        let mut hostnames = HashSet::new();
+        hostnames.insert("host.example.com".to_owned());
+        let token = client.create_token("alice", hostnames).await.unwrap();
+        (client, token.token)
+    }
+
+    #[tokio::test]
+    async fn tokens_update_their_hostnames() {
+        let (client, token) = token_client().await;
+        let req = update("alice", &token, "host.example.com", "93.184.216.34");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "good 93.184.216.34".to_owned())
+        );
+        let user = client.get_user("alice").await.unwrap();
+        assert!(user.token_infos()[0].last_used_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn tokens_are_refused_other_hostnames() {
+        let (client, token) = token_client().await;
+        let req = update("alice", &token, "other.example.com", "93.184.216.34");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "nohost".to_owned())
+        );
+        let req = update("alice", &token, "other.example.com", "93.184.216.34");
+        let (status, _) = send(&client, req, ResponseMode::Json).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        // The password still may.
+        let req = update("alice", "secret", "other.example.com", "93.184.216.34");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "good 93.184.216.34".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_token_secrets_are_badauth() {
+        let (client, token) = token_client().await;
+        let (id, _) = token.split_at(token.find('.').unwrap());
+        let req = update(
+            "alice",
+            &format!("{}.guess", id),
+            "host.example.com",
+            "93.184.216.34",
+        );
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "badauth".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn query_tokens_update_their_hostnames() {
+        let (client, token) = token_client().await;
+        let req = token_update(Some("alice"), &token, "host.example.com");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "good 93.184.216.34".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn query_tokens_need_a_username() {
+        let (client, token) = token_client().await;
+        let req = token_update(None, &token, "host.example.com");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "nohost".to_owned())
+        );
+        let req = token_update(None, &token, "host.example.com");
+        let (status, body) = send(&client, req, ResponseMode::Json).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("username"), "{}", body);
+    }
+
+    // Even with the right password in the header, the request is refused
+    // rather than updated with one of the two.
+    #[tokio::test]
+    async fn query_tokens_are_refused_with_an_authorization_header() {
+        let (client, token) = token_client().await;
+        for mode in &[ResponseMode::Dyn, ResponseMode::Json] {
+            let mut req = token_update(Some("alice"), &token, "host.example.com");
+            let creds = base64::encode("alice:secret");
+            req.headers_mut()
+                .insert("Authorization", format!("Basic {}", creds).parse().unwrap());
+            let (status, body) = send(&client, req, *mode).await;
+            match mode {
+                ResponseMode::Dyn => assert_eq!(body, "nohost"),
+                ResponseMode::Json => {
+                    assert_eq!(status, StatusCode::BAD_REQUEST);
+                    assert!(body.contains("token"), "{}", body);
+                }
+            }
+        }
+        let user = client.get_user("alice").await.unwrap();
+        assert!(user.hosts().is_empty());
+        assert!(user.token_infos()[0].last_used_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn query_tokens_are_refused_other_hostnames() {
+        let (client, token) = token_client().await;
+        let req = token_update(Some("alice"), &token, "other.example.com");
+        assert_eq!(
+            send(&client, req, ResponseMode::Dyn).await,
+            (StatusCode::OK, "nohost".to_owned())
+        );
+        let req = token_update(Some("alice"), &token, "other.example.com");
+        let (status, _) = send(&client, req, ResponseMode::Json).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,461 @@
+mod settings;
+
+use audit_log::audit_log;
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    response::{IntoResponse as _, Response as AxumResponse},
+    routing::{delete, get, post},
+    Router,
+};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use change_password::change_password;
+use create_user::create_user;
+use ddns_core::{
+    auth::secrets_match,
+    client::Client,
+    config::ClientBuilder,
+    dyndns::ResponseMode,
+    error::ResponseError,
+    password::{self, HashParams},
+    source::client_ip,
+};
+use http::header::HeaderMap;
+use lambda_http::{Body, IntoResponse, Request, RequestExt, Response};
+use nic::nic;
+use settings::Config;
+use std::{
+    collections::HashMap,
+    env,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokens::tokens;
+use user_admin::user_admin;
+
+type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+struct AppState {
+    client: Client,
+    response_mode: ResponseMode,
+    trusted_proxies: usize,
+    admin_api_key: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    ddns_core::logging::init();
+    if env::args().nth(1).as_deref() == Some("calibrate") {
+        return calibrate(env::args().skip(2).collect());
+    }
+    let config = Config::load(env::args().nth(1).or_else(|| env::var("DDNS_CONFIG").ok()))?;
+    let client = ClientBuilder::from_settings(config.client)?.build()?;
+
+    let app = app(AppState {
+        client,
+        response_mode: config.response_mode,
+        trusted_proxies: config.trusted_proxies,
+        admin_api_key: config.admin_api_key,
+    });
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+
+    let handle = Handle::new();
+    tokio::spawn(shutdown(handle.clone()));
+    match config.tls {
+        Some((cert, key)) => {
+            let tls = RustlsConfig::from_pem_file(cert, key).await?;
+            axum_server::bind_rustls(config.listen, tls)
+                .handle(handle)
+                .serve(service)
+                .await?
+        }
+        None => {
+            axum_server::bind(config.listen)
+                .handle(handle)
+                .serve(service)
+                .await?
+        }
+    };
+    Ok(())
+}
+
+// The admin routes only exist when there is a key to check them against.
+fn app(state: AppState) -> Router {
+    let mut app = Router::new()
+        .route("/nic/update", get(update))
+        .route("/nic/password", post(password))
+        .route("/nic/tokens", get(user_tokens).post(user_tokens))
+        .route("/nic/tokens/:id", delete(user_token));
+    if state.admin_api_key.is_some() {
+        app = app
+            .route("/user", post(create).get(admin))
+            .route(
+                "/user/:username",
+                get(admin_user).patch(admin_user).delete(admin_user),
+            )
+            .route(
+                "/user/:username/:collection",
+                get(admin_user).post(admin_user),
+            )
+            .route("/user/:username/:collection/:id", delete(admin_user))
+            .route("/audit", get(audit));
+    } else {
+        log::warn!("admin_api_key is not set, the /user and /audit routes are disabled");
+    }
+    app.with_state(Arc::new(state))
+}
+
+// `server calibrate [target_ms] [mem_cost_kib]` finds the argon2 time cost
+// that makes one hash take about `target_ms` on this machine.
+fn calibrate(args: Vec<String>) -> Result<(), Error> {
+    let target: u64 = match args.first() {
+        Some(ms) => ms.parse()?,
+        None => 500,
+    };
+    let defaults = HashParams::default();
+    let mem_cost: u32 = match args.get(1) {
+        Some(kib) => kib.parse()?,
+        None => defaults.mem_cost,
+    };
+    let (params, took) =
+        password::calibrate(Duration::from_millis(target), mem_cost, defaults.lanes)?;
+    println!("argon2_mem_cost = {}", params.mem_cost);
+    println!("argon2_time_cost = {}", params.time_cost);
+    println!("argon2_lanes = {}", params.lanes);
+    println!("# one hash took {} ms", took.as_millis());
+    Ok(())
+}
+
+// Stops accepting connections on SIGINT or SIGTERM and gives in-flight
+// requests time to finish.
+async fn shutdown(handle: Handle) {
+    let interrupt = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("unable to listen for ctrl-c");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("unable to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+    handle.graceful_shutdown(Some(Duration::from_secs(30)));
+}
+
+async fn update(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: http::Request<hyper::Body>,
+) -> AxumResponse {
+    let request = match to_lambda_request(request, HashMap::new()).await {
+        Ok(r) => r,
+        Err(e) => return to_axum_response(e.into_response()),
+    };
+    let source_ip = source_ip(&state, &request, addr);
+    to_axum_response(nic(&state.client, &request, source_ip, state.response_mode).await)
+}
+
+async fn password(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: http::Request<hyper::Body>,
+) -> AxumResponse {
+    match to_lambda_request(request, HashMap::new()).await {
+        Ok(request) => {
+            let source_ip = source_ip(&state, &request, addr);
+            to_axum_response(change_password(&state.client, request, source_ip).await)
+        }
+        Err(e) => to_axum_response(e.into_response()),
+    }
+}
+
+async fn user_tokens(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: http::Request<hyper::Body>,
+) -> AxumResponse {
+    match to_lambda_request(request, HashMap::new()).await {
+        Ok(request) => {
+            let source_ip = source_ip(&state, &request, addr);
+            to_axum_response(tokens(&state.client, request, source_ip).await)
+        }
+        Err(e) => to_axum_response(e.into_response()),
+    }
+}
+
+async fn user_token(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    request: http::Request<hyper::Body>,
+) -> AxumResponse {
+    let mut path = HashMap::new();
+    path.insert("id".to_owned(), id);
+    match to_lambda_request(request, path).await {
+        Ok(request) => {
+            let source_ip = source_ip(&state, &request, addr);
+            to_axum_response(tokens(&state.client, request, source_ip).await)
+        }
+        Err(e) => to_axum_response(e.into_response()),
+    }
+}
+
+async fn create(
+    State(state): State<Arc<AppState>>,
+    request: http::Request<hyper::Body>,
+) -> AxumResponse {
+    match admin_request(&state, request, HashMap::new()).await {
+        Ok(request) => to_axum_response(create_user(&state.client, request).await),
+        Err(e) => to_axum_response(e.into_response()),
+    }
+}
+
+async fn admin(
+    State(state): State<Arc<AppState>>,
+    request: http::Request<hyper::Body>,
+) -> AxumResponse {
+    match admin_request(&state, request, HashMap::new()).await {
+        Ok(request) => to_axum_response(user_admin(&state.client, request).await),
+        Err(e) => to_axum_response(e.into_response()),
+    }
+}
+
+async fn audit(
+    State(state): State<Arc<AppState>>,
+    request: http::Request<hyper::Body>,
+) -> AxumResponse {
+    match admin_request(&state, request, HashMap::new()).await {
+        Ok(request) => to_axum_response(audit_log(&state.client, request).await),
+        Err(e) => to_axum_response(e.into_response()),
+    }
+}
+
+// Passes the path parameters on under the names API Gateway gives them.
+async fn admin_user(
+    State(state): State<Arc<AppState>>,
+    Path(path): Path<HashMap<String, String>>,
+    request: http::Request<hyper::Body>,
+) -> AxumResponse {
+    match admin_request(&state, request, path).await {
+        Ok(request) => to_axum_response(user_admin(&state.client, request).await),
+        Err(e) => to_axum_response(e.into_response()),
+    }
+}
+
+async fn admin_request(
+    state: &AppState,
+    request: http::Request<hyper::Body>,
+    path: HashMap<String, String>,
+) -> Result<Request, ResponseError> {
+    if !api_key_matches(request.headers(), &state.admin_api_key) {
+        return Err(ResponseError::InvalidCredentials);
+    }
+    to_lambda_request(request, path).await
+}
+
+fn api_key_matches(headers: &HeaderMap, key: &Option<String>) -> bool {
+    match (headers.get("x-api-key").and_then(|v| v.to_str().ok()), key) {
+        (Some(given), Some(key)) => secrets_match(given, key),
+        _ => false,
+    }
+}
+
+// Listening on `[::]` reports IPv4 callers as IPv4-mapped IPv6 addresses.
+// The address of whoever is calling, for the lockout and for updates that do
+// not give one.
+fn source_ip(state: &AppState, request: &Request, addr: SocketAddr) -> Option<IpAddr> {
+    client_ip(
+        request.headers(),
+        Some(canonical_ip(addr.ip())),
+        state.trusted_proxies,
+    )
+}
+
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(v6),
+        },
+        ip => ip,
+    }
+}
+
+// The handlers are written against lambda_http's request type, so the server
+// builds the same request API Gateway would hand to the function.
+async fn to_lambda_request(
+    request: http::Request<hyper::Body>,
+    path: HashMap<String, String>,
+) -> Result<Request, ResponseError> {
+    let (parts, body) = request.into_parts();
+    let bytes = hyper::body::to_bytes(body)
+        .await
+        .map_err(|e| ResponseError::ParseError(format!("{}", e)))?;
+    let body = if bytes.is_empty() {
+        Body::Empty
+    } else {
+        match String::from_utf8(bytes.to_vec()) {
+            Ok(text) => Body::Text(text),
+            Err(e) => Body::Binary(e.into_bytes()),
+        }
+    };
+    let mut queries: HashMap<String, Vec<String>> = HashMap::new();
+    if let Some(query) = parts.uri.query() {
+        let pairs: Vec<(String, String)> = serde_urlencoded::from_str(query)
+            .map_err(|e| ResponseError::ParseError(format!("{}", e)))?;
+        for (key, value) in pairs {
+            queries.entry(key).or_default().push(value);
+        }
+    }
+    Ok(Request::from_parts(parts, body)
+        .with_query_string_parameters(queries)
+        .with_path_parameters(path))
+}
+
+fn to_axum_response(response: Response<Body>) -> AxumResponse {
+    let (parts, body) = response.into_parts();
+    let body = match body {
+        Body::Empty => hyper::Body::empty(),
+        Body::Text(text) => hyper::Body::from(text),
+        Body::Binary(bytes) => hyper::Body::from(bytes),
+    };
+    http::Response::from_parts(parts, body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ddns_core::{
+        dns::MemoryDnsProvider,
+        lockout::MemoryAttemptStore,
+        password::{Hasher, TEST_PARAMS},
+        store::{MemoryUserStore, PutMode, UserStore},
+        user::test_user,
+        zone::Zone,
+    };
+    use http::StatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    fn state(admin_api_key: Option<&str>) -> AppState {
+        state_with(MemoryUserStore::new(), TEST_PARAMS, admin_api_key)
+    }
+
+    fn state_with(
+        users: MemoryUserStore,
+        params: HashParams,
+        admin_api_key: Option<&str>,
+    ) -> AppState {
+        let client = Client::builder()
+            .user_store(users)
+            .dns_provider(MemoryDnsProvider::new(vec![Zone::new("example.com", "Z1")]))
+            .attempt_store(MemoryAttemptStore::new())
+            .hash_params(params)
+            .build()
+            .unwrap();
+        AppState {
+            client,
+            response_mode: ResponseMode::Json,
+            trusted_proxies: 0,
+            admin_api_key: admin_api_key.map(|k| k.to_owned()),
+        }
+    }
+
+    async fn list_users(state: AppState, api_key: Option<&str>) -> StatusCode {
+        let mut request = http::Request::builder().uri("/user");
+        if let Some(key) = api_key {
+            request = request.header("x-api-key", key);
+        }
+        let request = request.body(hyper::Body::empty()).unwrap();
+        app(state).oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn admin_routes_need_a_configured_key() {
+        assert_eq!(list_users(state(None), None).await, StatusCode::NOT_FOUND);
+        assert_eq!(
+            list_users(state(None), Some("change-me")).await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn admin_routes_need_the_right_key() {
+        let key = Some("change-me");
+        assert_eq!(list_users(state(key), None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            list_users(state(key), Some("change-mf")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            list_users(state(key), Some("change")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(list_users(state(key), key).await, StatusCode::OK);
+    }
+
+    // Alice updating host.example.com, as a caller connected from 198.51.100.7.
+    fn update_request() -> http::Request<hyper::Body> {
+        let mut request = http::Request::builder()
+            .uri("/nic/update?hostname=host.example.com&myip=93.184.216.34")
+            .header("User-Agent", "test/1.0")
+            // alice:secret
+            .header("Authorization", "Basic YWxpY2U6c2VjcmV0")
+            .body(hyper::Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([198, 51, 100, 7], 40000))));
+        request
+    }
+
+    // Passwords are checked on tokio's blocking threads, so even on this
+    // test's single thread other requests are answered while logins are.
+    #[tokio::test]
+    async fn logins_do_not_hold_up_other_requests() {
+        let params = HashParams {
+            mem_cost: 16384,
+            time_cost: 2,
+            lanes: 1,
+        };
+        let users = MemoryUserStore::new();
+        let mut user = test_user();
+        user.set_password("secret", &Hasher::new(params)).unwrap();
+        users.put_user(user, PutMode::Create).await.unwrap();
+        let app = app(state_with(users, params, Some("change-me")));
+
+        let finished = Arc::new(AtomicUsize::new(0));
+        let logins: Vec<_> = (0..4)
+            .map(|_| {
+                let app = app.clone();
+                let finished = finished.clone();
+                tokio::spawn(async move {
+                    let status = app.oneshot(update_request()).await.unwrap().status();
+                    finished.fetch_add(1, Ordering::SeqCst);
+                    status
+                })
+            })
+            .collect();
+        // Lets every login get as far as checking its password.
+        tokio::task::yield_now().await;
+
+        let request = http::Request::builder()
+            .uri("/user")
+            .header("x-api-key", "change-me")
+            .body(hyper::Body::empty())
+            .unwrap();
+        assert_eq!(app.oneshot(request).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(finished.load(Ordering::SeqCst), 0);
+        for login in logins {
+            assert_eq!(login.await.unwrap(), StatusCode::OK);
+        }
+    }
+}
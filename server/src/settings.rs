@@ -0,0 +1,131 @@
+use ddns_core::{config::Settings, dyndns::ResponseMode, error::ConfigError};
+use serde::Deserialize;
+use std::{env, fs, net::SocketAddr, path::Path};
+
+// The server reads the same file as the client settings, with its own values
+// under a `[server]` table. The two are read apart so each can refuse keys it
+// does not know.
+#[derive(Debug, Clone, Default)]
+struct File {
+    client: Settings,
+    server: ServerSettings,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    pub listen: Option<String>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub admin_api_key: Option<String>,
+    pub response_mode: Option<String>,
+    pub trusted_proxies: Option<usize>,
+}
+
+pub struct Config {
+    pub client: Settings,
+    pub listen: SocketAddr,
+    pub tls: Option<(String, String)>,
+    pub admin_api_key: Option<String>,
+    pub response_mode: ResponseMode,
+    pub trusted_proxies: usize,
+}
+
+impl Config {
+    pub fn load(path: Option<String>) -> Result<Self, ConfigError> {
+        let File {
+            mut client,
+            mut server,
+        } = match path {
+            Some(path) => read_file(&path)?,
+            None => File::default(),
+        };
+        client.merge_env()?;
+        server.merge_env()?;
+
+        let listen = server.listen.unwrap_or_else(|| "0.0.0.0:8080".into());
+        let listen = listen.parse().map_err(|_| {
+            ConfigError::Invalid("listen".into(), format!("{} is not an address", listen))
+        })?;
+        let tls = match (server.tls_cert, server.tls_key) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            (Some(_), None) => return Err(ConfigError::Missing("tls_key".into())),
+            (None, Some(_)) => return Err(ConfigError::Missing("tls_cert".into())),
+        };
+        let response_mode = match server.response_mode {
+            Some(mode) => mode
+                .parse()
+                .map_err(|e| ConfigError::Invalid("response_mode".into(), e))?,
+            None => ResponseMode::default(),
+        };
+        Ok(Config {
+            client,
+            listen,
+            tls,
+            admin_api_key: server.admin_api_key,
+            response_mode,
+            trusted_proxies: server.trusted_proxies.unwrap_or(0),
+        })
+    }
+}
+
+fn read_file(path: impl AsRef<Path>) -> Result<File, ConfigError> {
+    let raw = fs::read_to_string(path.as_ref())
+        .map_err(|e| ConfigError::File(format!("{}: {}", path.as_ref().display(), e)))?;
+    parse_file(&raw).map_err(|e| ConfigError::File(format!("{}: {}", path.as_ref().display(), e)))
+}
+
+fn parse_file(raw: &str) -> Result<File, toml::de::Error> {
+    let mut table: toml::value::Table = toml::from_str(raw)?;
+    let server = match table.remove("server") {
+        Some(server) => server.try_into()?,
+        None => ServerSettings::default(),
+    };
+    Ok(File {
+        client: toml::Value::Table(table).try_into()?,
+        server,
+    })
+}
+
+impl ServerSettings {
+    fn merge_env(&mut self) -> Result<(), ConfigError> {
+        env_string("LISTEN_ADDR", &mut self.listen);
+        env_string("TLS_CERT", &mut self.tls_cert);
+        env_string("TLS_KEY", &mut self.tls_key);
+        env_string("ADMIN_API_KEY", &mut self.admin_api_key);
+        env_string("NIC_RESPONSE_MODE", &mut self.response_mode);
+        if let Ok(raw) = env::var("NIC_TRUSTED_PROXIES") {
+            self.trusted_proxies = Some(raw.parse().map_err(|_| {
+                ConfigError::Invalid(
+                    "NIC_TRUSTED_PROXIES".into(),
+                    format!("{} is not a number", raw),
+                )
+            })?);
+        }
+        Ok(())
+    }
+}
+
+fn env_string(key: &str, value: &mut Option<String>) {
+    if let Ok(v) = env::var(key) {
+        if !v.is_empty() {
+            *value = Some(v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_holds_client_and_server_settings() {
+        let file =
+            parse_file("user_store = \"memory\"\n[server]\nlisten = \"127.0.0.1:8080\"\n").unwrap();
+        assert_eq!(file.client.user_store.as_deref(), Some("memory"));
+        assert_eq!(file.server.listen.as_deref(), Some("127.0.0.1:8080"));
+        assert!(parse_file("users_table = \"users\"").is_err());
+        assert!(parse_file("[server]\nlisten_addr = \"\"").is_err());
+    }
+}
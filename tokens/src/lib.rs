@@ -0,0 +1,78 @@
+use ddns_core::{auth::parse_authorization, client::Client, error::ResponseError};
+use http::{Method, StatusCode};
+use lambda_http::{Body, IntoResponse, Request, RequestExt, Response};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, net::IpAddr};
+
+// Serves `GET` and `POST /nic/tokens` and `DELETE /nic/tokens/{id}` for the
+// user in the Authorization header. Only the account password is accepted, so
+// a leaked token cannot be used to make more.
+pub async fn tokens(
+    client: &Client,
+    request: Request,
+    source_ip: Option<IpAddr>,
+) -> Response<Body> {
+    match handle(client, &request, source_ip).await {
+        Ok(resp) => resp,
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn handle(
+    client: &Client,
+    request: &Request,
+    source_ip: Option<IpAddr>,
+) -> Result<Response<Body>, ResponseError> {
+    let creds = match request.headers().get("Authorization") {
+        Some(a) => parse_authorization(a)?,
+        None => return Err(ResponseError::MissingHeader("Authorization".into())),
+    };
+    let user = client
+        .authenticate(&creds.username, &creds.password, source_ip)
+        .await?;
+    let id = request.path_parameters().get("id").map(|i| i.to_owned());
+    match (request.method(), id) {
+        (&Method::GET, None) => Ok(json_response(StatusCode::OK, &user.token_infos())),
+        (&Method::POST, None) => {
+            let hostnames = parse_request(request)?;
+            let token = client.create_token(user.username(), hostnames).await?;
+            Ok(json_response(StatusCode::CREATED, &token))
+        }
+        (&Method::DELETE, Some(id)) => {
+            client.revoke_token(user.username(), &id).await?;
+            Ok(Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::from(()))
+                .expect("unable to create response"))
+        }
+        _ => Err(ResponseError::NotFound(format!(
+            "{} {}",
+            request.method(),
+            request.uri().path()
+        ))),
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenRequest {
+    #[serde(default)]
+    hostnames: HashSet<String>,
+}
+
+fn parse_request(request: &Request) -> Result<HashSet<String>, ResponseError> {
+    match request.payload::<TokenRequest>() {
+        Ok(Some(r)) if !r.hostnames.is_empty() => Ok(r.hostnames),
+        Ok(_) => Err(ResponseError::MissingField("hostnames".into())),
+        Err(e) => Err(ResponseError::ParseError(format!("{}", e))),
+    }
+}
+
+fn json_response(status: StatusCode, body: &impl Serialize) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            serde_json::to_string(body).expect("unable to turn body into json"),
+        ))
+        .expect("unable to create response")
+}
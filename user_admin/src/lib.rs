@@ -0,0 +1,457 @@
+use ddns_core::{
+    client::Client,
+    error::{ResponseError, ResponseErrors},
+    store::UserUpdate,
+    user::{check_password, UserInfo},
+    zone::check_grant,
+};
+use http::{Method, StatusCode};
+use lambda_http::{Body, IntoResponse, Request, RequestExt, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 100;
+
+enum Resource {
+    Users,
+    User(String),
+    Hosts(String),
+    Tokens(String),
+    Token(String, String),
+}
+
+// Routes are told apart by their path parameters alone: `collection` is
+// `hosts` or `tokens` in `/user/{username}/{collection}` and
+// `/user/{username}/{collection}/{id}`.
+fn resource(request: &Request) -> Option<Resource> {
+    let params = request.path_parameters();
+    let username = params.get("username").map(|u| u.to_owned());
+    match (username, params.get("collection"), params.get("id")) {
+        (None, None, None) => Some(Resource::Users),
+        (Some(username), None, None) => Some(Resource::User(username)),
+        (Some(username), Some("hosts"), None) => Some(Resource::Hosts(username)),
+        (Some(username), Some("tokens"), None) => Some(Resource::Tokens(username)),
+        (Some(username), Some("tokens"), Some(id)) => {
+            Some(Resource::Token(username, id.to_owned()))
+        }
+        _ => None,
+    }
+}
+
+// Serves `GET /user`, `GET`, `PATCH` and `DELETE /user/{username}`,
+// `GET /user/{username}/hosts`, `GET` and `POST /user/{username}/tokens`, and
+// `DELETE /user/{username}/tokens/{id}`.
+pub async fn user_admin(client: &Client, request: Request) -> Response<Body> {
+    let result = match (request.method(), resource(&request)) {
+        (&Method::GET, Some(Resource::Users)) => list_users(client, &request).await,
+        (&Method::GET, Some(Resource::User(username))) => get_user(client, &username).await,
+        (&Method::PATCH, Some(Resource::User(username))) => {
+            update_user(client, &username, &request).await
+        }
+        (&Method::DELETE, Some(Resource::User(username))) => delete_user(client, &username).await,
+        (&Method::GET, Some(Resource::Hosts(username))) => get_hosts(client, &username).await,
+        (&Method::GET, Some(Resource::Tokens(username))) => get_tokens(client, &username).await,
+        (&Method::POST, Some(Resource::Tokens(username))) => {
+            create_token(client, &username, &request).await
+        }
+        (&Method::DELETE, Some(Resource::Token(username, id))) => {
+            client.revoke_token(&username, &id).await.map(|_| {
+                Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .body(Body::from(()))
+                    .expect("unable to create response")
+            })
+        }
+        _ => Err(ResponseError::NotFound(format!(
+            "{} {}",
+            request.method(),
+            request.uri().path()
+        ))),
+    };
+    match result {
+        Ok(resp) => resp,
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn get_user(client: &Client, username: &str) -> Result<Response<Body>, ResponseError> {
+    let user = client.get_user(username).await?;
+    Ok(json_response(StatusCode::OK, &UserInfo::from(&user)))
+}
+
+async fn get_hosts(client: &Client, username: &str) -> Result<Response<Body>, ResponseError> {
+    let user = client.get_user(username).await?;
+    Ok(json_response(StatusCode::OK, user.hosts()))
+}
+
+async fn get_tokens(client: &Client, username: &str) -> Result<Response<Body>, ResponseError> {
+    let user = client.get_user(username).await?;
+    Ok(json_response(StatusCode::OK, &user.token_infos()))
+}
+
+async fn create_token(
+    client: &Client,
+    username: &str,
+    request: &Request,
+) -> Result<Response<Body>, ResponseError> {
+    let hostnames = parse_token_request(request)?;
+    let token = client.create_token(username, hostnames).await?;
+    Ok(json_response(StatusCode::CREATED, &token))
+}
+
+async fn delete_user(client: &Client, username: &str) -> Result<Response<Body>, ResponseError> {
+    client.delete_user(username).await?;
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::from(()))
+        .expect("unable to create response"))
+}
+
+#[derive(Serialize)]
+struct UserList {
+    users: Vec<UserInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
+}
+
+async fn list_users(client: &Client, request: &Request) -> Result<Response<Body>, ResponseError> {
+    let queries = request.query_string_parameters();
+    let limit = match queries.get("limit") {
+        Some(raw) => match raw.parse::<usize>() {
+            Ok(limit) if (1..=MAX_PAGE_SIZE).contains(&limit) => limit,
+            _ => {
+                return Err(ResponseError::InvalidQuery(
+                    "limit".into(),
+                    format!("must be between 1 and {}", MAX_PAGE_SIZE),
+                ))
+            }
+        },
+        None => DEFAULT_PAGE_SIZE,
+    };
+    let cursor = queries.get("cursor").map(|c| c.to_owned());
+    let page = client.list_users(limit, cursor).await?;
+    Ok(json_response(
+        StatusCode::OK,
+        &UserList {
+            users: page.users.iter().map(UserInfo::from).collect(),
+            cursor: page.cursor,
+        },
+    ))
+}
+
+// TTLs are only set when a user is created. Unknown fields, `ttl` and `ttls`
+// among them, are refused rather than silently ignored.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct UpdateUserRequest {
+    #[serde(default)]
+    add_domains: HashSet<String>,
+    #[serde(default)]
+    remove_domains: HashSet<String>,
+    #[serde(default)]
+    password: Option<String>,
+    // Replaces the user's allowed agents; an empty list allows any agent.
+    #[serde(default)]
+    user_agents: Option<HashSet<String>>,
+    #[serde(default)]
+    allow_private_addresses: Option<bool>,
+}
+
+async fn update_user(
+    client: &Client,
+    username: &str,
+    request: &Request,
+) -> Result<Response<Body>, ResponseError> {
+    let req = parse_update(request)?;
+    let added: Vec<String> = req.add_domains.iter().cloned().collect();
+    if !added.is_empty() {
+        client.check_hostnames(&added).await?;
+    }
+
+    // Only what was asked for is written, so the user's hosts and tokens are
+    // left as they are. Removed domains are checked by the store as it
+    // writes, so two requests cannot each remove the last domain.
+    let mut update = UserUpdate {
+        add_domains: req.add_domains,
+        remove_domains: req.remove_domains,
+        user_agents: req.user_agents,
+        allow_private_addresses: req.allow_private_addresses,
+        ..UserUpdate::default()
+    };
+    if let Some(password) = &req.password {
+        update.set_password(password, client.hasher()).await?;
+    }
+    let user = client.update_user(username, update).await?;
+    Ok(json_response(StatusCode::OK, &UserInfo::from(&user)))
+}
+
+fn parse_update(request: &Request) -> Result<UpdateUserRequest, ResponseErrors> {
+    let mut errs = ResponseErrors::default();
+    let req = match request.payload::<UpdateUserRequest>() {
+        Ok(Some(r)) => r,
+        Ok(None) => UpdateUserRequest::default(),
+        Err(e) => {
+            errs.add(ResponseError::ParseError(format!("{}", e)));
+            return Err(errs);
+        }
+    };
+    if req.add_domains.is_empty()
+        && req.remove_domains.is_empty()
+        && req.password.is_none()
+        && req.user_agents.is_none()
+        && req.allow_private_addresses.is_none()
+    {
+        errs.add(ResponseError::MissingField(
+            "add_domains, remove_domains, password, user_agents or allow_private_addresses".into(),
+        ));
+    }
+    if let Some(agents) = &req.user_agents {
+        if agents.iter().any(|a| a.trim().is_empty()) {
+            errs.add(ResponseError::InvalidField(
+                "user_agents".into(),
+                "contains an empty entry".into(),
+            ));
+        }
+    }
+    for domain in &req.add_domains {
+        if let Err(reason) = check_grant(domain) {
+            errs.add(ResponseError::InvalidField(
+                "add_domains".into(),
+                format!("{} {}", domain, reason),
+            ));
+        }
+    }
+    for domain in req.add_domains.intersection(&req.remove_domains) {
+        errs.add(ResponseError::InvalidField(
+            "remove_domains".into(),
+            format!("{} is also in add_domains", domain),
+        ));
+    }
+    if let Some(password) = &req.password {
+        if let Err(reason) = check_password(password) {
+            errs.add(ResponseError::InvalidField("password".into(), reason));
+        }
+    }
+    errs.into_result(req)
+}
+
+fn parse_token_request(request: &Request) -> Result<HashSet<String>, ResponseError> {
+    match request.payload::<TokenRequest>() {
+        Ok(Some(r)) if !r.hostnames.is_empty() => Ok(r.hostnames),
+        Ok(_) => Err(ResponseError::MissingField("hostnames".into())),
+        Err(e) => Err(ResponseError::ParseError(format!("{}", e))),
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenRequest {
+    #[serde(default)]
+    hostnames: HashSet<String>,
+}
+
+fn json_response(status: StatusCode, body: &impl Serialize) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            serde_json::to_string(body).expect("unable to turn body into json"),
+        ))
+        .expect("unable to create response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ddns_core::{
+        dns::MemoryDnsProvider,
+        lockout::MemoryAttemptStore,
+        password::{test_hasher, TEST_PARAMS},
+        store::{MemoryUserStore, PutMode, UserStore},
+        user::User,
+        zone::Zone,
+    };
+    use std::collections::HashMap;
+
+    // A client with one user, alice, who may update two hosts.
+    async fn client() -> Client {
+        let users = MemoryUserStore::new();
+        let mut domains = HashSet::new();
+        domains.insert("host.example.com".to_owned());
+        domains.insert("other.example.com".to_owned());
+        let user = User::new("alice", "secret", domains, &test_hasher()).unwrap();
+        users.put_user(user, PutMode::Create).await.unwrap();
+        Client::builder()
+            .user_store(users)
+            .dns_provider(MemoryDnsProvider::new(vec![Zone::new("example.com", "Z1")]))
+            .attempt_store(MemoryAttemptStore::new())
+            .hash_params(TEST_PARAMS)
+            .build()
+            .unwrap()
+    }
+
+    fn request(method: Method, path: &[(&str, &str)], body: &str) -> Request {
+        let path: HashMap<String, String> = path
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect();
+        let body = if body.is_empty() {
+            Body::Empty
+        } else {
+            Body::Text(body.to_owned())
+        };
+        http::Request::builder()
+            .method(method)
+            .uri("/user")
+            .header("Content-Type", "application/json")
+            .body(body)
+            .unwrap()
+            .with_path_parameters(path)
+    }
+
+    async fn send(client: &Client, request: Request) -> (StatusCode, serde_json::Value) {
+        let response = user_admin(client, request).await;
+        let body = response.body().as_ref();
+        let body = if body.is_empty() {
+            serde_json::Value::Null
+        } else {
+            serde_json::from_slice(body).unwrap()
+        };
+        (response.status(), body)
+    }
+
+    fn domains(body: &serde_json::Value) -> Vec<&str> {
+        let mut domains: Vec<&str> = body["domains"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d.as_str().unwrap())
+            .collect();
+        domains.sort_unstable();
+        domains
+    }
+
+    #[tokio::test]
+    async fn get_user_shows_the_user() {
+        let client = client().await;
+        let (status, body) =
+            send(&client, request(Method::GET, &[("username", "alice")], "")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["username"], "alice");
+        assert_eq!(
+            domains(&body),
+            vec!["host.example.com", "other.example.com"]
+        );
+        assert!(body.get("password").is_none());
+
+        let req = request(Method::GET, &[("username", "mallory")], "");
+        assert_eq!(send(&client, req).await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn list_users_pages_through_users() {
+        let client = client().await;
+        let (status, body) = send(&client, request(Method::GET, &[], "")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["users"].as_array().unwrap().len(), 1);
+        assert_eq!(body["users"][0]["username"], "alice");
+        assert!(body.get("cursor").is_none());
+    }
+
+    #[tokio::test]
+    async fn patch_adds_and_removes_domains() {
+        let client = client().await;
+        let req = request(
+            Method::PATCH,
+            &[("username", "alice")],
+            r#"{"add_domains": ["new.example.com"], "remove_domains": ["other.example.com"]}"#,
+        );
+        let (status, body) = send(&client, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(domains(&body), vec!["host.example.com", "new.example.com"]);
+    }
+
+    #[tokio::test]
+    async fn patch_cannot_remove_every_domain() {
+        let client = client().await;
+        let req = request(
+            Method::PATCH,
+            &[("username", "alice")],
+            r#"{"remove_domains": ["host.example.com", "other.example.com"]}"#,
+        );
+        assert_eq!(send(&client, req).await.0, StatusCode::BAD_REQUEST);
+
+        // Removing them one at a time is refused at the last one too.
+        let req = request(
+            Method::PATCH,
+            &[("username", "alice")],
+            r#"{"remove_domains": ["other.example.com"]}"#,
+        );
+        assert_eq!(send(&client, req).await.0, StatusCode::OK);
+        let req = request(
+            Method::PATCH,
+            &[("username", "alice")],
+            r#"{"remove_domains": ["host.example.com"]}"#,
+        );
+        assert_eq!(send(&client, req).await.0, StatusCode::BAD_REQUEST);
+        let user = client.get_user("alice").await.unwrap();
+        assert!(user.domains().contains("host.example.com"));
+    }
+
+    #[tokio::test]
+    async fn patch_refuses_ttls() {
+        let client = client().await;
+        for body in &[r#"{"ttl": 600}"#, r#"{"ttls": {"host.example.com": 600}}"#] {
+            let req = request(Method::PATCH, &[("username", "alice")], body);
+            let (status, body) = send(&client, req).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(body.to_string().contains("unknown field"), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn patch_refuses_domains_the_user_does_not_have() {
+        let client = client().await;
+        let req = request(
+            Method::PATCH,
+            &[("username", "alice")],
+            r#"{"remove_domains": ["nothere.example.com"]}"#,
+        );
+        assert_eq!(send(&client, req).await.0, StatusCode::BAD_REQUEST);
+        let req = request(
+            Method::PATCH,
+            &[("username", "mallory")],
+            r#"{"allow_private_addresses": true}"#,
+        );
+        assert_eq!(send(&client, req).await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn delete_removes_the_user() {
+        let client = client().await;
+        let req = request(Method::DELETE, &[("username", "alice")], "");
+        assert_eq!(send(&client, req).await.0, StatusCode::NO_CONTENT);
+        let req = request(Method::GET, &[("username", "alice")], "");
+        assert_eq!(send(&client, req).await.0, StatusCode::NOT_FOUND);
+        let req = request(Method::DELETE, &[("username", "alice")], "");
+        assert_eq!(send(&client, req).await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn collections_are_routed_by_path_parameter() {
+        let client = client().await;
+        let path = [("username", "alice"), ("collection", "hosts")];
+        let (status, body) = send(&client, request(Method::GET, &path, "")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!({}));
+
+        let path = [("username", "alice"), ("collection", "tokens")];
+        let (status, body) = send(&client, request(Method::GET, &path, "")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!([]));
+
+        let path = [("username", "alice"), ("collection", "hostess")];
+        let (status, _) = send(&client, request(Method::GET, &path, "")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}